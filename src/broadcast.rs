@@ -0,0 +1,56 @@
+// Cron schedule for pushing the broadcast contact to every recipient
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use cron::Schedule;
+
+use crate::error::BotError;
+
+// Accepts the usual five fields (minute hour day-of-month month day-of-week), or six and seven
+// with leading seconds and trailing year. Times are UTC.
+pub fn parse_schedule(expression: &str) -> Result<Schedule, BotError> {
+    let expression = expression.trim();
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_string()
+    };
+    Schedule::from_str(&expression)
+        .map_err(|e| BotError::Config(format!("BROADCAST_SCHEDULE '{}' is invalid: {}", expression, e)))
+}
+
+// When the schedule next fires, from now; None once it never fires again
+pub fn next_fire(schedule: &Schedule) -> Option<(chrono::DateTime<Utc>, Duration)> {
+    let next = schedule.upcoming(Utc).next()?;
+    let wait = (next - Utc::now()).to_std().unwrap_or_default();
+    Some((next, wait))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn five_fields_fire_on_the_minute() {
+        let schedule = parse_schedule("30 9 * * Mon-Fri").unwrap();
+        let next = schedule.upcoming(Utc).next().unwrap();
+        assert_eq!(next.format("%H:%M:%S").to_string(), "09:30:00");
+    }
+
+    #[test]
+    fn six_fields_include_seconds() {
+        let (_, wait) = next_fire(&parse_schedule("* * * * * *").unwrap()).unwrap();
+        assert!(wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn an_invalid_schedule_is_a_config_error() {
+        assert!(matches!(parse_schedule("every morning"), Err(BotError::Config(message)) if message.contains("BROADCAST_SCHEDULE")));
+    }
+
+    #[test]
+    fn a_schedule_in_the_past_never_fires() {
+        assert!(next_fire(&parse_schedule("0 0 0 1 1 * 2001").unwrap()).is_none());
+    }
+}
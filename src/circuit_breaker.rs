@@ -0,0 +1,242 @@
+// Circuit breaker that stops hammering Infobip while it is failing
+use std::sync::Mutex;
+
+use infobip_sdk::model::whatsapp::{
+    SendContactRequestBody, SendDocumentRequestBody, SendImageRequestBody, SendLocationRequestBody, SendTemplateRequestBody,
+    SendTextRequestBody,
+};
+use log::{info, warn};
+use prometheus::IntGauge;
+use tokio::time::{Duration, Instant};
+
+use crate::error::BotError;
+use crate::sender::MessageSender;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerState {
+    // Sends go through; counts consecutive transient failures
+    Closed { failures: u32 },
+    // Sends fail fast until the cool-down is over
+    Open { until: Instant },
+    // One probe send is let through to see whether the API has recovered
+    HalfOpen { probe_started: Instant },
+}
+
+impl BreakerState {
+    // Value reported on the circuit_breaker_state gauge
+    fn gauge_value(self) -> i64 {
+        match self {
+            BreakerState::Closed { .. } => 0,
+            BreakerState::Open { .. } => 1,
+            BreakerState::HalfOpen { .. } => 2,
+        }
+    }
+}
+
+// Wraps a sender; after `failure_threshold` consecutive transient failures the breaker opens
+// for `cooldown`, then half-opens and closes again once a probe send succeeds.
+pub struct CircuitBreaker<S> {
+    inner: S,
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+    state_gauge: IntGauge,
+}
+
+impl<S: MessageSender> CircuitBreaker<S> {
+    pub fn new(inner: S, failure_threshold: u32, cooldown: Duration, state_gauge: IntGauge) -> Self {
+        let state = BreakerState::Closed { failures: 0 };
+        state_gauge.set(state.gauge_value());
+        CircuitBreaker {
+            inner,
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(state),
+            state_gauge,
+        }
+    }
+
+    fn set_state(&self, state: &mut BreakerState, next: BreakerState) {
+        *state = next;
+        self.state_gauge.set(next.gauge_value());
+    }
+
+    // Decide whether a send may go out now
+    fn before_send(&self) -> Result<(), BotError> {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until } if now >= until => {
+                info!("Circuit breaker half-open, probing the Infobip API");
+                self.set_state(&mut state, BreakerState::HalfOpen { probe_started: now });
+                Ok(())
+            }
+            // A probe that never reported back (e.g. cancelled) shouldn't wedge the breaker
+            BreakerState::HalfOpen { probe_started } if now >= probe_started + self.cooldown => {
+                self.set_state(&mut state, BreakerState::HalfOpen { probe_started: now });
+                Ok(())
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => Err(BotError::CircuitOpen),
+        }
+    }
+
+    // Only transient errors count against the API; a rejected request means it is reachable.
+    // A key refused even after reading it again fails every send alike, so that counts too.
+    fn after_send(&self, result: &Result<(), BotError>) {
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+        let failed = matches!(result, Err(e) if e.is_transient() || matches!(e, BotError::AuthFailed(_)));
+        match (*state, failed) {
+            (BreakerState::Closed { failures }, true) => {
+                let failures = failures + 1;
+                if failures >= self.failure_threshold {
+                    warn!(
+                        "Circuit breaker open after {} consecutive failures, pausing sends for {:?}",
+                        failures, self.cooldown
+                    );
+                    self.set_state(&mut state, BreakerState::Open { until: Instant::now() + self.cooldown });
+                } else {
+                    self.set_state(&mut state, BreakerState::Closed { failures });
+                }
+            }
+            (BreakerState::HalfOpen { .. }, true) => {
+                warn!("Circuit breaker probe failed, pausing sends for another {:?}", self.cooldown);
+                self.set_state(&mut state, BreakerState::Open { until: Instant::now() + self.cooldown });
+            }
+            (BreakerState::HalfOpen { .. }, false) => {
+                info!("Circuit breaker closed, the Infobip API has recovered");
+                self.set_state(&mut state, BreakerState::Closed { failures: 0 });
+            }
+            (BreakerState::Closed { .. }, false) => {
+                self.set_state(&mut state, BreakerState::Closed { failures: 0 });
+            }
+            // A send that started before the breaker opened; the open state stands
+            (BreakerState::Open { .. }, _) => {}
+        }
+    }
+}
+
+impl<S: MessageSender> MessageSender for CircuitBreaker<S> {
+    async fn send_text(&self, request_body: SendTextRequestBody) -> Result<(), BotError> {
+        self.before_send()?;
+        let result = self.inner.send_text(request_body).await;
+        self.after_send(&result);
+        result
+    }
+
+    async fn send_contact(&self, request_body: SendContactRequestBody) -> Result<(), BotError> {
+        self.before_send()?;
+        let result = self.inner.send_contact(request_body).await;
+        self.after_send(&result);
+        result
+    }
+
+    async fn send_template(&self, request_body: SendTemplateRequestBody) -> Result<(), BotError> {
+        self.before_send()?;
+        let result = self.inner.send_template(request_body).await;
+        self.after_send(&result);
+        result
+    }
+
+    async fn send_image(&self, request_body: SendImageRequestBody) -> Result<(), BotError> {
+        self.before_send()?;
+        let result = self.inner.send_image(request_body).await;
+        self.after_send(&result);
+        result
+    }
+
+    async fn send_document(&self, request_body: SendDocumentRequestBody) -> Result<(), BotError> {
+        self.before_send()?;
+        let result = self.inner.send_document(request_body).await;
+        self.after_send(&result);
+        result
+    }
+
+    async fn send_location(&self, request_body: SendLocationRequestBody) -> Result<(), BotError> {
+        self.before_send()?;
+        let result = self.inner.send_location(request_body).await;
+        self.after_send(&result);
+        result
+    }
+
+    // Not a send, so it neither waits on the breaker nor counts towards tripping it
+    async fn check_sender(&self, sender: &str) -> Result<(), BotError> {
+        self.inner.check_sender(sender).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sender::tests::{MockSender, text_body};
+
+    const COOLDOWN: Duration = Duration::from_millis(50);
+
+    fn breaker() -> CircuitBreaker<MockSender> {
+        CircuitBreaker::new(MockSender::new(), 2, COOLDOWN, IntGauge::new("test_breaker_state", "test").unwrap())
+    }
+
+    fn timeout() -> Result<(), BotError> {
+        Err(BotError::Timeout(Duration::from_secs(1)))
+    }
+
+    async fn send(breaker: &CircuitBreaker<MockSender>) -> Result<(), BotError> {
+        breaker.send_text(text_body("+15551234567", "hi")).await
+    }
+
+    #[tokio::test]
+    async fn closed_open_half_open_closed() {
+        let breaker = breaker();
+        breaker.inner.then(timeout()).then(timeout());
+        assert!(matches!(send(&breaker).await, Err(BotError::Timeout(_))));
+        assert_eq!(breaker.state_gauge.get(), 0);
+        assert!(matches!(send(&breaker).await, Err(BotError::Timeout(_))));
+        assert_eq!(breaker.state_gauge.get(), 1);
+
+        // Open: fails fast without calling Infobip
+        assert!(matches!(send(&breaker).await, Err(BotError::CircuitOpen)));
+        assert_eq!(breaker.inner.sent().len(), 2);
+
+        // After the cool-down one probe goes through, and its success closes the breaker
+        tokio::time::sleep(COOLDOWN).await;
+        assert!(send(&breaker).await.is_ok());
+        assert_eq!(breaker.state_gauge.get(), 0);
+        assert!(send(&breaker).await.is_ok());
+        assert_eq!(breaker.inner.sent().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn failed_probe_opens_again() {
+        let breaker = breaker();
+        breaker.inner.then(timeout()).then(timeout()).then(timeout());
+        let _ = send(&breaker).await;
+        let _ = send(&breaker).await;
+        tokio::time::sleep(COOLDOWN).await;
+        assert!(matches!(send(&breaker).await, Err(BotError::Timeout(_))));
+        assert_eq!(breaker.state_gauge.get(), 1);
+        assert!(matches!(send(&breaker).await, Err(BotError::CircuitOpen)));
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_do_not_trip_it() {
+        let breaker = breaker();
+        let rejected = || Err(BotError::Send("rejected".to_string()));
+        breaker.inner.then(rejected()).then(rejected()).then(rejected());
+        for _ in 0..3 {
+            assert!(matches!(send(&breaker).await, Err(BotError::Send(_))));
+        }
+        assert_eq!(breaker.state_gauge.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn auth_failures_open_the_breaker() {
+        let breaker = breaker();
+        let refused = || Err(BotError::AuthFailed(crate::error::tests::infobip_error(401, "UNAUTHORIZED")));
+        breaker.inner.then(refused()).then(refused());
+        assert!(matches!(send(&breaker).await, Err(BotError::AuthFailed(_))));
+        assert!(matches!(send(&breaker).await, Err(BotError::AuthFailed(_))));
+        assert_eq!(breaker.state_gauge.get(), 1);
+        assert!(matches!(send(&breaker).await, Err(BotError::CircuitOpen)));
+    }
+}
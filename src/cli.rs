@@ -0,0 +1,44 @@
+// Command-line flags; each one overrides the environment variable of the same setting
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+#[derive(Debug, Clone, Parser)]
+#[command(version, about = "WhatsApp bot that turns trigger messages into contact cards")]
+pub struct Cli {
+    #[arg(long, help = "Comma-separated trigger words (TRIGGER_WORDS)")]
+    pub trigger_word: Option<String>,
+
+    #[arg(long, help = "Port to listen on (PORT)")]
+    pub port: Option<u16>,
+
+    #[arg(long, help = "Comma-separated recipient numbers (RECIPIENT_PHONE_NUMBER)")]
+    pub recipient: Option<String>,
+
+    #[arg(long, help = "Infobip API base URL (INFOBIP_BASE_URL)")]
+    pub base_url: Option<String>,
+
+    #[arg(long, help = "TOML file with settings that environment variables may override (CONFIG_FILE)")]
+    pub config_file: Option<PathBuf>,
+}
+
+impl Cli {
+    // Flags that were passed, keyed by the environment variable they override
+    pub fn overrides(&self) -> HashMap<&'static str, String> {
+        let mut overrides = HashMap::new();
+        if let Some(words) = &self.trigger_word {
+            overrides.insert("TRIGGER_WORDS", words.clone());
+        }
+        if let Some(port) = self.port {
+            overrides.insert("PORT", port.to_string());
+        }
+        if let Some(recipient) = &self.recipient {
+            overrides.insert("RECIPIENT_PHONE_NUMBER", recipient.clone());
+        }
+        if let Some(base_url) = &self.base_url {
+            overrides.insert("INFOBIP_BASE_URL", base_url.clone());
+        }
+        overrides
+    }
+}
@@ -0,0 +1,294 @@
+// Parsing of "addcontact Jane Smith +15551234567 jane@x.com" style commands
+use std::fmt;
+
+use crate::{PhoneKind, PhoneNumber, VCard};
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    MissingName,
+    MissingPhone,
+    InvalidPhone(String),
+    InvalidEmail(String),
+    UnknownAlias(String),
+    InvalidAlias(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::MissingName => write!(f, "a contact name is required"),
+            ParseError::MissingPhone => write!(f, "a phone number is required"),
+            ParseError::InvalidPhone(number) => write!(
+                f,
+                "'{}' is not a valid phone number, use international format with the country code, e.g. +15551234567",
+                number
+            ),
+            ParseError::InvalidEmail(email) => write!(f, "'{}' is not a valid email address", email),
+            ParseError::UnknownAlias(alias) => write!(f, "there is no contact called '{}' in the directory", alias),
+            ParseError::InvalidAlias(alias) => {
+                write!(f, "'{}' can't be used as an alias, use a single word without digits", alias)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Pull a name, phone numbers and optional email out of a command whose trigger word was already
+// stripped, keeping the casing the sender used. Words are classified individually, so the
+// numbers and email may appear in any order after the name; the first name word is the first
+// name and any others make up the last name.
+// A number may be labelled "work:", "home:" or "cell:"/"mobile:"; unlabelled numbers are cell.
+// National numbers get `default_country_code`, see normalize_phone.
+pub fn parse_contact_command(command: &str, default_country_code: Option<&str>) -> Result<VCard, ParseError> {
+    let mut name_parts = Vec::new();
+    let mut phone_numbers = Vec::new();
+    let mut email = None;
+    // Numbers are often typed in groups ("+1 555 123 4567"), so adjacent digit groups are joined
+    let mut digit_groups = String::new();
+    let mut kind = PhoneKind::Cell;
+
+    for word in command.split_whitespace() {
+        let labelled = word
+            .split_once(':')
+            .and_then(|(label, rest)| Some((phone_kind(label)?, rest)));
+        let word = match labelled {
+            Some((label_kind, rest)) => {
+                push_phone(&mut phone_numbers, &mut digit_groups, kind, default_country_code)?;
+                kind = label_kind;
+                if rest.is_empty() {
+                    continue;
+                }
+                rest
+            }
+            None => word,
+        };
+        if is_digit_group(word) {
+            digit_groups.push_str(word);
+            continue;
+        }
+        push_phone(&mut phone_numbers, &mut digit_groups, kind, default_country_code)?;
+        kind = PhoneKind::Cell;
+        if word.contains('@') {
+            if !is_valid_email(word) {
+                return Err(ParseError::InvalidEmail(word.to_string()));
+            }
+            email = Some(word.to_string());
+        } else {
+            name_parts.push(word);
+        }
+    }
+    push_phone(&mut phone_numbers, &mut digit_groups, kind, default_country_code)?;
+
+    let (first_name, last_name) = match name_parts.split_first() {
+        Some((first, rest)) => (first.to_string(), rest.join(" ")),
+        None => return Err(ParseError::MissingName),
+    };
+
+    let mut contact = VCard::with_phone_numbers(first_name, last_name, phone_numbers)?;
+    contact.email = email;
+    Ok(contact)
+}
+
+// Turn the digit groups collected so far into a validated number, if they form one
+fn push_phone(
+    phone_numbers: &mut Vec<PhoneNumber>,
+    digit_groups: &mut String,
+    kind: PhoneKind,
+    default_country_code: Option<&str>,
+) -> Result<(), ParseError> {
+    if let Some(number) = as_phone_number(&std::mem::take(digit_groups)) {
+        phone_numbers.push(PhoneNumber {
+            number: normalize_phone(&number, default_country_code)?,
+            kind,
+        });
+    }
+    Ok(())
+}
+
+fn phone_kind(label: &str) -> Option<PhoneKind> {
+    match label.to_lowercase().as_str() {
+        "cell" | "mobile" => Some(PhoneKind::Cell),
+        "work" => Some(PhoneKind::Work),
+        "home" => Some(PhoneKind::Home),
+        _ => None,
+    }
+}
+
+// Short usage hint sent back when a command can't be parsed
+pub fn usage(trigger: &str) -> String {
+    format!(
+        "Usage: {} <first name> [last name] <phone number> [work:<number>] [home:<number>] [email]\nExample: {} Jane Smith +15551234567 jane@example.com",
+        trigger, trigger
+    )
+}
+
+// Digits, '+' and common separators only, e.g. "+1", "555-123" or "(020)"
+fn is_digit_group(word: &str) -> bool {
+    word.chars().any(|c| c.is_ascii_digit())
+        && word
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '(' | ')' | '.'))
+}
+
+// Joined digit groups count as a phone number if they are digits (with an optional leading '+')
+// once common separators are removed
+fn as_phone_number(groups: &str) -> Option<String> {
+    let cleaned: String = groups
+        .chars()
+        .filter(|c| !matches!(c, '-' | '(' | ')' | '.'))
+        .collect();
+    let digits = cleaned.strip_prefix('+').unwrap_or(&cleaned);
+    if digits.len() >= 5 && digits.chars().all(|c| c.is_ascii_digit()) {
+        Some(cleaned)
+    } else {
+        None
+    }
+}
+
+// Normalize a number to E.164 ("+" then 7 to 15 digits, no leading zero in the country code).
+// Spaces and common separators are dropped and a "00" international prefix becomes "+".
+pub fn validate_e164(number: &str) -> Result<String, ParseError> {
+    let cleaned: String = number
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '(' | ')' | '.'))
+        .collect();
+    let digits = cleaned
+        .strip_prefix('+')
+        .or_else(|| cleaned.strip_prefix("00"))
+        .ok_or_else(|| ParseError::InvalidPhone(number.trim().to_string()))?;
+    let valid = (7..=15).contains(&digits.len())
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && !digits.starts_with('0');
+    if valid {
+        Ok(format!("+{}", digits))
+    } else {
+        Err(ParseError::InvalidPhone(number.trim().to_string()))
+    }
+}
+
+// validate_e164, except that with a default country code a national number, one dialled with a
+// single leading 0, has the 0 replaced by the code: "07700 900123" with 44 is +447700900123.
+// Without the 0 there's no telling whether the code is already there, so those still fail.
+pub fn normalize_phone(number: &str, default_country_code: Option<&str>) -> Result<String, ParseError> {
+    let cleaned: String = number
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '(' | ')' | '.'))
+        .collect();
+    if let Some(code) = default_country_code
+        && let Some(national) = cleaned.strip_prefix('0')
+        && !national.starts_with('0')
+    {
+        return validate_e164(&format!("+{}{}", code, national))
+            .map_err(|_| ParseError::InvalidPhone(number.trim().to_string()));
+    }
+    validate_e164(number)
+}
+
+pub fn is_valid_email(word: &str) -> bool {
+    match word.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(contact: &VCard) -> Vec<&str> {
+        contact.phone_numbers.iter().map(|phone| phone.number.as_str()).collect()
+    }
+
+    #[test]
+    fn name_phone_and_email() {
+        let contact = parse_contact_command("Jane Smith +15551234567 jane@example.com", None).unwrap();
+        assert_eq!(contact.first_name, "Jane");
+        assert_eq!(contact.last_name, "Smith");
+        assert_eq!(numbers(&contact), ["+15551234567"]);
+        assert_eq!(contact.email.as_deref(), Some("jane@example.com"));
+    }
+
+    #[test]
+    fn extra_whitespace_is_ignored() {
+        let contact = parse_contact_command("   Jane \t  Smith   +15551234567  ", None).unwrap();
+        assert_eq!(contact.first_name, "Jane");
+        assert_eq!(contact.last_name, "Smith");
+        assert_eq!(numbers(&contact), ["+15551234567"]);
+    }
+
+    #[test]
+    fn email_is_optional() {
+        let contact = parse_contact_command("Jane +15551234567", None).unwrap();
+        assert_eq!(contact.email, None);
+        assert_eq!(contact.last_name, "");
+    }
+
+    #[test]
+    fn grouped_number_and_any_order() {
+        let contact = parse_contact_command("jane@example.com +1 555-123-4567 Jane Mary Smith", None).unwrap();
+        assert_eq!(numbers(&contact), ["+15551234567"]);
+        assert_eq!(contact.first_name, "Jane");
+        assert_eq!(contact.last_name, "Mary Smith");
+        assert_eq!(contact.email.as_deref(), Some("jane@example.com"));
+    }
+
+    #[test]
+    fn missing_parts_are_reported() {
+        assert_eq!(parse_contact_command("+15551234567", None).unwrap_err(), ParseError::MissingName);
+        assert_eq!(parse_contact_command("Jane Smith", None).unwrap_err(), ParseError::MissingPhone);
+        assert_eq!(parse_contact_command("", None).unwrap_err(), ParseError::MissingName);
+        assert_eq!(
+            parse_contact_command("Jane +15551234567 jane@", None).unwrap_err(),
+            ParseError::InvalidEmail("jane@".to_string())
+        );
+    }
+
+    #[test]
+    fn valid_e164_numbers_are_normalized() {
+        assert_eq!(validate_e164("+15551234567").unwrap(), "+15551234567");
+        assert_eq!(validate_e164("+44 20 7946 0958").unwrap(), "+442079460958");
+        assert_eq!(validate_e164("+1 (555) 123-4567").unwrap(), "+15551234567");
+        assert_eq!(validate_e164("0033 1 23 45 67 89").unwrap(), "+33123456789");
+        assert_eq!(validate_e164("+1234567").unwrap(), "+1234567");
+    }
+
+    #[test]
+    fn invalid_e164_numbers_are_refused() {
+        for number in ["5551234567", "+0551234567", "+155512", "+1234567890123456", "+1555abc4567", "+", ""] {
+            assert!(validate_e164(number).is_err(), "{:?} was accepted", number);
+        }
+        assert_eq!(validate_e164(" 5551234567 ").unwrap_err(), ParseError::InvalidPhone("5551234567".to_string()));
+    }
+
+    #[test]
+    fn a_national_number_gets_the_default_country_code() {
+        assert_eq!(normalize_phone("07700900123", Some("44")).unwrap(), "+447700900123");
+        assert_eq!(normalize_phone("07700 900-123", Some("44")).unwrap(), "+447700900123");
+        let contact = parse_contact_command("Jane Smith 07700 900123", Some("44")).unwrap();
+        assert_eq!(numbers(&contact), ["+447700900123"]);
+    }
+
+    #[test]
+    fn an_international_number_is_left_alone() {
+        assert_eq!(normalize_phone("+15551234567", Some("44")).unwrap(), "+15551234567");
+        assert_eq!(normalize_phone("+44 7700 900123", Some("1")).unwrap(), "+447700900123");
+        // 00 is the international prefix, not a national number
+        assert_eq!(normalize_phone("00447700900123", Some("1")).unwrap(), "+447700900123");
+    }
+
+    #[test]
+    fn numbers_the_default_code_cannot_fix_are_refused() {
+        // No leading 0, so there's no telling whether a code is already there
+        assert!(matches!(normalize_phone("7700900123", Some("44")), Err(ParseError::InvalidPhone(_))));
+        assert!(matches!(normalize_phone("0abc", Some("44")), Err(ParseError::InvalidPhone(number)) if number == "0abc"));
+        // Without a default code national numbers fail as before
+        assert!(matches!(normalize_phone("07700900123", None), Err(ParseError::InvalidPhone(_))));
+    }
+}
@@ -0,0 +1,121 @@
+// Contacts waiting for the sender to confirm them with a YES reply
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::VCard;
+
+// What a reply did to the sender's pending contact
+#[derive(Debug)]
+pub enum Resolution {
+    Confirmed(VCard),
+    Declined(VCard),
+    // The reply came after the TTL; the contact is gone
+    Expired(VCard),
+}
+
+struct Pending {
+    contact: VCard,
+    expires_at: Instant,
+    // messageId the confirmation prompt went out under
+    prompt_id: String,
+}
+
+pub struct ConfirmationStore {
+    ttl: Duration,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+fn is_yes(reply: &str) -> bool {
+    matches!(reply.trim().to_lowercase().as_str(), "yes" | "y")
+}
+
+fn is_no(reply: &str) -> bool {
+    matches!(reply.trim().to_lowercase().as_str(), "no" | "n")
+}
+
+impl ConfirmationStore {
+    pub fn new(ttl: Duration) -> Self {
+        ConfirmationStore {
+            ttl,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Hold a contact until `from` confirms it, replacing anything they had pending
+    pub fn request(&self, from: &str, contact: VCard, prompt_id: &str) {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().expect("confirmation lock poisoned");
+        pending.retain(|_, entry| entry.expires_at > now);
+        pending.insert(
+            from.to_string(),
+            Pending {
+                contact,
+                expires_at: now + self.ttl,
+                prompt_id: prompt_id.to_string(),
+            },
+        );
+    }
+
+    // Apply a reply from `from`. Returns None, leaving any pending contact alone, when they
+    // have nothing pending, the reply is neither yes nor no, or it quotes some other message
+    // than the prompt. An unquoted reply goes to whatever they have pending.
+    pub fn resolve(&self, from: &str, reply: &str, quoted_message_id: Option<&str>) -> Option<Resolution> {
+        if !is_yes(reply) && !is_no(reply) {
+            return None;
+        }
+        let mut pending = self.pending.lock().expect("confirmation lock poisoned");
+        if let Some(quoted) = quoted_message_id
+            && pending.get(from)?.prompt_id != quoted
+        {
+            return None;
+        }
+        let entry = pending.remove(from)?;
+        Some(if entry.expires_at <= Instant::now() {
+            Resolution::Expired(entry.contact)
+        } else if is_yes(reply) {
+            Resolution::Confirmed(entry.contact)
+        } else {
+            Resolution::Declined(entry.contact)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::jane;
+
+    const FROM: &str = "15557654321";
+
+    #[test]
+    fn yes_confirms_and_no_declines() {
+        let store = ConfirmationStore::new(Duration::from_secs(60));
+        store.request(FROM, jane(), "prompt-1");
+        assert!(matches!(store.resolve(FROM, " Yes ", None), Some(Resolution::Confirmed(contact)) if contact.first_name == "Jane"));
+        assert!(store.resolve(FROM, "yes", None).is_none());
+
+        store.request(FROM, jane(), "prompt-2");
+        assert!(matches!(store.resolve(FROM, "n", None), Some(Resolution::Declined(_))));
+    }
+
+    #[test]
+    fn a_late_reply_finds_it_expired() {
+        let store = ConfirmationStore::new(Duration::from_millis(10));
+        store.request(FROM, jane(), "prompt-1");
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(matches!(store.resolve(FROM, "yes", None), Some(Resolution::Expired(_))));
+    }
+
+    #[test]
+    fn other_replies_leave_it_pending() {
+        let store = ConfirmationStore::new(Duration::from_secs(60));
+        store.request(FROM, jane(), "prompt-1");
+        assert!(store.resolve(FROM, "maybe", None).is_none());
+        assert!(store.resolve("15550000000", "yes", None).is_none());
+        // Quoting some other message than the prompt
+        assert!(store.resolve(FROM, "yes", Some("other-message")).is_none());
+        assert!(matches!(store.resolve(FROM, "yes", Some("prompt-1")), Some(Resolution::Confirmed(_))));
+    }
+}
@@ -0,0 +1,230 @@
+// Guided contact entry: a bare trigger word starts a conversation that asks for the name, phone
+// number and email one message at a time
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::command::{ParseError, is_valid_email, normalize_phone};
+use crate::{PhoneKind, PhoneNumber, VCard};
+
+// Where a sender is in the flow; each inbound message feeds the current step
+#[derive(Debug)]
+pub enum ContactBuilderState {
+    AwaitingName,
+    AwaitingPhone {
+        first_name: String,
+        last_name: String,
+    },
+    AwaitingEmail {
+        first_name: String,
+        last_name: String,
+        phone: PhoneNumber,
+    },
+    Complete(Box<VCard>),
+}
+
+impl ContactBuilderState {
+    // Feed one answer to the current step. An invalid answer leaves the state where it was.
+    pub fn advance(&mut self, input: &str, default_country_code: Option<&str>) -> Result<(), ParseError> {
+        let input = input.trim();
+        let next = match self {
+            ContactBuilderState::AwaitingName => {
+                let mut words = input.split_whitespace();
+                let first_name = words.next().ok_or(ParseError::MissingName)?.to_string();
+                ContactBuilderState::AwaitingPhone {
+                    first_name,
+                    last_name: words.collect::<Vec<_>>().join(" "),
+                }
+            }
+            ContactBuilderState::AwaitingPhone { first_name, last_name } => {
+                if input.is_empty() {
+                    return Err(ParseError::MissingPhone);
+                }
+                let phone = PhoneNumber {
+                    number: normalize_phone(input, default_country_code)?,
+                    kind: PhoneKind::Cell,
+                };
+                ContactBuilderState::AwaitingEmail {
+                    first_name: std::mem::take(first_name),
+                    last_name: std::mem::take(last_name),
+                    phone,
+                }
+            }
+            ContactBuilderState::AwaitingEmail { .. } => {
+                let email = if input.eq_ignore_ascii_case("skip") {
+                    None
+                } else if is_valid_email(input) {
+                    Some(input.to_string())
+                } else {
+                    return Err(ParseError::InvalidEmail(input.to_string()));
+                };
+                let ContactBuilderState::AwaitingEmail { first_name, last_name, phone } =
+                    std::mem::replace(self, ContactBuilderState::AwaitingName)
+                else {
+                    unreachable!("matched AwaitingEmail above");
+                };
+                let mut contact = VCard::with_phone_numbers(first_name, last_name, vec![phone])?;
+                contact.email = email;
+                ContactBuilderState::Complete(Box::new(contact))
+            }
+            ContactBuilderState::Complete(_) => return Ok(()),
+        };
+        *self = next;
+        Ok(())
+    }
+
+    // Question to send for the current step
+    pub fn prompt(&self) -> String {
+        match self {
+            ContactBuilderState::AwaitingName => {
+                "What is the contact's name? Reply CANCEL at any time to stop.".to_string()
+            }
+            ContactBuilderState::AwaitingPhone { first_name, .. } => format!(
+                "What is {}'s phone number? Use international format, e.g. +15551234567.",
+                first_name
+            ),
+            ContactBuilderState::AwaitingEmail { first_name, .. } => {
+                format!("What is {}'s email address? Reply SKIP to leave it out.", first_name)
+            }
+            ContactBuilderState::Complete(_) => "Done.".to_string(),
+        }
+    }
+}
+
+// What a message did to the sender's flow
+#[derive(Debug)]
+pub enum Step {
+    // Send this to the sender: the next question, or the same one again after a bad answer
+    Prompt(String),
+    Complete(Box<VCard>),
+    Cancelled,
+}
+
+struct Session {
+    state: ContactBuilderState,
+    expires_at: Instant,
+}
+
+// In-progress flows per sender. A flow left idle for `ttl` is forgotten.
+pub struct ContactBuilder {
+    ttl: Duration,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+fn is_cancel(input: &str) -> bool {
+    input.trim().eq_ignore_ascii_case("cancel")
+}
+
+impl ContactBuilder {
+    pub fn new(ttl: Duration) -> Self {
+        ContactBuilder {
+            ttl,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Start (or restart) a flow for `from` and return the first question
+    pub fn start(&self, from: &str) -> String {
+        let now = Instant::now();
+        let state = ContactBuilderState::AwaitingName;
+        let prompt = state.prompt();
+        let mut sessions = self.sessions.lock().expect("contact builder lock poisoned");
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(
+            from.to_string(),
+            Session {
+                state,
+                expires_at: now + self.ttl,
+            },
+        );
+        prompt
+    }
+
+    // Feed a message from `from` into their flow. Returns None when they have no flow running.
+    pub fn feed(&self, from: &str, input: &str, default_country_code: Option<&str>) -> Option<Step> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().expect("contact builder lock poisoned");
+        let session = sessions.get_mut(from)?;
+        if session.expires_at <= now {
+            sessions.remove(from);
+            return None;
+        }
+        if is_cancel(input) {
+            sessions.remove(from);
+            return Some(Step::Cancelled);
+        }
+        session.expires_at = now + self.ttl;
+        if let Err(e) = session.state.advance(input, default_country_code) {
+            return Some(Step::Prompt(format!("Sorry, {}. {}", e, session.state.prompt())));
+        }
+        if matches!(session.state, ContactBuilderState::Complete(_)) {
+            let ContactBuilderState::Complete(contact) = sessions.remove(from)?.state else {
+                unreachable!("checked for Complete above");
+            };
+            return Some(Step::Complete(contact));
+        }
+        Some(Step::Prompt(session.state.prompt()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FROM: &str = "15557654321";
+
+    fn prompt(step: Option<Step>) -> String {
+        match step {
+            Some(Step::Prompt(prompt)) => prompt,
+            other => panic!("expected a prompt, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn happy_path_builds_the_contact() {
+        let builder = ContactBuilder::new(Duration::from_secs(60));
+        assert_eq!(builder.start(FROM), "What is the contact's name? Reply CANCEL at any time to stop.");
+        assert_eq!(
+            prompt(builder.feed(FROM, "Jane Smith", None)),
+            "What is Jane's phone number? Use international format, e.g. +15551234567."
+        );
+        assert_eq!(prompt(builder.feed(FROM, "+1 555 123 0000", None)), "What is Jane's email address? Reply SKIP to leave it out.");
+        let Some(Step::Complete(contact)) = builder.feed(FROM, "jane@example.com", None) else {
+            panic!("the flow should be complete");
+        };
+        assert_eq!((contact.first_name.as_str(), contact.last_name.as_str()), ("Jane", "Smith"));
+        assert_eq!(contact.phone_numbers[0].number, "+15551230000");
+        assert_eq!(contact.email.as_deref(), Some("jane@example.com"));
+        // The flow is over
+        assert!(builder.feed(FROM, "hello", None).is_none());
+    }
+
+    #[test]
+    fn a_bad_answer_asks_again() {
+        let builder = ContactBuilder::new(Duration::from_secs(60));
+        builder.start(FROM);
+        builder.feed(FROM, "Jane", None);
+        let retry = prompt(builder.feed(FROM, "555", None));
+        assert!(retry.starts_with("Sorry, '555' is not a valid phone number"), "{}", retry);
+        assert!(retry.ends_with("What is Jane's phone number? Use international format, e.g. +15551234567."));
+        builder.feed(FROM, "+15551230000", None);
+        assert!(matches!(builder.feed(FROM, "SKIP", None), Some(Step::Complete(contact)) if contact.email.is_none()));
+    }
+
+    #[test]
+    fn cancel_mid_flow() {
+        let builder = ContactBuilder::new(Duration::from_secs(60));
+        builder.start(FROM);
+        builder.feed(FROM, "Jane Smith", None);
+        assert!(matches!(builder.feed(FROM, " Cancel ", None), Some(Step::Cancelled)));
+        assert!(builder.feed(FROM, "+15551230000", None).is_none());
+    }
+
+    #[test]
+    fn an_idle_flow_is_forgotten() {
+        let builder = ContactBuilder::new(Duration::from_millis(10));
+        builder.start(FROM);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(builder.feed(FROM, "Jane Smith", None).is_none());
+    }
+}
@@ -0,0 +1,326 @@
+// The Infobip client, with the API key read again when a call is refused with 401, so a key
+// rotated while we run is picked up without a restart. Requests go out through reqwest rather
+// than the SDK's client, which drops the response headers, so a 429's Retry-After is visible.
+use std::fs;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use infobip_sdk::api::whatsapp::{
+    PATH_GET_TEMPLATES, PATH_SEND_CONTACT, PATH_SEND_DOCUMENT, PATH_SEND_IMAGE, PATH_SEND_LOCATION, PATH_SEND_TEMPLATE,
+    PATH_SEND_TEXT,
+};
+use infobip_sdk::api::{ApiError, SdkError};
+use infobip_sdk::model::whatsapp::{
+    SendContactRequestBody, SendDocumentRequestBody, SendImageRequestBody, SendLocationRequestBody, SendTemplateRequestBody,
+    SendTextRequestBody,
+};
+use log::{error, info, warn};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+use serde::Serialize;
+use validator::Validate;
+
+use crate::error::BotError;
+use crate::sender::MessageSender;
+
+pub type KeySource = Box<dyn Fn() -> Result<String, BotError> + Send + Sync>;
+
+// Without a key source a 401 is returned as it is. With one, the key is read again and the call
+// retried once under the new key; a 401 that survives that comes back as BotError::AuthFailed,
+// which the circuit breaker counts.
+pub struct RefreshingClient {
+    base_url: String,
+    http: reqwest::Client,
+    api_key: RwLock<String>,
+    read_key: Option<KeySource>,
+}
+
+// A key kept in a file, as secret stores mount them; surrounding whitespace is not part of it
+pub fn read_key_file(path: &str) -> Result<String, BotError> {
+    let key = fs::read_to_string(path)
+        .map_err(|e| BotError::Config(format!("could not read API key file {}: {}", path, e)))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(BotError::Config(format!("API key file {} is empty", path)));
+    }
+    Ok(key.to_string())
+}
+
+// Retry-After is either a number of seconds or an HTTP-date; a date already past means now
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((at.with_timezone(&Utc) - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+// The same error the SDK builds from a refused call: Infobip's JSON error body when it parses
+fn api_error(status: StatusCode, body: &str) -> SdkError {
+    match serde_json::from_str(body) {
+        Ok(details) => SdkError::ApiRequestError(ApiError { details, status }),
+        Err(e) => SdkError::Serde(e),
+    }
+}
+
+impl RefreshingClient {
+    pub fn new(base_url: String, api_key: String, read_key: Option<KeySource>) -> Self {
+        RefreshingClient { base_url, http: reqwest::Client::new(), api_key: RwLock::new(api_key), read_key }
+    }
+
+    fn current_key(&self) -> String {
+        self.api_key.read().expect("api key lock poisoned").clone()
+    }
+
+    // Read the key again after `refused_key` got a 401. True when there is a different key to
+    // try, which another call may already have switched to.
+    fn refresh(&self, refused_key: &str) -> bool {
+        let Some(read_key) = &self.read_key else {
+            return false;
+        };
+        let key = match read_key() {
+            Ok(key) => key,
+            Err(e) => {
+                error!("Infobip refused the API key and it could not be read again: {}", e);
+                return false;
+            }
+        };
+        if key == refused_key {
+            warn!("Infobip refused the API key, and reading it again gave the same one");
+            return false;
+        }
+        let mut current = self.api_key.write().expect("api key lock poisoned");
+        if *current != key {
+            info!("Infobip refused the API key, switching to the newly read one");
+            *current = key;
+        }
+        true
+    }
+
+    async fn request(&self, api_key: &str, method: Method, path: &str, body: Option<&str>) -> Result<(), BotError> {
+        let mut request = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path))
+            .header(AUTHORIZATION, format!("App {}", api_key));
+        if let Some(body) = body {
+            request = request.header(CONTENT_TYPE, "application/json").body(body.to_string());
+        }
+        let response = request.send().await.map_err(SdkError::from)?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, Utc::now()));
+        let text = response.text().await.map_err(SdkError::from)?;
+        match BotError::from(api_error(status, &text)) {
+            BotError::RateLimited { message, .. } => Err(BotError::RateLimited { message, retry_after }),
+            // A 429 whose body isn't Infobip's error JSON is still a rate limit
+            e if status == StatusCode::TOO_MANY_REQUESTS => Err(BotError::RateLimited { message: e.to_string(), retry_after }),
+            e => Err(e),
+        }
+    }
+
+    async fn call(&self, method: Method, path: &str, body: Option<&str>) -> Result<(), BotError> {
+        let key = self.current_key();
+        let refused = match self.request(&key, method.clone(), path, body).await {
+            Err(e) if self.read_key.is_some() && e.is_auth_error() => e,
+            result => return result,
+        };
+        if !self.refresh(&key) {
+            return Err(refused.into_auth_failed());
+        }
+        match self.request(&self.current_key(), method, path, body).await {
+            Err(e) if e.is_auth_error() => Err(e.into_auth_failed()),
+            result => result,
+        }
+    }
+
+    // Checked before anything is sent, as the SDK does
+    async fn post<T: Validate + Serialize>(&self, path: &str, request_body: T) -> Result<(), BotError> {
+        request_body.validate().map_err(SdkError::from)?;
+        let body = serde_json::to_string(&request_body).map_err(SdkError::from)?;
+        self.call(Method::POST, path, Some(&body)).await
+    }
+}
+
+impl MessageSender for RefreshingClient {
+    async fn send_text(&self, request_body: SendTextRequestBody) -> Result<(), BotError> {
+        self.post(PATH_SEND_TEXT, request_body).await
+    }
+
+    async fn send_contact(&self, request_body: SendContactRequestBody) -> Result<(), BotError> {
+        self.post(PATH_SEND_CONTACT, request_body).await
+    }
+
+    async fn send_template(&self, request_body: SendTemplateRequestBody) -> Result<(), BotError> {
+        self.post(PATH_SEND_TEMPLATE, request_body).await
+    }
+
+    async fn send_image(&self, request_body: SendImageRequestBody) -> Result<(), BotError> {
+        self.post(PATH_SEND_IMAGE, request_body).await
+    }
+
+    async fn send_document(&self, request_body: SendDocumentRequestBody) -> Result<(), BotError> {
+        self.post(PATH_SEND_DOCUMENT, request_body).await
+    }
+
+    async fn send_location(&self, request_body: SendLocationRequestBody) -> Result<(), BotError> {
+        self.post(PATH_SEND_LOCATION, request_body).await
+    }
+
+    async fn check_sender(&self, sender: &str) -> Result<(), BotError> {
+        let path = PATH_GET_TEMPLATES.replace("{sender}", sender.trim_start_matches('+'));
+        self.call(Method::GET, &path, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use warp::Filter;
+
+    use super::*;
+    use crate::sender::tests::text_body;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(parse_retry_after("120", now()), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now()), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now()), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now()), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_unparseable() {
+        assert_eq!(parse_retry_after("soon", now()), None);
+        assert_eq!(parse_retry_after("", now()), None);
+    }
+
+    // Infobip, accepting only "valid-key"; counts the requests it gets
+    fn serve(requests: Arc<AtomicUsize>) -> SocketAddr {
+        let route = warp::any().and(warp::header::optional::<String>("authorization")).map(move |auth: Option<String>| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            if auth.as_deref() == Some("App valid-key") {
+                return warp::reply::with_status(warp::reply::json(&serde_json::json!({})), warp::http::StatusCode::OK);
+            }
+            let refused = serde_json::json!({
+                "requestError": { "serviceException": { "messageId": "UNAUTHORIZED", "text": "Invalid login details" } }
+            });
+            warp::reply::with_status(warp::reply::json(&refused), warp::http::StatusCode::UNAUTHORIZED)
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    // A client starting on `key` whose key source then hands out `rotated`
+    fn client(addr: SocketAddr, key: &str, rotated: Option<&str>) -> RefreshingClient {
+        let read_key: Option<KeySource> = rotated.map(|rotated| {
+            let rotated = Mutex::new(rotated.to_string());
+            Box::new(move || Ok(rotated.lock().unwrap().clone())) as KeySource
+        });
+        RefreshingClient::new(format!("http://{}", addr), key.to_string(), read_key)
+    }
+
+    #[tokio::test]
+    async fn a_refused_key_is_read_again_and_the_send_retried() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let client = client(serve(requests.clone()), "old-key", Some("valid-key"));
+        client.send_text(text_body("+15551234567", "hello")).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        // The new key is kept for the calls after
+        client.send_text(text_body("+15551234567", "again")).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn a_key_that_is_still_refused_fails_the_send() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let addr = serve(requests.clone());
+        // The source still holds the refused key: nothing to retry with
+        let unchanged = client(addr, "old-key", Some("old-key"));
+        let result = unchanged.send_text(text_body("+15551234567", "hello")).await;
+        assert!(matches!(result, Err(BotError::AuthFailed(_))), "{:?}", result);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // A new key that is refused too
+        let rotated = client(addr, "old-key", Some("other-key"));
+        assert!(matches!(rotated.send_text(text_body("+15551234567", "hello")).await, Err(BotError::AuthFailed(_))));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn without_a_key_source_a_401_is_returned_as_it_is() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let client = client(serve(requests.clone()), "old-key", None);
+        let error = client.send_text(text_body("+15551234567", "hello")).await.unwrap_err();
+        assert!(error.is_auth_error(), "{:?}", error);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    struct TempKeyFile(std::path::PathBuf);
+
+    impl TempKeyFile {
+        fn new(key: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("tool-test-{}.key", uuid::Uuid::new_v4()));
+            fs::write(&path, key).unwrap();
+            TempKeyFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempKeyFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn a_key_file_is_read_without_surrounding_whitespace() {
+        let file = TempKeyFile::new("  valid-key\n");
+        assert_eq!(read_key_file(file.path()).unwrap(), "valid-key");
+    }
+
+    #[test]
+    fn a_missing_or_empty_key_file_is_a_config_error() {
+        let empty = TempKeyFile::new(" \n");
+        assert!(matches!(read_key_file(empty.path()), Err(BotError::Config(_))));
+        let missing = std::env::temp_dir().join(format!("tool-test-{}.key", uuid::Uuid::new_v4()));
+        assert!(matches!(read_key_file(missing.to_str().unwrap()), Err(BotError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn a_key_rotated_in_its_file_is_picked_up_after_a_401() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let file = TempKeyFile::new("old-key");
+        let path = file.path().to_string();
+        let read_key: KeySource = Box::new(move || read_key_file(&path));
+        let addr = serve(requests.clone());
+        let client = RefreshingClient::new(format!("http://{}", addr), read_key_file(file.path()).unwrap(), Some(read_key));
+        assert!(matches!(client.send_text(text_body("+15551234567", "hello")).await, Err(BotError::AuthFailed(_))));
+
+        fs::write(&file.0, "valid-key\n").unwrap();
+        client.send_text(text_body("+15551234567", "hello")).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,129 @@
+// Messages the worker gave up on, kept with the reason so they can be inspected and replayed
+use std::sync::Mutex;
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+
+use crate::WhatsAppMessage;
+use crate::error::BotError;
+use crate::migrations;
+
+#[derive(Debug, Serialize)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub message: WhatsAppMessage,
+    pub reason: String,
+    pub failed_at: String,
+    pub replayed_at: Option<String>,
+}
+
+// Why a dead letter couldn't be claimed for replay
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    NotFound,
+    AlreadyReplayed,
+}
+
+pub struct DeadLetterStore {
+    conn: Mutex<Connection>,
+}
+
+impl DeadLetterStore {
+    // Open (or create) the store in the database at `database_url`; a sqlite:// prefix is accepted
+    pub fn open(database_url: &str) -> Result<Self, BotError> {
+        let conn = migrations::open(database_url)?;
+        Ok(DeadLetterStore { conn: Mutex::new(conn) })
+    }
+
+    pub fn record(&self, message: &WhatsAppMessage, reason: &str) -> Result<i64, BotError> {
+        let payload = serde_json::to_string(message)
+            .map_err(|e| BotError::Send(format!("could not serialize dead-lettered message: {}", e)))?;
+        let conn = self.conn.lock().expect("dead letter lock poisoned");
+        conn.execute(
+            "INSERT INTO dead_letters (payload, reason) VALUES (?1, ?2)",
+            params![payload, reason],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    // The most recent `limit` entries, newest first
+    pub fn list(&self, limit: usize) -> Result<Vec<DeadLetter>, BotError> {
+        let conn = self.conn.lock().expect("dead letter lock poisoned");
+        let rows = conn
+            .prepare("SELECT id, payload, reason, failed_at, replayed_at FROM dead_letters ORDER BY id DESC LIMIT ?1")?
+            .query_map(params![limit as i64], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(id, payload, reason, failed_at, replayed_at)| {
+                Ok(DeadLetter { id, message: decode(id, &payload)?, reason, failed_at, replayed_at })
+            })
+            .collect()
+    }
+
+    // Mark an entry replayed and hand back its message. Claiming and marking in one statement
+    // means two concurrent replays can't both get it.
+    pub fn claim_for_replay(&self, id: i64) -> Result<Result<WhatsAppMessage, ReplayError>, BotError> {
+        let conn = self.conn.lock().expect("dead letter lock poisoned");
+        let payload: Option<String> = conn
+            .query_row(
+                "UPDATE dead_letters SET replayed_at = datetime('now') WHERE id = ?1 AND replayed_at IS NULL
+                 RETURNING payload",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(payload) = payload {
+            return Ok(Ok(decode(id, &payload)?));
+        }
+        let exists = conn.prepare("SELECT 1 FROM dead_letters WHERE id = ?1")?.exists(params![id])?;
+        Ok(Err(if exists { ReplayError::AlreadyReplayed } else { ReplayError::NotFound }))
+    }
+
+    // Undo a claim whose message could not be queued, so it can be replayed again later
+    pub fn release(&self, id: i64) -> Result<(), BotError> {
+        let conn = self.conn.lock().expect("dead letter lock poisoned");
+        conn.execute("UPDATE dead_letters SET replayed_at = NULL WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+}
+
+fn decode(id: i64, payload: &str) -> Result<WhatsAppMessage, BotError> {
+    serde_json::from_str(payload).map_err(|e| BotError::Send(format!("dead letter {} is corrupt: {}", id, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::text_message;
+
+    #[test]
+    fn records_are_listed_newest_first() {
+        let store = DeadLetterStore::open(":memory:").unwrap();
+        let first = store.record(&text_message("m1", "addcontact Jane Smith +15551230000"), "rejected").unwrap();
+        let second = store.record(&text_message("m2", "addcontact Bob +15551230001"), "timed out").unwrap();
+        let listed = store.list(10).unwrap();
+        assert_eq!(listed.iter().map(|entry| entry.id).collect::<Vec<_>>(), [second, first]);
+        assert_eq!(listed[1].message.correlation_id, "m1");
+        assert_eq!(listed[1].message.text.as_deref(), Some("addcontact Jane Smith +15551230000"));
+        assert_eq!(listed[1].reason, "rejected");
+        assert!(!listed[1].failed_at.is_empty());
+        assert_eq!(listed[1].replayed_at, None);
+        assert_eq!(store.list(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn a_dead_letter_is_claimed_for_replay_once() {
+        let store = DeadLetterStore::open(":memory:").unwrap();
+        let id = store.record(&text_message("m1", "addcontact Jane Smith +15551230000"), "rejected").unwrap();
+        assert_eq!(store.claim_for_replay(id).unwrap().unwrap().correlation_id, "m1");
+        assert!(store.list(1).unwrap()[0].replayed_at.is_some());
+        assert!(matches!(store.claim_for_replay(id).unwrap(), Err(ReplayError::AlreadyReplayed)));
+        assert!(matches!(store.claim_for_replay(id + 1).unwrap(), Err(ReplayError::NotFound)));
+        // A claim given back can be made again
+        store.release(id).unwrap();
+        assert!(store.claim_for_replay(id).unwrap().is_ok());
+    }
+}
@@ -0,0 +1,75 @@
+// Time-windowed set of recently seen keys, used to drop redelivered webhooks and repeated
+// contacts
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Remembers keys for `window`, or for the window given with the key, holding at most
+// `capacity` of them (oldest evicted first)
+pub struct DedupCache {
+    window: Duration,
+    capacity: usize,
+    seen: Mutex<SeenKeys>,
+}
+
+// Keys with the time they expire. With windows of different lengths the queue isn't in expiry
+// order, so a key may outlive its window here until it reaches the front; lookups check the
+// expiry themselves.
+#[derive(Default)]
+struct SeenKeys {
+    by_key: HashMap<String, Instant>,
+    order: VecDeque<(String, Instant)>,
+}
+
+impl SeenKeys {
+    fn evict(&mut self, now: Instant, capacity: usize) {
+        while let Some((key, expires_at)) = self.order.front() {
+            let expired = now >= *expires_at;
+            if !expired && self.order.len() <= capacity {
+                break;
+            }
+            // Only drop the map entry if it hasn't been refreshed since
+            if self.by_key.get(key) == Some(expires_at) {
+                self.by_key.remove(key);
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
+impl DedupCache {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        DedupCache {
+            window,
+            capacity: capacity.max(1),
+            seen: Mutex::new(SeenKeys::default()),
+        }
+    }
+
+    // Records the key and returns true the first time it is seen within the window.
+    // The check and insert happen under one lock so concurrent callers can't both win.
+    pub fn insert(&self, key: &str) -> bool {
+        self.insert_for(key, self.window)
+    }
+
+    // insert, remembering this key for `window` instead
+    pub fn insert_for(&self, key: &str, window: Duration) -> bool {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().expect("dedup lock poisoned");
+        seen.evict(now, self.capacity);
+        if seen.by_key.get(key).is_some_and(|expires_at| now < *expires_at) {
+            return false;
+        }
+        let expires_at = now + window;
+        seen.by_key.insert(key.to_string(), expires_at);
+        seen.order.push_back((key.to_string(), expires_at));
+        seen.evict(now, self.capacity);
+        true
+    }
+
+    // Forget a key, e.g. when the message it guarded could not be accepted after all
+    pub fn remove(&self, key: &str) {
+        let mut seen = self.seen.lock().expect("dedup lock poisoned");
+        seen.by_key.remove(key);
+    }
+}
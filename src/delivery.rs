@@ -0,0 +1,135 @@
+// Delivery reports Infobip posts to our status callback once it knows the fate of a send
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryReports {
+    pub results: Vec<DeliveryReport>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryReport {
+    // The messageId we sent with, i.e. the delivery's idempotency key
+    pub message_id: String,
+    pub to: String,
+    pub status: ReportStatus,
+    #[serde(default)]
+    pub error: Option<ReportError>,
+    // Only present on seen (read) reports
+    #[serde(default)]
+    pub seen_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportStatus {
+    pub group_name: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportError {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeliveryStatus {
+    Sent,
+    Delivered,
+    Read,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Read => "read",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+impl DeliveryReport {
+    // Infobip groups its detailed statuses; PENDING and anything unrecognised means the message
+    // is still on its way
+    pub fn delivery_status(&self) -> DeliveryStatus {
+        if self.seen_at.is_some() {
+            return DeliveryStatus::Read;
+        }
+        match self.status.group_name.to_ascii_uppercase().as_str() {
+            "DELIVERED" => DeliveryStatus::Delivered,
+            "UNDELIVERABLE" | "EXPIRED" | "REJECTED" => DeliveryStatus::Failed,
+            _ => DeliveryStatus::Sent,
+        }
+    }
+
+    // Best available explanation of a failure, for the logs
+    pub fn failure_reason(&self) -> &str {
+        self.error
+            .as_ref()
+            .and_then(|e| e.description.as_deref().or(e.name.as_deref()))
+            .or(self.status.description.as_deref())
+            .or(self.status.name.as_deref())
+            .unwrap_or(&self.status.group_name)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // A status callback as Infobip sends it: one delivered, one failed, one seen
+    pub const SAMPLE_REPORTS: &str = r#"{
+        "results": [
+            {
+                "bulkId": null,
+                "price": { "pricePerMessage": 0, "currency": "EUR" },
+                "status": { "groupId": 3, "groupName": "DELIVERED", "id": 5, "name": "DELIVERED_TO_HANDSET", "description": "Message delivered to handset" },
+                "error": { "groupId": 0, "groupName": "OK", "id": 0, "name": "NO_ERROR", "description": "No Error", "permanent": false },
+                "messageId": "a1b2c3",
+                "doneAt": "2019-11-09T16:01:00.000+0000",
+                "messageCount": 1,
+                "sentAt": "2019-11-09T16:00:00.000+0000",
+                "to": "15551234567"
+            },
+            {
+                "status": { "groupId": 2, "groupName": "UNDELIVERABLE", "id": 9, "name": "UNDELIVERABLE_NOT_DELIVERED", "description": "Message sent not delivered" },
+                "error": { "groupId": 1, "groupName": "HANDSET_ERRORS", "id": 27, "name": "EC_ABSENT_SUBSCRIBER", "description": "Absent Subscriber", "permanent": false },
+                "messageId": "d4e5f6",
+                "to": "15557654000"
+            },
+            {
+                "status": { "groupId": 3, "groupName": "DELIVERED", "id": 5, "name": "DELIVERED_TO_HANDSET" },
+                "messageId": "g7h8i9",
+                "to": "15551234567",
+                "seenAt": "2019-11-09T16:05:00.000+0000"
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn parses_a_sample_report() {
+        let reports: DeliveryReports = serde_json::from_str(SAMPLE_REPORTS).unwrap();
+        let statuses: Vec<DeliveryStatus> = reports.results.iter().map(DeliveryReport::delivery_status).collect();
+        assert_eq!(statuses, [DeliveryStatus::Delivered, DeliveryStatus::Failed, DeliveryStatus::Read]);
+        assert_eq!(reports.results[0].message_id, "a1b2c3");
+        assert_eq!(reports.results[1].to, "15557654000");
+        assert_eq!(reports.results[1].failure_reason(), "Absent Subscriber");
+    }
+
+    #[test]
+    fn pending_and_unknown_groups_count_as_sent() {
+        let report: DeliveryReport =
+            serde_json::from_str(r#"{"messageId": "x", "to": "1", "status": {"groupName": "PENDING"}}"#).unwrap();
+        assert_eq!(report.delivery_status(), DeliveryStatus::Sent);
+        assert_eq!(report.failure_reason(), "PENDING");
+    }
+}
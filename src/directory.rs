@@ -0,0 +1,346 @@
+// Contacts loaded from CONTACTS_CSV at startup, so "addcontact jane" can stand for a full card.
+// Edits made through /contacts are written back to the same file.
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::command::{ParseError, is_valid_email, validate_e164};
+use crate::error::BotError;
+use crate::{FieldLimits, PhoneKind, PhoneNumber, VCard};
+
+// One CSV row, also the JSON body of the /contacts routes; only alias, first_name and phone
+// are required
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub alias: String,
+    pub first_name: String,
+    #[serde(default)]
+    pub last_name: String,
+    pub phone: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub organization: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+    // Semicolon-separated, so a category may contain a comma: "Support;Sales, EMEA"
+    #[serde(default)]
+    pub categories: Option<String>,
+}
+
+// Empty CSV cells come through as Some("")
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+impl Entry {
+    fn from_contact(alias: &str, contact: &VCard) -> Self {
+        Entry {
+            alias: alias.to_string(),
+            first_name: contact.first_name.clone(),
+            last_name: contact.last_name.clone(),
+            phone: contact.primary_phone().to_string(),
+            email: contact.email.clone(),
+            organization: contact.organization.clone(),
+            title: contact.title.clone(),
+            url: contact.url.clone(),
+            note: contact.note.clone(),
+            categories: (!contact.categories.is_empty()).then(|| contact.categories.join(";")),
+        }
+    }
+
+    fn into_contact(self, limits: &FieldLimits) -> Result<VCard, BotError> {
+        if self.first_name.trim().is_empty() {
+            return Err(ParseError::MissingName.into());
+        }
+        let phone = PhoneNumber {
+            number: validate_e164(&self.phone)?,
+            kind: PhoneKind::Cell,
+        };
+        let mut contact = VCard::with_phone_numbers(
+            self.first_name.trim().to_string(),
+            self.last_name.trim().to_string(),
+            vec![phone],
+        )?;
+        contact.email = match non_empty(self.email) {
+            Some(email) if !is_valid_email(&email) => return Err(ParseError::InvalidEmail(email).into()),
+            email => email,
+        };
+        contact.organization = non_empty(self.organization);
+        contact.title = non_empty(self.title);
+        contact.url = non_empty(self.url);
+        contact.note = non_empty(self.note);
+        contact.categories = self
+            .categories
+            .unwrap_or_default()
+            .split(';')
+            .map(str::trim)
+            .filter(|category| !category.is_empty())
+            .map(str::to_string)
+            .collect();
+        limits.check(&contact)?;
+        Ok(contact)
+    }
+}
+
+// Aliases are matched case-insensitively
+pub struct ContactDirectory {
+    path: String,
+    contacts: RwLock<HashMap<String, VCard>>,
+    limits: FieldLimits,
+}
+
+// A message naming a single word without digits is looked up as an alias, so anything else
+// could never be used
+fn valid_alias(alias: &str) -> bool {
+    !alias.is_empty() && !alias.chars().any(|c| c.is_whitespace() || c.is_ascii_digit())
+}
+
+impl ContactDirectory {
+    // A file that can't be read is an error; a bad row is skipped with a warning
+    pub fn load(path: &str, limits: FieldLimits) -> Result<Self, BotError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| BotError::Config(format!("CONTACTS_CSV '{}' can't be read: {}", path, e)))?;
+        let mut contacts = HashMap::new();
+        for (index, row) in reader.deserialize::<Entry>().enumerate() {
+            // Line 1 is the header
+            let line = index + 2;
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => {
+                    warn!("Skipping contacts CSV line {}: {}", line, e);
+                    continue;
+                }
+            };
+            let alias = row.alias.trim().to_lowercase();
+            if alias.is_empty() {
+                warn!("Skipping contacts CSV line {}: the alias is empty", line);
+                continue;
+            }
+            if contacts.contains_key(&alias) {
+                warn!("Skipping contacts CSV line {}: alias '{}' is already taken", line, alias);
+                continue;
+            }
+            match row.into_contact(&limits) {
+                Ok(contact) => {
+                    contacts.insert(alias, contact);
+                }
+                Err(e) => warn!("Skipping contacts CSV line {} ({}): {}", line, alias, e.contact_problem()),
+            }
+        }
+        Ok(ContactDirectory { path: path.to_string(), contacts: RwLock::new(contacts), limits })
+    }
+
+    pub fn get(&self, alias: &str) -> Option<VCard> {
+        self.contacts.read().expect("directory lock poisoned").get(&alias.trim().to_lowercase()).cloned()
+    }
+
+    pub fn entry(&self, alias: &str) -> Option<Entry> {
+        let alias = alias.trim().to_lowercase();
+        self.get(&alias).map(|contact| Entry::from_contact(&alias, &contact))
+    }
+
+    // Contacts with the category `group`, matched case-insensitively, by alias
+    pub fn group(&self, group: &str) -> Vec<VCard> {
+        let group = group.trim().to_lowercase();
+        let contacts = self.contacts.read().expect("directory lock poisoned");
+        let mut members: Vec<(&String, &VCard)> = contacts
+            .iter()
+            .filter(|(_, contact)| contact.categories.iter().any(|category| category.to_lowercase() == group))
+            .collect();
+        members.sort_by(|a, b| a.0.cmp(b.0));
+        members.into_iter().map(|(_, contact)| contact.clone()).collect()
+    }
+
+    // Every contact, by alias
+    pub fn entries(&self) -> Vec<Entry> {
+        let contacts = self.contacts.read().expect("directory lock poisoned");
+        let mut entries: Vec<Entry> =
+            contacts.iter().map(|(alias, contact)| Entry::from_contact(alias, contact)).collect();
+        entries.sort_by(|a, b| a.alias.cmp(&b.alias));
+        entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.contacts.read().expect("directory lock poisoned").len()
+    }
+
+    // Add or replace a contact and save the file. Returns whether the alias is new; an invalid
+    // contact is the inner error.
+    pub fn upsert(&self, entry: Entry) -> Result<Result<bool, BotError>, BotError> {
+        let alias = entry.alias.trim().to_lowercase();
+        if !valid_alias(&alias) {
+            return Ok(Err(ParseError::InvalidAlias(entry.alias).into()));
+        }
+        let contact = match entry.into_contact(&self.limits) {
+            Ok(contact) => contact,
+            Err(e) => return Ok(Err(e)),
+        };
+        let mut contacts = self.contacts.write().expect("directory lock poisoned");
+        let mut updated = contacts.clone();
+        let created = updated.insert(alias, contact).is_none();
+        self.save(&updated)?;
+        *contacts = updated;
+        Ok(Ok(created))
+    }
+
+    // Remove a contact and save the file. Returns false if there was none by that alias.
+    pub fn remove(&self, alias: &str) -> Result<bool, BotError> {
+        let mut contacts = self.contacts.write().expect("directory lock poisoned");
+        let mut updated = contacts.clone();
+        if updated.remove(&alias.trim().to_lowercase()).is_none() {
+            return Ok(false);
+        }
+        self.save(&updated)?;
+        *contacts = updated;
+        Ok(true)
+    }
+
+    // Write to a temporary file and rename it over the CSV, so a crash mid-write can't leave
+    // half a directory behind
+    fn save(&self, contacts: &HashMap<String, VCard>) -> Result<(), BotError> {
+        let unwritable = |e: String| BotError::Config(format!("CONTACTS_CSV '{}' can't be written: {}", self.path, e));
+        let mut entries: Vec<Entry> =
+            contacts.iter().map(|(alias, contact)| Entry::from_contact(alias, contact)).collect();
+        entries.sort_by(|a, b| a.alias.cmp(&b.alias));
+        let temp_path = format!("{}.tmp", self.path);
+        let mut writer = csv::Writer::from_path(&temp_path).map_err(|e| unwritable(e.to_string()))?;
+        for entry in &entries {
+            writer.serialize(entry).map_err(|e| unwritable(e.to_string()))?;
+        }
+        writer.flush().map_err(|e| unwritable(e.to_string()))?;
+        std::fs::rename(&temp_path, &self.path).map_err(|e| unwritable(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::tests::test_config;
+
+    // A CSV file of its own for one test, removed again when dropped
+    pub struct TempCsv(pub PathBuf);
+
+    impl TempCsv {
+        pub fn new(contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("tool-test-{}.csv", uuid::Uuid::new_v4()));
+            std::fs::write(&path, contents).unwrap();
+            TempCsv(path)
+        }
+
+        pub fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempCsv {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    pub const SAMPLE_CSV: &str = "alias,first_name,last_name,phone,email,categories
+jane,Jane,Smith,+15551230000,jane@example.com,Sales;Support
+Bob,Bob,,+1 555 123 0001,,
+";
+
+    fn load(contents: &str) -> (TempCsv, ContactDirectory) {
+        let file = TempCsv::new(contents);
+        let directory = ContactDirectory::load(file.path(), FieldLimits::from_config(&test_config(&[]))).unwrap();
+        (file, directory)
+    }
+
+    #[test]
+    fn loads_a_valid_csv() {
+        let (_file, directory) = load(SAMPLE_CSV);
+        assert_eq!(directory.len(), 2);
+        let jane = directory.get("jane").unwrap();
+        assert_eq!((jane.first_name.as_str(), jane.last_name.as_str()), ("Jane", "Smith"));
+        assert_eq!(jane.email.as_deref(), Some("jane@example.com"));
+        assert_eq!(jane.categories, ["Sales", "Support"]);
+        // Aliases ignore case, numbers are normalized and empty cells are left out
+        let bob = directory.get(" BOB ").unwrap();
+        assert_eq!(bob.phone_numbers[0].number, "+15551230001");
+        assert_eq!(bob.email, None);
+        assert!(bob.categories.is_empty());
+    }
+
+    #[test]
+    fn malformed_rows_are_skipped() {
+        let (_file, directory) = load(
+            "alias,first_name,last_name,phone,email
+jane,Jane,Smith,+15551230000,
+bad-phone,Bad,Phone,12345,
+bad-email,Bad,Email,+15551230001,not-an-email
+no-name,,Nobody,+15551230002,
+jane,Jane,Again,+15551230003,
+short,row
+,Empty,Alias,+15551230004,
+",
+        );
+        assert_eq!(directory.entries().iter().map(|entry| entry.alias.as_str()).collect::<Vec<_>>(), ["jane"]);
+        assert_eq!(directory.get("jane").unwrap().last_name, "Smith");
+    }
+
+    #[test]
+    fn unknown_alias() {
+        let (_file, directory) = load(SAMPLE_CSV);
+        assert!(directory.get("alice").is_none());
+        assert!(directory.entry("alice").is_none());
+    }
+
+    #[test]
+    fn a_missing_file_is_an_error() {
+        let limits = FieldLimits::from_config(&test_config(&[]));
+        assert!(matches!(ContactDirectory::load("/nonexistent/contacts.csv", limits), Err(BotError::Config(_))));
+    }
+
+    fn entry(alias: &str, phone: &str) -> Entry {
+        Entry {
+            alias: alias.to_string(),
+            first_name: "Alice".to_string(),
+            last_name: "Jones".to_string(),
+            phone: phone.to_string(),
+            email: None,
+            organization: None,
+            title: None,
+            url: None,
+            note: None,
+            categories: Some("Support;Sales, EMEA".to_string()),
+        }
+    }
+
+    #[test]
+    fn changes_are_saved_to_the_file() {
+        let (file, directory) = load(SAMPLE_CSV);
+        assert!(directory.upsert(entry("Alice", "+15551230009")).unwrap().unwrap());
+        assert!(!directory.upsert(entry("alice", "+15551230010")).unwrap().unwrap());
+        assert!(directory.remove("JANE").unwrap());
+        assert!(!directory.remove("jane").unwrap());
+
+        let reloaded = ContactDirectory::load(file.path(), FieldLimits::from_config(&test_config(&[]))).unwrap();
+        assert_eq!(reloaded.entries().iter().map(|entry| entry.alias.as_str()).collect::<Vec<_>>(), ["alice", "bob"]);
+        let alice = reloaded.get("alice").unwrap();
+        assert_eq!(alice.phone_numbers[0].number, "+15551230010");
+        assert_eq!(alice.categories, ["Support", "Sales, EMEA"]);
+    }
+
+    #[test]
+    fn an_invalid_contact_is_not_saved() {
+        let (file, directory) = load(SAMPLE_CSV);
+        assert!(matches!(directory.upsert(entry("alice", "12345")).unwrap(), Err(BotError::Parse(_))));
+        assert!(matches!(directory.upsert(entry("alice 2", "+15551230009")).unwrap(), Err(BotError::Parse(ParseError::InvalidAlias(_)))));
+        assert!(directory.get("alice").is_none());
+        assert_eq!(std::fs::read_to_string(&file.0).unwrap(), SAMPLE_CSV);
+    }
+}
@@ -0,0 +1,182 @@
+// Error type shared by config loading, parsing and the send path
+use std::time::Duration;
+
+use infobip_sdk::api::SdkError;
+use thiserror::Error;
+use warp::http::StatusCode;
+
+use crate::command::ParseError;
+
+#[derive(Debug, Error)]
+pub enum BotError {
+    #[error("configuration error: {0}")]
+    Config(String),
+
+    #[error("send failed: {0}")]
+    Send(String),
+
+    #[error("could not parse contact: {0}")]
+    Parse(#[from] ParseError),
+
+    // With the delay the server asked for in Retry-After, when it gave one
+    #[error("rate limited: {message}")]
+    RateLimited { message: String, retry_after: Option<Duration> },
+
+    #[error("Infobip API error: {0}")]
+    Infobip(SdkError),
+
+    #[error("Infobip refused the API key, also after reading it again: {0}")]
+    AuthFailed(SdkError),
+
+    #[error("storage error: {0}")]
+    Storage(#[from] rusqlite::Error),
+
+    #[error("circuit breaker is open, not calling the Infobip API")]
+    CircuitOpen,
+
+    #[error("Infobip call timed out after {0:?}")]
+    Timeout(Duration),
+
+    #[error("message is {length} characters, over the {max} character limit, and can't be split safely")]
+    MessageTooLong { length: usize, max: usize },
+
+    #[error("the {field} is {length} characters, over the {max} character limit")]
+    FieldTooLong { field: &'static str, length: usize, max: usize },
+
+    // Shutdown stopped waiting for the queue, so some messages were dead-lettered or abandoned
+    #[error("queue not drained within {0:?}")]
+    DrainTimeout(Duration),
+}
+
+impl From<SdkError> for BotError {
+    fn from(e: SdkError) -> Self {
+        match &e {
+            // The error carries no headers; RefreshingClient adds the Retry-After delay
+            SdkError::ApiRequestError(api_error) if api_error.status.as_u16() == 429 => {
+                BotError::RateLimited { message: api_error.to_string(), retry_after: None }
+            }
+            _ => BotError::Infobip(e),
+        }
+    }
+}
+
+impl BotError {
+    // Timeouts, connection failures, rate limits and 5xx responses are worth retrying; anything
+    // else (validation errors, other 4xx, bad input) will fail the same way again
+    pub fn is_transient(&self) -> bool {
+        match self {
+            BotError::RateLimited { .. } | BotError::Timeout(_) => true,
+            BotError::Infobip(SdkError::ApiRequestError(api_error)) => api_error.status.is_server_error(),
+            BotError::Infobip(SdkError::Reqwest(e)) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+
+    // How long a rate-limited call was told to wait before trying again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            BotError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    // Infobip refused the API key
+    pub fn is_auth_error(&self) -> bool {
+        matches!(self, BotError::Infobip(SdkError::ApiRequestError(api_error)) if api_error.status.as_u16() == 401)
+    }
+
+    // An auth error that reading the key again didn't fix
+    pub fn into_auth_failed(self) -> BotError {
+        match self {
+            BotError::Infobip(e) => BotError::AuthFailed(e),
+            other => other,
+        }
+    }
+
+    // The messageId Infobip gave a rejected (4xx) request, naming what was wrong with it
+    pub fn infobip_error_id(&self) -> Option<&str> {
+        match self {
+            BotError::Infobip(SdkError::ApiRequestError(api_error)) if api_error.status.is_client_error() => {
+                api_error.details.request_error.service_exception.message_id.as_deref()
+            }
+            _ => None,
+        }
+    }
+
+    // Why a contact was rejected, worded for its sender: a parse error without the "could not
+    // parse contact" prefix
+    pub fn contact_problem(&self) -> String {
+        match self {
+            BotError::Parse(e) => e.to_string(),
+            e => e.to_string(),
+        }
+    }
+
+    // HTTP status to answer with when this error ends a request
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            BotError::Config(_) | BotError::Storage(_) | BotError::DrainTimeout(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            BotError::Parse(_) | BotError::MessageTooLong { .. } | BotError::FieldTooLong { .. } => {
+                StatusCode::BAD_REQUEST
+            }
+            BotError::RateLimited { .. } | BotError::Send(_) | BotError::CircuitOpen => StatusCode::SERVICE_UNAVAILABLE,
+            BotError::Infobip(_) | BotError::AuthFailed(_) => StatusCode::BAD_GATEWAY,
+            BotError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use infobip_sdk::api::{ApiError, ApiErrorDetails};
+
+    use super::*;
+
+    // An error response from Infobip as the SDK reports it
+    pub fn infobip_error(status: u16, message_id: &str) -> SdkError {
+        let details: ApiErrorDetails = serde_json::from_value(serde_json::json!({
+            "requestError": { "serviceException": { "messageId": message_id, "text": "refused" } }
+        }))
+        .unwrap();
+        SdkError::ApiRequestError(ApiError { details, status: reqwest::StatusCode::from_u16(status).unwrap() })
+    }
+
+    #[test]
+    fn parse_errors_convert() {
+        let e: BotError = ParseError::MissingPhone.into();
+        assert!(matches!(e, BotError::Parse(ParseError::MissingPhone)));
+        assert_eq!(e.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(e.contact_problem(), "a phone number is required");
+    }
+
+    #[test]
+    fn storage_errors_convert() {
+        let e: BotError = rusqlite::Error::InvalidQuery.into();
+        assert!(matches!(e, BotError::Storage(_)));
+        assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn infobip_errors_convert() {
+        let e = BotError::from(infobip_error(400, "BAD_REQUEST"));
+        assert!(matches!(e, BotError::Infobip(_)));
+        assert!(!e.is_transient());
+        assert_eq!(e.infobip_error_id(), Some("BAD_REQUEST"));
+
+        let e = BotError::from(infobip_error(503, "UNAVAILABLE"));
+        assert!(e.is_transient());
+        assert_eq!(e.infobip_error_id(), None);
+
+        let e = BotError::from(infobip_error(401, "UNAUTHORIZED"));
+        assert!(e.is_auth_error());
+        assert!(matches!(e.into_auth_failed(), BotError::AuthFailed(_)));
+    }
+
+    #[test]
+    fn too_many_requests_is_a_rate_limit() {
+        let e = BotError::from(infobip_error(429, "TOO_MANY_REQUESTS"));
+        assert!(matches!(e, BotError::RateLimited { retry_after: None, .. }));
+        assert!(e.is_transient());
+        assert_eq!(e.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,115 @@
+// Caps how many Infobip calls are outstanding at once, across all workers
+use infobip_sdk::model::whatsapp::{
+    SendContactRequestBody, SendDocumentRequestBody, SendImageRequestBody, SendLocationRequestBody, SendTemplateRequestBody,
+    SendTextRequestBody,
+};
+use tokio::sync::Semaphore;
+
+use crate::error::BotError;
+use crate::sender::MessageSender;
+
+// Each call holds a permit while it runs; without a limit calls go straight through. Wrapped
+// around the TimeoutSender, so a call that times out gives its permit back, and the wait for a
+// permit isn't counted against the timeout.
+pub struct InFlightLimit<S> {
+    inner: S,
+    permits: Option<Semaphore>,
+}
+
+impl<S: MessageSender> InFlightLimit<S> {
+    pub fn new(inner: S, max_in_flight: Option<usize>) -> Self {
+        InFlightLimit { inner, permits: max_in_flight.map(Semaphore::new) }
+    }
+
+    async fn limited(
+        &self,
+        send: impl Future<Output = Result<(), BotError>>,
+    ) -> Result<(), BotError> {
+        let _permit = match &self.permits {
+            Some(permits) => Some(permits.acquire().await.expect("the semaphore is never closed")),
+            None => None,
+        };
+        send.await
+    }
+}
+
+impl<S: MessageSender> MessageSender for InFlightLimit<S> {
+    async fn send_text(&self, request_body: SendTextRequestBody) -> Result<(), BotError> {
+        self.limited(self.inner.send_text(request_body)).await
+    }
+
+    async fn send_contact(&self, request_body: SendContactRequestBody) -> Result<(), BotError> {
+        self.limited(self.inner.send_contact(request_body)).await
+    }
+
+    async fn send_template(&self, request_body: SendTemplateRequestBody) -> Result<(), BotError> {
+        self.limited(self.inner.send_template(request_body)).await
+    }
+
+    async fn send_image(&self, request_body: SendImageRequestBody) -> Result<(), BotError> {
+        self.limited(self.inner.send_image(request_body)).await
+    }
+
+    async fn send_document(&self, request_body: SendDocumentRequestBody) -> Result<(), BotError> {
+        self.limited(self.inner.send_document(request_body)).await
+    }
+
+    async fn send_location(&self, request_body: SendLocationRequestBody) -> Result<(), BotError> {
+        self.limited(self.inner.send_location(request_body)).await
+    }
+
+    async fn check_sender(&self, sender: &str) -> Result<(), BotError> {
+        self.limited(self.inner.check_sender(sender)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use crate::sender::tests::{MockSender, text_body};
+    use crate::timeout::TimeoutSender;
+
+    #[tokio::test]
+    async fn no_more_than_the_limit_are_sent_at_once() {
+        let mock = MockSender::new();
+        mock.delay_by(Duration::from_millis(200));
+        let sender = Arc::new(InFlightLimit::new(mock, Some(2)));
+        for _ in 0..5 {
+            let sender = sender.clone();
+            tokio::spawn(async move { sender.send_text(text_body("+15551234567", "hello")).await });
+        }
+        // The mock notes a send when it starts, so only the permitted ones show up
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(sender.inner.sent().len(), 2);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(sender.inner.sent().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn a_send_that_times_out_gives_its_permit_back() {
+        let mock = MockSender::new();
+        mock.delay_by(Duration::from_secs(5));
+        let sender = InFlightLimit::new(TimeoutSender::new(mock, Duration::from_millis(50)), Some(1));
+        let started = Instant::now();
+        assert!(matches!(sender.send_text(text_body("+15551234567", "hello")).await, Err(BotError::Timeout(_))));
+        assert!(matches!(sender.send_text(text_body("+15551234567", "again")).await, Err(BotError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn without_a_limit_sends_go_straight_through() {
+        let mock = MockSender::new();
+        mock.delay_by(Duration::from_millis(200));
+        let sender = Arc::new(InFlightLimit::new(mock, None));
+        for _ in 0..5 {
+            let sender = sender.clone();
+            tokio::spawn(async move { sender.send_text(text_body("+15551234567", "hello")).await });
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(sender.inner.sent().len(), 5);
+    }
+}
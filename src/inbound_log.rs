@@ -0,0 +1,62 @@
+// Append-only SQLite log of inbound messages, for analysing trigger usage
+use std::sync::Mutex;
+
+use rusqlite::{Connection, params};
+
+use crate::WhatsAppMessage;
+use crate::error::BotError;
+use crate::migrations;
+
+pub struct InboundLog {
+    conn: Mutex<Connection>,
+}
+
+impl InboundLog {
+    // Open (or create) the log in the database at `database_url`; a sqlite:// prefix is accepted
+    pub fn open(database_url: &str) -> Result<Self, BotError> {
+        let conn = migrations::open(database_url)?;
+        Ok(InboundLog { conn: Mutex::new(conn) })
+    }
+
+    // Record a message and the trigger word it matched, if any
+    pub fn record(&self, message: &WhatsAppMessage, trigger_matched: Option<&str>) -> Result<(), BotError> {
+        let conn = self.conn.lock().expect("inbound log lock poisoned");
+        conn.execute(
+            "INSERT INTO inbound_log (sender, message_id, text, trigger_matched) VALUES (?1, ?2, ?3, ?4)",
+            params![message.from, message.message_id, message.text, trigger_matched],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::migrations::tests::TempDatabase;
+    use crate::tests::text_message;
+
+    #[test]
+    fn a_recorded_message_is_one_row() {
+        let database = TempDatabase::new();
+        let log = InboundLog::open(&database.url()).unwrap();
+        log.record(&text_message("m1", "addcontact Jane Smith +15551230000"), Some("addcontact")).unwrap();
+        log.record(&text_message("m2", "hello"), None).unwrap();
+
+        let conn = migrations::open(&database.url()).unwrap();
+        let rows: Vec<(String, Option<String>, Option<String>)> = conn
+            .prepare("SELECT message_id, text, trigger_matched FROM inbound_log ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("m1".to_string(), Some("addcontact Jane Smith +15551230000".to_string()), Some("addcontact".to_string())),
+                ("m2".to_string(), Some("hello".to_string()), None),
+            ]
+        );
+    }
+}
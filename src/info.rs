@@ -0,0 +1,76 @@
+// Build and configuration details served at GET /info
+use serde::Serialize;
+
+use crate::some_module::Config;
+use crate::trigger::TriggerMatchMode;
+use crate::{LogFormat, VCardVersion};
+
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub built_at: String,
+    pub config: ConfigSummary,
+}
+
+// The settings worth knowing when debugging a deployment. Fields are copied one by one so a new
+// secret added to Config can't end up here by accident.
+#[derive(Debug, Serialize)]
+pub struct ConfigSummary {
+    pub trigger_words: Vec<String>,
+    pub trigger_match_mode: TriggerMatchMode,
+    pub recipient_count: usize,
+    pub bind_address: String,
+    pub port: u16,
+    pub webhook_path: String,
+    pub tls: bool,
+    pub dry_run: bool,
+    pub debug_echo: bool,
+    pub vcard_version: VCardVersion,
+    pub send_as_text: bool,
+    pub reply_to_sender: bool,
+    pub template_name: Option<String>,
+    pub require_confirmation: bool,
+    pub guided_flow: bool,
+    pub worker_count: usize,
+    pub persist_queue: bool,
+    pub log_format: LogFormat,
+    pub signature_verification: bool,
+}
+
+impl BuildInfo {
+    pub fn new(config: &Config) -> Self {
+        let built_at = env!("BUILD_TIMESTAMP")
+            .parse()
+            .ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_default();
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("GIT_HASH"),
+            built_at,
+            config: ConfigSummary {
+                trigger_words: config.trigger_words.clone(),
+                trigger_match_mode: config.trigger_match_mode,
+                recipient_count: config.recipient_phone_numbers.len(),
+                bind_address: config.bind_address.clone(),
+                port: config.port,
+                webhook_path: config.webhook_path.clone(),
+                tls: config.tls_cert_path.is_some(),
+                dry_run: config.dry_run,
+                debug_echo: config.debug_echo,
+                vcard_version: config.vcard_version,
+                send_as_text: config.send_as_text,
+                reply_to_sender: config.reply_to_sender,
+                template_name: config.template_name.clone(),
+                require_confirmation: config.require_confirmation,
+                guided_flow: config.guided_flow,
+                worker_count: config.worker_count,
+                persist_queue: config.persist_queue,
+                log_format: config.log_format,
+                signature_verification: config.webhook_secret.is_some(),
+            },
+        }
+    }
+}
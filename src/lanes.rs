@@ -0,0 +1,117 @@
+// The message queue as two lanes. Messages someone is waiting on a reply to go in the priority
+// lane, bulk work (broadcasts, redeliveries, replays) in the normal one, and workers always
+// take from the priority lane first, so a long broadcast doesn't hold up a confirmation.
+use tokio::sync::mpsc;
+
+use crate::WhatsAppMessage;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lane {
+    Priority,
+    Normal,
+}
+
+#[derive(Clone)]
+pub struct LaneSender {
+    priority: mpsc::Sender<WhatsAppMessage>,
+    normal: mpsc::Sender<WhatsAppMessage>,
+}
+
+#[derive(Clone)]
+pub struct WeakLaneSender {
+    priority: mpsc::WeakSender<WhatsAppMessage>,
+    normal: mpsc::WeakSender<WhatsAppMessage>,
+}
+
+pub struct LaneReceiver {
+    priority: mpsc::Receiver<WhatsAppMessage>,
+    normal: mpsc::Receiver<WhatsAppMessage>,
+}
+
+// Each lane holds up to `capacity` messages, so a full broadcast backlog can't shut out replies
+pub fn lanes(capacity: usize) -> (LaneSender, LaneReceiver) {
+    let (priority_tx, priority_rx) = mpsc::channel(capacity);
+    let (normal_tx, normal_rx) = mpsc::channel(capacity);
+    (
+        LaneSender { priority: priority_tx, normal: normal_tx },
+        LaneReceiver { priority: priority_rx, normal: normal_rx },
+    )
+}
+
+impl LaneSender {
+    pub fn lane(&self, lane: Lane) -> &mpsc::Sender<WhatsAppMessage> {
+        match lane {
+            Lane::Priority => &self.priority,
+            Lane::Normal => &self.normal,
+        }
+    }
+
+    // Messages waiting in both lanes
+    pub fn depth(&self) -> usize {
+        [&self.priority, &self.normal]
+            .iter()
+            .map(|tx| tx.max_capacity() - tx.capacity())
+            .sum()
+    }
+
+    pub fn downgrade(&self) -> WeakLaneSender {
+        WeakLaneSender { priority: self.priority.downgrade(), normal: self.normal.downgrade() }
+    }
+}
+
+impl WeakLaneSender {
+    pub fn upgrade(&self) -> Option<LaneSender> {
+        Some(LaneSender { priority: self.priority.upgrade()?, normal: self.normal.upgrade()? })
+    }
+}
+
+impl LaneReceiver {
+    // A message that is already waiting, priority lane first
+    pub fn try_recv(&mut self) -> Option<WhatsAppMessage> {
+        self.priority.try_recv().or_else(|_| self.normal.try_recv()).ok()
+    }
+
+    // The next message, from the priority lane whenever it has one. None once both lanes are
+    // closed and drained.
+    pub async fn recv(&mut self) -> Option<WhatsAppMessage> {
+        tokio::select! {
+            biased;
+            Some(message) = self.priority.recv() => Some(message),
+            Some(message) = self.normal.recv() => Some(message),
+            else => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::text_message;
+
+    #[tokio::test]
+    async fn the_priority_lane_is_taken_first() {
+        let (tx, mut rx) = lanes(8);
+        for id in ["b1", "b2", "b3"] {
+            tx.lane(Lane::Normal).try_send(text_message(id, "broadcast")).unwrap();
+        }
+        tx.lane(Lane::Priority).try_send(text_message("p1", "YES")).unwrap();
+        assert_eq!(tx.depth(), 4);
+
+        let order: Vec<String> = [rx.recv().await, rx.try_recv(), rx.recv().await, rx.try_recv()]
+            .into_iter()
+            .map(|message| message.unwrap().message_id.unwrap())
+            .collect();
+        assert_eq!(order, ["p1", "b1", "b2", "b3"]);
+        assert_eq!(tx.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn recv_ends_once_both_lanes_are_closed_and_drained() {
+        let (tx, mut rx) = lanes(8);
+        tx.lane(Lane::Normal).try_send(text_message("b1", "broadcast")).unwrap();
+        drop(tx);
+        assert!(rx.recv().await.is_some());
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -1,187 +1,6614 @@
-use infobip_sdk::api::whatsapp::WhatsAppClient;
-use infobip_sdk::configuration::Configuration;
+use infobip_sdk::model::whatsapp::{
+    Contact, ContactAddress, ContactContent, ContactEmail, ContactName, ContactOrganization,
+    ContactPhone, ContactUrl, DocumentContent, FailoverMessage, ImageContent, LocationContent, PhoneType,
+    SendContactRequestBody, SendDocumentRequestBody, SendImageRequestBody, SendLocationRequestBody, SendTemplateRequestBody,
+    SendTextRequestBody, TemplateBodyContent, TemplateContent, TemplateData, TextContent,
+};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::env;
-use tokio::sync::mpsc;
-use warp::Filter;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::net::{IpAddr, SocketAddr};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+use tokio::sync::mpsc::error::TrySendError;
+use warp::filters::BoxedFilter;
+use warp::{Filter, Reply};
+use warp::http::HeaderMap;
+use warp::hyper::body::Bytes;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
 use dotenv::dotenv;
-use log::{error, info};
+use log::{error, info, warn};
+use clap::Parser;
+use broadcast::{next_fire, parse_schedule};
+use circuit_breaker::CircuitBreaker;
+use cli::Cli;
+use credentials::{KeySource, RefreshingClient, read_key_file};
+use confirmation::{ConfirmationStore, Resolution};
+use contact_builder::{ContactBuilder, Step};
+use command::{ParseError, is_valid_email, parse_contact_command, validate_e164};
+use dead_letter::{DeadLetterStore, ReplayError};
+use dedup::DedupCache;
+use directory::{ContactDirectory, Entry};
+use delivery::{DeliveryReports, DeliveryStatus};
+use error::BotError;
+use in_flight::InFlightLimit;
+use lanes::{Lane, LaneReceiver, LaneSender, WeakLaneSender, lanes};
+use inbound_log::InboundLog;
+use info::BuildInfo;
+use media::{Media, MediaKind};
+use metrics::Metrics;
+use pending::PendingQueue;
+use rate_limit::{KeyedRateLimiter, RateLimiter};
+use reload::ConfigHandle;
+use queue_store::{Delivery, QueueStatus, QueueStore};
+use send_cap::{SendCounter, current_day};
+use sender::MessageSender;
+use sender_router::{SenderRouter, SenderRouting};
+use session::{SESSION_WINDOW, SessionTracker};
+use settings::Settings;
+use split::split_message;
+use suppression::SuppressionList;
+use timeout::TimeoutSender;
+use trigger::{TriggerMatchMode, compile_patterns, find_trigger, strip_trigger};
+use telemetry::{otlp_tracer, remote_context};
+use template::{MESSAGE_PLACEHOLDERS, NO_MATCH_PLACEHOLDERS, TEMPLATE_PLACEHOLDERS, render_template, validate_template};
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+type HmacSha256 = Hmac<Sha256>;
+
+mod broadcast;
+mod circuit_breaker;
+mod cli;
+mod credentials;
+mod command;
+mod confirmation;
+mod contact_builder;
+mod dead_letter;
+mod dedup;
+mod directory;
+mod delivery;
+mod error;
+mod in_flight;
+mod lanes;
+mod inbound_log;
+mod info;
+mod media;
+mod metrics;
+mod migrations;
+mod pending;
+mod rate_limit;
+mod qr;
+mod redact;
+mod reload;
+mod queue_store;
+mod send_cap;
+mod sender;
+mod sender_router;
+mod session;
+mod settings;
+mod suppression;
+mod split;
+mod telemetry;
+mod template;
+mod timeout;
+mod trigger;
 
 // This is the configuration struct for environment variables
 mod some_module{
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
+    use super::{LogFormat, LogLevel, VCardVersion, WatchdogAction};
+    use crate::sender_router::{SenderRouter, SenderRouting};
+    use crate::trigger::TriggerMatchMode;
 
-    #[derive(Debug, Deserialize, Clone)]
+    // Serialize is only used to see which settings a reload changed
+    #[derive(Debug, Deserialize, Serialize, Clone)]
     pub struct Config{
-        pub infobip_api_key: String,
+        // Set directly, or read from infobip_api_key_file (a mounted secret, say) at startup and
+        // again on every credential refresh
+        pub infobip_api_key: Option<String>,
+        pub infobip_api_key_file: Option<String>,
         pub infobip_base_url: String,
         pub whatsapp_phone_number_id: String,
-        pub trigger_word: String,
-        pub recipient_phone_number: String,
+        // More sender numbers to send from, after whatsapp_phone_number_id, and how to choose
+        pub sender_numbers: Vec<String>,
+        pub sender_routing: SenderRouting,
+        pub sender_routes: Vec<String>,
+        #[serde(skip)]
+        pub sender_router: SenderRouter,
+        pub trigger_words: Vec<String>,
+        pub trigger_match_mode: TriggerMatchMode,
+        // trigger_words compiled for TRIGGER_MATCH_MODE
+        #[serde(skip)]
+        pub trigger_patterns: Vec<regex::Regex>,
+        // Entries dropped from TRIGGER_WORDS as repeats of an earlier one, to warn about
+        #[serde(skip)]
+        pub duplicate_trigger_words: Vec<String>,
+        pub recipient_phone_numbers: Vec<String>,
+        // Country code, without the '+', for numbers in contact commands dialled nationally
+        pub default_country_code: Option<String>,
+        pub vcard_version: VCardVersion,
+        pub send_as_text: bool,
+        pub max_retries: u32,
+        pub base_backoff_ms: u64,
+        // Longest Retry-After from a 429 that is waited out; longer ones are cut to this
+        pub max_retry_after_secs: u64,
+        pub webhook_secret: Option<String>,
+        pub webhook_signature_header: String,
+        // Replay protection: with a skew set, requests must carry a timestamp within that many
+        // seconds of our clock, and the signature covers "{timestamp}.{body}"
+        pub webhook_max_skew_secs: Option<u64>,
+        pub webhook_timestamp_header: String,
+        pub bind_address: String,
+        pub port: u16,
+        pub verify_token: Option<String>,
+        // How long shutdown waits for the queue to drain before dead-lettering what's left
+        pub drain_timeout_secs: u64,
+        pub reply_to_sender: bool,
+        pub rate_per_second: f64,
+        pub burst_size: u32,
+        pub per_recipient_rate_per_minute: f64,
+        pub per_recipient_burst: u32,
+        pub per_recipient_idle_ttl_secs: u64,
+        pub persist_queue: bool,
+        pub database_url: String,
+        pub dedup_window_secs: u64,
+        pub dedup_capacity: usize,
+        pub message_template: String,
+        pub reply_on_no_match: bool,
+        pub no_match_message: String,
+        // Line added under text vCard messages, e.g. "— Sent by AcmeBot"
+        pub bot_signature: Option<String>,
+        // Text sent just before a native contact card, which has no room for one of its own
+        pub contact_caption: Option<String>,
+        // Sent as soon as a trigger word matches, e.g. "Got it, working on that…"
+        pub ack_message: Option<String>,
+        pub log_format: LogFormat,
+        pub log_level: Option<LogLevel>,
+        // OTLP/HTTP traces endpoint; spans are only exported when set
+        pub otlp_endpoint: Option<String>,
+        pub dry_run: bool,
+        pub allowed_senders: Vec<String>,
+        pub blocked_senders: Vec<String>,
+        pub breaker_failure_threshold: u32,
+        pub breaker_cooldown_secs: u64,
+        pub template_name: Option<String>,
+        pub template_language: String,
+        pub template_placeholders: Vec<String>,
+        pub worker_count: usize,
+        pub log_inbound: bool,
+        pub require_confirmation: bool,
+        pub confirmation_ttl_secs: u64,
+        pub max_body_bytes: u64,
+        pub webhook_path: String,
+        pub tls_cert_path: Option<String>,
+        pub tls_key_path: Option<String>,
+        pub retry_on_failed_delivery: bool,
+        pub guided_flow: bool,
+        pub guided_flow_ttl_secs: u64,
+        pub stop_keywords: Vec<String>,
+        pub start_keywords: Vec<String>,
+        pub send_timeout_secs: u64,
+        // Infobip calls allowed to be outstanding at once, whatever the worker count and rate
+        pub max_in_flight: Option<usize>,
+        // Ask Infobip at startup whether each sender number is usable; strict fails startup
+        pub startup_checks: bool,
+        // On a 401, read the key again and retry once with it. Without a key file only the config
+        // file is read again; the environment is fixed at startup.
+        pub refresh_credentials_on_auth_error: bool,
+        pub strict_startup_checks: bool,
+        pub admin_token: Option<String>,
+        // Browser origins allowed to call the admin routes ("*" for any); no CORS when empty
+        pub cors_allowed_origins: Vec<String>,
+        pub cors_allowed_methods: Vec<String>,
+        pub cors_allowed_headers: Vec<String>,
+        // Messages queued but none processed for this long means the workers are stuck
+        pub watchdog_interval_secs: Option<u64>,
+        pub watchdog_action: WatchdogAction,
+        // Mask phone numbers and message text in the logs; on by default with APP_ENV=production
+        pub redact_pii: bool,
+        pub contact_dedup_window_secs: u64,
+        pub contacts_csv: Option<String>,
+        pub queue_capacity: usize,
+        pub max_message_chars: usize,
+        // Longest first or last name, note, and any other contact field, counted after trimming
+        pub max_name_chars: usize,
+        pub max_note_chars: usize,
+        pub max_field_chars: usize,
+        // Answer webhooks with what we parsed, for integration debugging only
+        pub debug_echo: bool,
+        // Cron expression (UTC) for sending broadcast_contact to every recipient
+        pub broadcast_schedule: Option<String>,
+        // Contact command text, or a directory alias
+        pub broadcast_contact: Option<String>,
+        // How often to retry opening the databases before giving up at startup
+        pub startup_retry_attempts: u32,
+        pub startup_retry_delay_secs: u64,
+        // Image or document sent before the contact, with an optional caption
+        pub media_url: Option<String>,
+        pub media_caption: Option<String>,
+        // Location sent back when a message is one of location_keywords
+        pub location_latitude: Option<f64>,
+        pub location_longitude: Option<f64>,
+        pub location_name: Option<String>,
+        pub location_address: Option<String>,
+        pub location_keywords: Vec<String>,
+        // vCards one sender may trigger per day, unlimited when unset. Days start at
+        // daily_cap_reset_hour UTC.
+        pub max_sends_per_sender_per_day: Option<u32>,
+        pub daily_cap_reset_hour: u32,
+        // Infobip error ids meaning the recipient can't take a contact card; those sends are
+        // retried as a text vCard
+        pub contact_fallback_errors: Vec<String>,
+        // Random pause between the sends of one message, so a fan-out doesn't go out back to back
+        pub send_jitter_ms_min: u64,
+        pub send_jitter_ms_max: u64,
+        // Pace each broadcast evenly over this long instead
+        pub broadcast_spread_minutes: Option<u64>,
     }
 }
 
 // Incoming wozap payloaddd!
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 struct WhatsAppMessage {
     from: String,
     text: Option<String>,
+    // Provider's messageId, used to spot redeliveries
+    #[serde(default)]
+    message_id: Option<String>,
+    // Set when the message is a shared location rather than text
+    #[serde(default)]
+    location: Option<InboundLocation>,
+    // messageId of the message this one replies to, when the sender quoted one
+    #[serde(default)]
+    quoted_message_id: Option<String>,
+    // Ties together the log lines for this message from webhook to send: the provider's
+    // messageId, or a generated UUID when there is none
+    #[serde(default)]
+    correlation_id: String,
+    // Row id in the persisted queue, when persistence is enabled
+    #[serde(skip)]
+    queue_id: Option<i64>,
+    // Set when a failed delivery report sends the vCard to one recipient again
+    #[serde(skip)]
+    redelivery: Option<Redelivery>,
+    // Queued by the broadcast schedule rather than received
+    #[serde(default)]
+    broadcast: bool,
+    // The ack to send back to the sender before the message is handled. Not persisted: after a
+    // restart only the contact is worth sending.
+    #[serde(skip)]
+    ack: Option<String>,
+    // Span context of where the message was queued, so processing joins the same trace
+    #[serde(skip)]
+    trace_context: Option<opentelemetry::Context>,
+    // Position in the pending queue view, 0 when not queued through it
+    #[serde(skip)]
+    pending_seq: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Redelivery {
+    // Row of the original message in the persisted queue
+    queue_id: i64,
+    recipient: String,
+    attempt: u32,
+}
+
+// Inbound webhook body as delivered by Infobip, one entry in results per message
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InboundWebhook {
+    results: Vec<InboundResult>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InboundResult {
+    from: String,
+    message_id: String,
+    message: InboundMessage,
+    #[serde(default)]
+    contact: Option<InboundContact>,
+    #[serde(default)]
+    context: Option<InboundContext>,
+}
+
+// Message content keyed by its type; anything we don't handle yet lands in Unsupported
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+enum InboundMessage {
+    Text { text: String },
+    Location(InboundLocation),
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Deserialize)]
+struct InboundContact {
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct InboundLocation {
+    latitude: f64,
+    longitude: f64,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    address: Option<String>,
+}
+
+// Present when the sender swiped to reply to an earlier message
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InboundContext {
+    #[serde(default, alias = "quoted_message_id")]
+    quoted_message_id: Option<String>,
+}
+
+impl From<InboundResult> for WhatsAppMessage {
+    fn from(result: InboundResult) -> Self {
+        let correlation_id = if result.message_id.is_empty() {
+            uuid::Uuid::new_v4().to_string()
+        } else {
+            result.message_id.clone()
+        };
+        WhatsAppMessage {
+            from: result.from,
+            correlation_id,
+            message_id: Some(result.message_id),
+            quoted_message_id: result.context.and_then(|context| context.quoted_message_id),
+            text: match &result.message {
+                // Phones differ on whether "é" arrives as one code point or as "e" plus an
+                // accent; composing it lets triggers and directory aliases compare equal
+                InboundMessage::Text { text } => Some(text.nfc().collect()),
+                _ => None,
+            },
+            location: match result.message {
+                InboundMessage::Location(location) => Some(location),
+                _ => None,
+            },
+            queue_id: None,
+            redelivery: None,
+            broadcast: false,
+            ack: None,
+            trace_context: None,
+            pending_seq: 0,
+        }
+    }
 }
 
 // This is the VCard struct for the contact info
-#[derive(Debug,Serialize)]
+#[derive(Debug, Serialize, Clone)]
 struct VCard{
     first_name: String,
     last_name: String,
-    phone_number: String,
+    // In the order they should be listed; the first is the primary number
+    phone_numbers: Vec<PhoneNumber>,
+    email: Option<String>,
+    organization: Option<String>,
+    address: Option<Address>,
+    // Job title or role
+    title: Option<String>,
+    url: Option<String>,
+    // Free text, may span several lines
+    note: Option<String>,
+    // Tags such as "Support" for grouping in the address book
+    categories: Vec<String>,
+}
+
+impl VCard {
+    // At least one phone number is required
+    fn with_phone_numbers(
+        first_name: String,
+        last_name: String,
+        phone_numbers: Vec<PhoneNumber>,
+    ) -> Result<Self, ParseError> {
+        if phone_numbers.is_empty() {
+            return Err(ParseError::MissingPhone);
+        }
+        Ok(VCard {
+            first_name,
+            last_name,
+            phone_numbers,
+            email: None,
+            organization: None,
+            address: None,
+            title: None,
+            url: None,
+            note: None,
+            categories: Vec::new(),
+        })
+    }
+
+    // Identity of the contact for spotting repeats: names with whitespace collapsed and case
+    // folded, plus the already E.164-normalized numbers and the email
+    fn normalized_key(&self) -> String {
+        let fold = |value: &str| value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        let numbers: Vec<&str> = self.phone_numbers.iter().map(|phone| phone.number.as_str()).collect();
+        format!(
+            "{}\n{}\n{}\n{}",
+            fold(&self.first_name),
+            fold(&self.last_name),
+            numbers.join(","),
+            self.email.as_deref().map(fold).unwrap_or_default()
+        )
+    }
+
+    // Number used in message templates
+    fn primary_phone(&self) -> &str {
+        self.phone_numbers.first().map_or("", |phone| phone.number.as_str())
+    }
 }
 
-//Initializing the logging
-fn init_logging() {
-    env_logger::init();
+// Longest value each contact field may have, so an oversized one can't bloat the vCard
+#[derive(Debug, Clone, Copy)]
+struct FieldLimits {
+    name: usize,
+    note: usize,
+    other: usize,
 }
 
-//Load configuration from environment variables
-fn load_config() -> some_module::Config{
-    some_module::Config{
-        infobip_api_key: env::var("INFOBIP_API_KEY").expect("INFOBIP_API_KEY must be set"),
-        infobip_base_url: env::var("INFOBIP_BASE_URL").expect("INFOBIP_BASE_URL must be set"),
-        whatsapp_phone_number_id: env::var("WHATSAPP_PHONE_NUMBER_ID").expect("WHATSAPP_PHONE_NUMBER_ID must be set"),
-        trigger_word: env::var("TRIGGER_WORD").unwrap_or("addcontact".to_string()),
-        recipient_phone_number: env::var("RECIPIENT_PHONE_NUMBER").expect("RECIPIENT_PHONE_NUMBER must be set"),
+impl FieldLimits {
+    fn from_config(config: &some_module::Config) -> Self {
+        FieldLimits { name: config.max_name_chars, note: config.max_note_chars, other: config.max_field_chars }
+    }
+
+    // The first field over its limit, if any. Values are measured trimmed.
+    fn check(&self, contact: &VCard) -> Result<(), BotError> {
+        let mut fields: Vec<(&'static str, &str, usize)> = vec![
+            ("first name", &contact.first_name, self.name),
+            ("last name", &contact.last_name, self.name),
+            ("email", contact.email.as_deref().unwrap_or_default(), self.other),
+            ("organization", contact.organization.as_deref().unwrap_or_default(), self.other),
+            ("title", contact.title.as_deref().unwrap_or_default(), self.other),
+            ("url", contact.url.as_deref().unwrap_or_default(), self.other),
+            ("note", contact.note.as_deref().unwrap_or_default(), self.note),
+        ];
+        fields.extend(contact.categories.iter().map(|category| ("category", category.as_str(), self.other)));
+        if let Some(address) = &contact.address {
+            fields.extend([
+                ("street", address.street.as_str(), self.other),
+                ("city", address.city.as_str(), self.other),
+                ("region", address.region.as_str(), self.other),
+                ("postal code", address.postal_code.as_str(), self.other),
+                ("country", address.country.as_str(), self.other),
+            ]);
+        }
+        for (field, value, max) in fields {
+            let length = value.trim().chars().count();
+            if length > max {
+                return Err(BotError::FieldTooLong { field, length, max });
+            }
+        }
+        Ok(())
     }
 }
 
-//Generate the vCard content
-fn generate_vcard(contact: &VCard) -> String{
-    format!(
-        "BEGIN:VCARD\nVERSION:3.0\nN:{};{}\nTEL;TYPE=CELL:{}\nEND:VCARD",
-        contact.last_name, contact.first_name, contact.phone_number
-    )
+#[derive(Debug, Serialize, Clone)]
+struct PhoneNumber {
+    number: String,
+    kind: PhoneKind,
 }
 
-// Define a local Message and Content struct for WhatsApp sending
-#[derive(Debug, Serialize, Default)]
-struct Message {
-    from: String,
-    to: String,
-    content: Content,
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+enum PhoneKind {
+    Cell,
+    Work,
+    Home,
+}
+
+impl PhoneKind {
+    // TEL TYPE parameter; 4.0 registers the values in lower case
+    fn vcard_type(self, version: VCardVersion) -> &'static str {
+        match (self, version) {
+            (PhoneKind::Cell, VCardVersion::V3_0) => "CELL",
+            (PhoneKind::Work, VCardVersion::V3_0) => "WORK",
+            (PhoneKind::Home, VCardVersion::V3_0) => "HOME",
+            (PhoneKind::Cell, VCardVersion::V4_0) => "cell",
+            (PhoneKind::Work, VCardVersion::V4_0) => "work",
+            (PhoneKind::Home, VCardVersion::V4_0) => "home",
+        }
+    }
+
+    fn infobip_type(self) -> PhoneType {
+        match self {
+            PhoneKind::Cell => PhoneType::Cell,
+            PhoneKind::Work => PhoneType::Work,
+            PhoneKind::Home => PhoneType::Home,
+        }
+    }
 }
 
-#[derive(Debug, Serialize)]
-#[serde(tag = "type", content = "text")]
-enum Content {
-    #[serde(rename = "text")]
-    Text(String),
+// vCard format version to emit
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+enum VCardVersion {
+    #[serde(rename = "3.0")]
+    V3_0,
+    #[serde(rename = "4.0")]
+    V4_0,
 }
 
-impl Default for Content {
-    fn default() -> Self {
-        Content::Text(String::new())
+impl std::str::FromStr for VCardVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "3.0" | "3" => Ok(VCardVersion::V3_0),
+            "4.0" | "4" => Ok(VCardVersion::V4_0),
+            other => Err(format!("unsupported vCard version '{}', expected 3.0 or 4.0", other)),
+        }
     }
 }
 
-async fn send_vcard(client: &WhatsAppClient, config: &some_module::Config, vcard: &str, recipient: &str) -> Result<(), Box<dyn std::error::Error>>{
-    // the sdk might not provide native support for certain functionalites
-    // Refer to official crate for more clarification
+// Postal address of a contact, rendered as the vCard ADR property
+#[derive(Debug, Serialize, Clone)]
+struct Address {
+    street: String,
+    city: String,
+    region: String,
+    postal_code: String,
+    country: String,
+}
 
-    let message = format!("Here is the contact vCard:\n{}", vcard);
+// How log lines are written: human-readable text or one JSON object per line
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum LogFormat {
+    Text,
+    Json,
+}
 
-    use infobip_sdk::model::whatsapp::SendTextRequestBody;
+impl std::str::FromStr for LogFormat {
+    type Err = String;
 
-    use infobip_sdk::model::whatsapp::TextContent;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unsupported log format '{}', expected text or json", other)),
+        }
+    }
+}
 
-    let request_body = SendTextRequestBody {
-        from: config.whatsapp_phone_number_id.clone(),
-        to: recipient.to_string(),
-        content: TextContent {
-            text: message.clone(),
-            preview_url: Some(false),
-        },
-        ..Default::default()
+// Verbosity from LOG_LEVEL, for deployments where RUST_LOG is awkward to set
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "trace" => Ok(LogLevel::Trace),
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            other => Err(format!(
+                "unsupported log level '{}', expected trace, debug, info, warn or error",
+                other
+            )),
+        }
+    }
+}
+
+// What the watchdog does about workers that stopped taking messages
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum WatchdogAction {
+    // Log it and report not ready, so the orchestrator can restart the service
+    Report,
+    // Also replace the worker tasks with fresh ones
+    Respawn,
+}
+
+impl std::str::FromStr for WatchdogAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "report" => Ok(WatchdogAction::Report),
+            "respawn" => Ok(WatchdogAction::Respawn),
+            other => Err(format!("unsupported watchdog action '{}', expected report or respawn", other)),
+        }
+    }
+}
+
+// RUST_LOG, when set, wins over LOG_LEVEL. Lines carry the fields of the span they were logged
+// in, as discrete keys in JSON mode. With an OTLP endpoint our spans are exported as well; shut
+// the returned provider down before exit to flush them.
+fn init_logging(format: LogFormat, level: Option<LogLevel>, otlp_endpoint: Option<&str>) -> Option<SdkTracerProvider> {
+    let filter = match level {
+        Some(level) if std::env::var_os("RUST_LOG").is_none() => tracing_subscriber::EnvFilter::new(level.as_str()),
+        _ => tracing_subscriber::EnvFilter::from_default_env(),
+    };
+    let (otel, provider, otlp_error) = match otlp_endpoint.map(otlp_tracer) {
+        Some(Ok((tracer, provider))) => {
+            let targets = Targets::new().with_target(env!("CARGO_CRATE_NAME"), tracing::Level::INFO);
+            (Some(tracing_opentelemetry::layer().with_tracer(tracer).with_filter(targets)), Some(provider), None)
+        }
+        Some(Err(e)) => (None, None, Some(e)),
+        None => (None, None, None),
     };
+    // stderr, and colour only on a terminal, as env_logger did before
+    let log_layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stderr()));
+    let registry = tracing_subscriber::registry().with(otel);
+    match format {
+        LogFormat::Text => registry.with(log_layer.with_filter(filter)).init(),
+        LogFormat::Json => registry.with(log_layer.json().flatten_event(true).with_filter(filter)).init(),
+    }
+    if let Some(e) = otlp_error {
+        warn!("{}, spans will not be exported", e);
+    }
+    provider
+}
 
-    match client
-        .send_text(request_body)
-        .await
+// Text sent along with the vCard when sending as text
+const DEFAULT_MESSAGE_TEMPLATE: &str = "Here is the contact vCard:\n{vcard}";
+
+// Reply to a text without a trigger word, when REPLY_ON_NO_MATCH is on
+const DEFAULT_NO_MATCH_MESSAGE: &str = "Sorry, I didn't understand that. Try '{trigger}' followed by a name and phone number.";
+
+// Values for the approved template's body placeholders, in order
+const DEFAULT_TEMPLATE_PLACEHOLDERS: &str = "{first_name} {last_name},{phone_number}";
+
+// Collect the setting sources, including the config file from --config-file or CONFIG_FILE
+fn load_settings(cli: &Cli) -> Result<Settings, BotError> {
+    let settings = Settings::new(cli.overrides());
+    let config_file = cli
+        .config_file
+        .clone()
+        .or_else(|| settings.get("CONFIG_FILE").map(PathBuf::from));
+    match config_file {
+        Some(path) => settings.with_file(&path),
+        None => Ok(settings),
+    }
+}
+
+// Load configuration from the command line, environment variables and the config file (in that order of precedence)
+fn load_config(settings: &Settings) -> Result<some_module::Config, BotError>{
+    let trigger_match_mode = settings.parse("TRIGGER_MATCH_MODE", TriggerMatchMode::Contains)?;
+    let (trigger_words, duplicate_trigger_words) = parse_trigger_words(
+        &settings
+            .get("TRIGGER_WORDS")
+            .or_else(|| settings.get("TRIGGER_WORD"))
+            .unwrap_or("addcontact".to_string()),
+        trigger_match_mode,
+    );
+    let trigger_patterns = compile_patterns(&trigger_words, trigger_match_mode)?;
+    let production = settings.get("APP_ENV").is_some_and(|env| env.trim().eq_ignore_ascii_case("production"));
+    let whatsapp_phone_number_id = settings.required("WHATSAPP_PHONE_NUMBER_ID")?;
+    let sender_numbers = parse_list(&settings.get("SENDER_NUMBERS").unwrap_or_default());
+    let sender_routing = settings.parse("SENDER_ROUTING", SenderRouting::Static)?;
+    let sender_routes = parse_list(&settings.get("SENDER_ROUTES").unwrap_or_default());
+    let mut senders = vec![whatsapp_phone_number_id.clone()];
+    senders.extend(sender_numbers.iter().filter(|number| **number != whatsapp_phone_number_id).cloned());
+    let sender_router = SenderRouter::new(senders, sender_routing, &sender_routes)?;
+    let infobip_api_key = settings.get("INFOBIP_API_KEY").filter(|key| !key.trim().is_empty());
+    let infobip_api_key_file = settings.get("INFOBIP_API_KEY_FILE").filter(|path| !path.trim().is_empty());
+    match (&infobip_api_key, &infobip_api_key_file) {
+        (None, None) => return Err(BotError::Config("INFOBIP_API_KEY or INFOBIP_API_KEY_FILE must be set".to_string())),
+        (Some(_), Some(_)) => {
+            return Err(BotError::Config("set only one of INFOBIP_API_KEY and INFOBIP_API_KEY_FILE".to_string()));
+        }
+        _ => {}
+    }
+    let config = some_module::Config{
+        infobip_api_key,
+        infobip_api_key_file,
+        infobip_base_url: settings.required("INFOBIP_BASE_URL")?,
+        whatsapp_phone_number_id,
+        sender_numbers,
+        sender_routing,
+        sender_routes,
+        sender_router,
+        trigger_words,
+        duplicate_trigger_words,
+        trigger_match_mode,
+        trigger_patterns,
+        recipient_phone_numbers: parse_recipients(&settings.required("RECIPIENT_PHONE_NUMBER")?)?,
+        default_country_code: settings
+            .get("DEFAULT_COUNTRY_CODE")
+            .map(|code| code.trim().trim_start_matches('+').to_string())
+            .filter(|code| !code.is_empty()),
+        vcard_version: settings.parse("VCARD_VERSION", VCardVersion::V3_0)?,
+        send_as_text: settings.flag("SEND_AS_TEXT", false),
+        max_retries: settings.parse("MAX_RETRIES", 3)?,
+        base_backoff_ms: settings.parse("BASE_BACKOFF_MS", 500)?,
+        max_retry_after_secs: settings.parse("MAX_RETRY_AFTER_SECS", 60)?,
+        webhook_secret: settings.get("WEBHOOK_SECRET").filter(|s| !s.is_empty()),
+        webhook_signature_header: settings.get("WEBHOOK_SIGNATURE_HEADER").unwrap_or("X-Hub-Signature-256".to_string()),
+        webhook_max_skew_secs: settings.parse_optional("WEBHOOK_MAX_SKEW_SECS")?,
+        webhook_timestamp_header: settings.get("WEBHOOK_TIMESTAMP_HEADER").unwrap_or("X-Timestamp".to_string()),
+        bind_address: settings.get("BIND_ADDRESS").unwrap_or("0.0.0.0".to_string()),
+        port: settings.parse("PORT", 8080)?,
+        verify_token: settings.get("VERIFY_TOKEN").filter(|s| !s.is_empty()),
+        // SHUTDOWN_TIMEOUT_SECS is the name this setting had before
+        drain_timeout_secs: settings.parse("DRAIN_TIMEOUT_SECS", settings.parse("SHUTDOWN_TIMEOUT_SECS", 30)?)?,
+        reply_to_sender: settings.flag("REPLY_TO_SENDER", false),
+        rate_per_second: settings.parse("RATE_PER_SECOND", 1.0)?,
+        burst_size: settings.parse("BURST_SIZE", 5)?,
+        per_recipient_rate_per_minute: settings.parse("PER_RECIPIENT_RATE_PER_MINUTE", 10.0)?,
+        per_recipient_burst: settings.parse("PER_RECIPIENT_BURST", 3)?,
+        per_recipient_idle_ttl_secs: settings.parse("PER_RECIPIENT_IDLE_TTL_SECS", 600)?,
+        persist_queue: settings.flag("PERSIST_QUEUE", false),
+        database_url: settings.get("DATABASE_URL").unwrap_or("tool.db".to_string()),
+        dedup_window_secs: settings.parse("DEDUP_WINDOW_SECS", 600)?,
+        dedup_capacity: settings.parse("DEDUP_CAPACITY", 10_000)?,
+        message_template: settings.get("MESSAGE_TEMPLATE").unwrap_or(DEFAULT_MESSAGE_TEMPLATE.to_string()),
+        reply_on_no_match: settings.flag("REPLY_ON_NO_MATCH", false),
+        no_match_message: settings
+            .get("NO_MATCH_MESSAGE")
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or(DEFAULT_NO_MATCH_MESSAGE.to_string()),
+        bot_signature: settings.get("BOT_SIGNATURE").filter(|s| !s.trim().is_empty()),
+        contact_caption: settings.get("CONTACT_CAPTION").filter(|s| !s.trim().is_empty()),
+        ack_message: settings.get("ACK_MESSAGE").filter(|s| !s.trim().is_empty()),
+        log_format: settings.parse("LOG_FORMAT", LogFormat::Text)?,
+        log_level: settings.parse_optional("LOG_LEVEL")?,
+        otlp_endpoint: settings.get("OTLP_ENDPOINT").filter(|s| !s.trim().is_empty()),
+        dry_run: settings.flag("DRY_RUN", false),
+        allowed_senders: parse_numbers("ALLOWED_SENDERS", &settings.get("ALLOWED_SENDERS").unwrap_or_default())?,
+        blocked_senders: parse_numbers("BLOCKED_SENDERS", &settings.get("BLOCKED_SENDERS").unwrap_or_default())?,
+        breaker_failure_threshold: settings.parse("BREAKER_FAILURE_THRESHOLD", 5)?,
+        breaker_cooldown_secs: settings.parse("BREAKER_COOLDOWN_SECS", 30)?,
+        template_name: settings.get("TEMPLATE_NAME").filter(|s| !s.is_empty()),
+        template_language: settings.get("TEMPLATE_LANGUAGE").unwrap_or("en".to_string()),
+        template_placeholders: settings
+            .get("TEMPLATE_PLACEHOLDERS")
+            .unwrap_or(DEFAULT_TEMPLATE_PLACEHOLDERS.to_string())
+            .split(',')
+            .map(|placeholder| placeholder.trim().to_string())
+            .collect(),
+        worker_count: settings.parse("WORKER_COUNT", 1)?,
+        log_inbound: settings.flag("LOG_INBOUND", false),
+        require_confirmation: settings.flag("REQUIRE_CONFIRMATION", false),
+        confirmation_ttl_secs: settings.parse("CONFIRMATION_TTL_SECS", 300)?,
+        max_body_bytes: settings.parse("MAX_BODY_BYTES", 64 * 1024)?,
+        webhook_path: settings.get("WEBHOOK_PATH").unwrap_or("webhook".to_string()),
+        tls_cert_path: settings.get("TLS_CERT_PATH").filter(|s| !s.is_empty()),
+        tls_key_path: settings.get("TLS_KEY_PATH").filter(|s| !s.is_empty()),
+        retry_on_failed_delivery: settings.flag("RETRY_ON_FAILED_DELIVERY", false),
+        guided_flow: settings.flag("GUIDED_FLOW", false),
+        guided_flow_ttl_secs: settings.parse("GUIDED_FLOW_TTL_SECS", 600)?,
+        stop_keywords: parse_keywords(&settings.get("STOP_KEYWORDS").unwrap_or("stop,unsubscribe".to_string())),
+        start_keywords: parse_keywords(&settings.get("START_KEYWORDS").unwrap_or("start".to_string())),
+        send_timeout_secs: settings.parse("SEND_TIMEOUT_SECS", 10)?,
+        max_in_flight: settings.parse_optional("MAX_IN_FLIGHT")?,
+        startup_checks: settings.flag("STARTUP_CHECKS", true),
+        refresh_credentials_on_auth_error: settings.flag("REFRESH_CREDENTIALS_ON_AUTH_ERROR", false),
+        strict_startup_checks: settings.flag("STRICT_STARTUP_CHECKS", false),
+        admin_token: settings.get("ADMIN_TOKEN").filter(|s| !s.is_empty()),
+        cors_allowed_origins: parse_list(&settings.get("CORS_ALLOWED_ORIGINS").unwrap_or_default()),
+        cors_allowed_methods: parse_list(&settings.get("CORS_ALLOWED_METHODS").unwrap_or("GET,POST,DELETE".to_string()))
+            .into_iter()
+            .map(|method| method.to_uppercase())
+            .collect(),
+        cors_allowed_headers: parse_keywords(
+            &settings.get("CORS_ALLOWED_HEADERS").unwrap_or("authorization,content-type".to_string()),
+        ),
+        watchdog_interval_secs: settings.parse_optional("WATCHDOG_INTERVAL_SECS")?,
+        watchdog_action: settings.parse("WATCHDOG_ACTION", WatchdogAction::Report)?,
+        redact_pii: settings.flag("REDACT_PII", production),
+        contact_dedup_window_secs: settings.parse("CONTACT_DEDUP_WINDOW_SECS", 3600)?,
+        contacts_csv: settings.get("CONTACTS_CSV").filter(|s| !s.is_empty()),
+        queue_capacity: settings.parse("QUEUE_CAPACITY", 100)?,
+        max_message_chars: settings.parse("MAX_MESSAGE_CHARS", 4096)?,
+        max_name_chars: settings.parse("MAX_NAME_CHARS", 100)?,
+        max_note_chars: settings.parse("MAX_NOTE_CHARS", 1000)?,
+        max_field_chars: settings.parse("MAX_FIELD_CHARS", 256)?,
+        debug_echo: settings.flag("DEBUG_ECHO", false),
+        broadcast_schedule: settings.get("BROADCAST_SCHEDULE").filter(|s| !s.trim().is_empty()),
+        broadcast_contact: settings.get("BROADCAST_CONTACT").filter(|s| !s.trim().is_empty()),
+        startup_retry_attempts: settings.parse("STARTUP_RETRY_ATTEMPTS", 5)?,
+        startup_retry_delay_secs: settings.parse("STARTUP_RETRY_DELAY_SECS", 2)?,
+        media_url: settings.get("MEDIA_URL").filter(|s| !s.trim().is_empty()),
+        media_caption: settings.get("MEDIA_CAPTION").filter(|s| !s.trim().is_empty()),
+        location_latitude: settings.parse_optional("LOCATION_LATITUDE")?,
+        location_longitude: settings.parse_optional("LOCATION_LONGITUDE")?,
+        location_name: settings.get("LOCATION_NAME").filter(|s| !s.trim().is_empty()),
+        location_address: settings.get("LOCATION_ADDRESS").filter(|s| !s.trim().is_empty()),
+        location_keywords: parse_keywords(&settings.get("LOCATION_KEYWORDS").unwrap_or("directions".to_string())),
+        max_sends_per_sender_per_day: settings.parse_optional("MAX_SENDS_PER_SENDER_PER_DAY")?,
+        daily_cap_reset_hour: settings.parse("DAILY_CAP_RESET_HOUR", 0)?,
+        send_jitter_ms_min: settings.parse("SEND_JITTER_MS_MIN", 0)?,
+        send_jitter_ms_max: settings.parse("SEND_JITTER_MS_MAX", 0)?,
+        broadcast_spread_minutes: settings.parse_optional("BROADCAST_SPREAD_MINUTES")?,
+        contact_fallback_errors: parse_list(
+            &settings.get("CONTACT_FALLBACK_ERRORS").unwrap_or("UNSUPPORTED_MESSAGE_TYPE".to_string()),
+        ),
+    };
+    validate_template("MESSAGE_TEMPLATE", &config.message_template, MESSAGE_PLACEHOLDERS)?;
+    validate_template("NO_MATCH_MESSAGE", &config.no_match_message, NO_MATCH_PLACEHOLDERS)?;
+    for placeholder in &config.template_placeholders {
+        validate_template("TEMPLATE_PLACEHOLDERS", placeholder, TEMPLATE_PLACEHOLDERS)?;
+    }
+    if !(config.rate_per_second > 0.0 && config.rate_per_second.is_finite()) {
+        return Err(BotError::Config("RATE_PER_SECOND must be a positive number".to_string()));
+    }
+    if !(config.per_recipient_rate_per_minute > 0.0 && config.per_recipient_rate_per_minute.is_finite()) {
+        return Err(BotError::Config("PER_RECIPIENT_RATE_PER_MINUTE must be a positive number".to_string()));
+    }
+    if config.webhook_path.split('/').all(|segment| segment.trim().is_empty()) {
+        return Err(BotError::Config("WEBHOOK_PATH must not be empty".to_string()));
+    }
+    match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            for (setting, path) in [("TLS_CERT_PATH", cert_path), ("TLS_KEY_PATH", key_path)] {
+                std::fs::File::open(path)
+                    .map_err(|e| BotError::Config(format!("{} '{}' can't be read: {}", setting, path, e)))?;
+            }
+        }
+        (Some(_), None) | (None, Some(_)) => {
+            return Err(BotError::Config("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string()));
+        }
+        (None, None) => {}
+    }
+    // Reports are matched to what we sent through the persisted queue
+    if config.retry_on_failed_delivery && !config.persist_queue {
+        return Err(BotError::Config("RETRY_ON_FAILED_DELIVERY requires PERSIST_QUEUE".to_string()));
+    }
+    if config.send_timeout_secs == 0 {
+        return Err(BotError::Config("SEND_TIMEOUT_SECS must be at least 1".to_string()));
+    }
+    if config.max_in_flight == Some(0) {
+        return Err(BotError::Config("MAX_IN_FLIGHT must be at least 1".to_string()));
+    }
+    match (config.location_latitude, config.location_longitude) {
+        (Some(latitude), Some(longitude)) => {
+            if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                return Err(BotError::Config(
+                    "LOCATION_LATITUDE must be within ±90 and LOCATION_LONGITUDE within ±180".to_string(),
+                ));
+            }
+        }
+        (None, None) if config.location_name.is_some() || config.location_address.is_some() => {
+            return Err(BotError::Config(
+                "LOCATION_NAME and LOCATION_ADDRESS need LOCATION_LATITUDE and LOCATION_LONGITUDE".to_string(),
+            ));
+        }
+        (None, None) => {}
+        _ => {
+            return Err(BotError::Config(
+                "LOCATION_LATITUDE and LOCATION_LONGITUDE must be set together".to_string(),
+            ));
+        }
+    }
+    if let Some(code) = &config.default_country_code
+        && !((1..=3).contains(&code.len()) && code.chars().all(|c| c.is_ascii_digit()) && !code.starts_with('0'))
     {
-        Ok(_) => {
-            info!("vCard sent successfully to {}", recipient);
-            Ok(())
+        return Err(BotError::Config(format!(
+            "DEFAULT_COUNTRY_CODE '{}' must be a country calling code like 44 or +1",
+            code
+        )));
+    }
+    match (&config.broadcast_schedule, &config.broadcast_contact) {
+        (Some(schedule), Some(contact)) => {
+            parse_schedule(schedule)?;
+            // A single word is an alias, looked up in the directory when the broadcast fires
+            let is_alias = contact.split_whitespace().count() == 1 && !contact.chars().any(|c| c.is_ascii_digit());
+            if is_alias && config.contacts_csv.is_none() {
+                return Err(BotError::Config(
+                    "BROADCAST_CONTACT is an alias, which needs CONTACTS_CSV".to_string(),
+                ));
+            }
+            if !is_alias {
+                parse_contact_command(contact, config.default_country_code.as_deref())
+                    .map_err(BotError::from)
+                    .and_then(|contact| FieldLimits::from_config(&config).check(&contact))
+                    .map_err(|e| BotError::Config(format!("BROADCAST_CONTACT is invalid: {}", e.contact_problem())))?;
+            }
         }
-        Err(e) => {
-            error!("Failed to send vCard: {}", e);
-            Err(Box::new(e))
+        (None, None) => {}
+        _ => {
+            return Err(BotError::Config(
+                "BROADCAST_SCHEDULE and BROADCAST_CONTACT must be set together".to_string(),
+            ));
+        }
+    }
+    // Release builds are what runs in production; the echo exists for local integration work
+    if config.debug_echo && !cfg!(debug_assertions) {
+        return Err(BotError::Config("DEBUG_ECHO is only available in debug builds".to_string()));
+    }
+    if config.max_message_chars == 0 {
+        return Err(BotError::Config("MAX_MESSAGE_CHARS must be at least 1".to_string()));
+    }
+    if config.max_name_chars == 0 || config.max_note_chars == 0 || config.max_field_chars == 0 {
+        return Err(BotError::Config(
+            "MAX_NAME_CHARS, MAX_NOTE_CHARS and MAX_FIELD_CHARS must be at least 1".to_string(),
+        ));
+    }
+    // It goes on a line of its own, so it has to fit in a message by itself
+    if let Some(signature) = &config.bot_signature
+        && signature.chars().count() > config.max_message_chars
+    {
+        return Err(BotError::Config(format!(
+            "BOT_SIGNATURE is {} characters, longer than MAX_MESSAGE_CHARS ({})",
+            signature.chars().count(),
+            config.max_message_chars
+        )));
+    }
+    if let Some(caption) = &config.contact_caption
+        && caption.chars().count() > config.max_message_chars
+    {
+        return Err(BotError::Config(format!(
+            "CONTACT_CAPTION is {} characters, longer than MAX_MESSAGE_CHARS ({})",
+            caption.chars().count(),
+            config.max_message_chars
+        )));
+    }
+    if let Some(ack) = &config.ack_message
+        && ack.chars().count() > config.max_message_chars
+    {
+        return Err(BotError::Config(format!(
+            "ACK_MESSAGE is {} characters, longer than MAX_MESSAGE_CHARS ({})",
+            ack.chars().count(),
+            config.max_message_chars
+        )));
+    }
+    // Whether it's reachable is checked once the service starts
+    if let Some(media_url) = &config.media_url {
+        let is_https = reqwest::Url::parse(media_url).is_ok_and(|url| url.scheme() == "https" && url.host().is_some());
+        if !is_https {
+            return Err(BotError::Config(format!("MEDIA_URL must be an https URL, got '{}'", media_url)));
+        }
+    }
+    if config.media_caption.as_ref().is_some_and(|caption| caption.chars().count() > 3000) {
+        return Err(BotError::Config("MEDIA_CAPTION must be at most 3000 characters".to_string()));
+    }
+    // The timestamp is only trustworthy when it's signed
+    if config.webhook_max_skew_secs.is_some() && config.webhook_secret.is_none() {
+        return Err(BotError::Config("WEBHOOK_MAX_SKEW_SECS requires WEBHOOK_SECRET".to_string()));
+    }
+    if config.webhook_max_skew_secs == Some(0) {
+        return Err(BotError::Config("WEBHOOK_MAX_SKEW_SECS must be at least 1".to_string()));
+    }
+    if config.send_jitter_ms_min > config.send_jitter_ms_max {
+        return Err(BotError::Config("SEND_JITTER_MS_MIN must not be above SEND_JITTER_MS_MAX".to_string()));
+    }
+    if config.broadcast_spread_minutes == Some(0) {
+        return Err(BotError::Config("BROADCAST_SPREAD_MINUTES must be at least 1".to_string()));
+    }
+    if config.max_sends_per_sender_per_day == Some(0) {
+        return Err(BotError::Config("MAX_SENDS_PER_SENDER_PER_DAY must be at least 1".to_string()));
+    }
+    if config.daily_cap_reset_hour > 23 {
+        return Err(BotError::Config("DAILY_CAP_RESET_HOUR must be between 0 and 23".to_string()));
+    }
+    if config.startup_retry_attempts == 0 {
+        return Err(BotError::Config("STARTUP_RETRY_ATTEMPTS must be at least 1".to_string()));
+    }
+    if config.queue_capacity == 0 {
+        return Err(BotError::Config("QUEUE_CAPACITY must be at least 1".to_string()));
+    }
+    if config.worker_count == 0 {
+        return Err(BotError::Config("WORKER_COUNT must be at least 1".to_string()));
+    }
+    if config.watchdog_interval_secs == Some(0) {
+        return Err(BotError::Config("WATCHDOG_INTERVAL_SECS must be at least 1".to_string()));
+    }
+    // warp panics on values it can't parse, so they're checked here
+    for origin in config.cors_allowed_origins.iter().filter(|origin| *origin != "*") {
+        let valid = reqwest::Url::parse(origin).is_ok_and(|url| {
+            matches!(url.scheme(), "http" | "https") && url.origin().ascii_serialization() == *origin
+        });
+        if !valid {
+            return Err(BotError::Config(format!(
+                "CORS_ALLOWED_ORIGINS entry '{}' must be an origin like https://admin.example.com",
+                origin
+            )));
         }
     }
+    if let Some(method) = config
+        .cors_allowed_methods
+        .iter()
+        .find(|method| warp::http::Method::from_bytes(method.as_bytes()).is_err())
+    {
+        return Err(BotError::Config(format!("CORS_ALLOWED_METHODS entry '{}' is not an HTTP method", method)));
+    }
+    if let Some(header) = config
+        .cors_allowed_headers
+        .iter()
+        .find(|header| warp::http::header::HeaderName::from_bytes(header.as_bytes()).is_err())
+    {
+        return Err(BotError::Config(format!("CORS_ALLOWED_HEADERS entry '{}' is not a header name", header)));
+    }
+    Ok(config)
 }
 
-// Webhook handler for incoming WhatsApp messages
-async fn handle_webhook(
-    message: WhatsAppMessage,
-    config: some_module::Config,
-    client: WhatsAppClient,
-) -> Result<impl warp::Reply, warp::Rejection>{
-    info!("Received message from {}: {:?}", message.from, message.text);
+// Split a comma-separated trigger list, trimming each word and dropping empty entries.
+// A single word without commas still works, and an empty list falls back to the default.
+// Triggers match case-insensitively, so a word differing from an earlier one only in case is
+// dropped too, and the first spelling is the one logged. Patterns are only dropped when exactly
+// repeated, since case matters in a regex ("\d" vs "\D"). Returns the words and the dropped ones.
+fn parse_trigger_words(raw: &str, mode: TriggerMatchMode) -> (Vec<String>, Vec<String>) {
+    let key = |word: &str| if mode == TriggerMatchMode::Regex { word.to_string() } else { word.to_lowercase() };
+    let mut seen = HashSet::new();
+    let (words, duplicates): (Vec<String>, Vec<String>) = raw
+        .split(',')
+        .map(|word| word.trim().to_string())
+        .filter(|word| !word.is_empty())
+        .partition(|word| seen.insert(key(word)));
+    if words.is_empty() {
+        (vec!["addcontact".to_string()], duplicates)
+    } else {
+        (words, duplicates)
+    }
+}
+
+fn warn_duplicate_triggers(config: &some_module::Config) {
+    if !config.duplicate_trigger_words.is_empty() {
+        warn!(
+            "Ignoring repeated trigger words in TRIGGER_WORDS: {}",
+            config.duplicate_trigger_words.join(", ")
+        );
+    }
+}
+
+// Split a comma-separated list, keeping the entries as written
+fn parse_list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
 
-    let trigger_word = config.trigger_word.to_lowercase();
-    let message_text = message.text.unwrap_or_default().to_lowercase();
+// Lowercased words of a comma-separated keyword list
+fn parse_keywords(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|word| word.trim().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
 
-    if message_text.contains(&trigger_word){
-        info!("Trigger word '{}' detected from {}", trigger_word, message.from);
+// Split a comma-separated list of phone numbers from `setting`, normalizing each to E.164
+fn parse_numbers(setting: &str, raw: &str) -> Result<Vec<String>, BotError> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|number| !number.is_empty())
+        .map(|number| validate_e164(number).map_err(|e| BotError::Config(format!("{}: {}", setting, e))))
+        .collect()
+}
 
-        //example contact
-        let contact = VCard{
-            first_name: "John".to_string(),
-            last_name: "Doe".to_string(),
-            phone_number: "1234567890".to_string(),
-        };
+// The recipient list must name at least one number
+fn parse_recipients(raw: &str) -> Result<Vec<String>, BotError> {
+    let recipients = parse_numbers("RECIPIENT_PHONE_NUMBER", raw)?;
+    if recipients.is_empty() {
+        return Err(BotError::Config("RECIPIENT_PHONE_NUMBER must list at least one number".to_string()));
+    }
+    Ok(recipients)
+}
+
+// Build the address the server listens on from the configured interface and port
+fn listen_addr(bind_address: &str, port: u16) -> Result<SocketAddr, BotError> {
+    let ip: IpAddr = bind_address
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .map_err(|e| BotError::Config(format!("BIND_ADDRESS '{}' is not a valid IP address: {}", bind_address, e)))?;
+    Ok(SocketAddr::new(ip, port))
+}
 
-        let vcard = generate_vcard(&contact);
-        if let Err(e) = send_vcard(&client, &config, &vcard, &config.recipient_phone_number).await{
-            error!("Error sending vCard: {}", e);
-            return Ok(warp::reply::with_status("Failed to send vCard", warp::http::StatusCode::INTERNAL_SERVER_ERROR));
+// Escape a property value per RFC 6350 section 3.4 so user input can't break the card structure
+fn escape_vcard_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\r' => {
+                // Treat CRLF as a single line break
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                escaped.push_str("\\n");
+            }
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
         }
     }
-    Ok(warp::reply::with_status("Message processed", warp::http::StatusCode::OK))
+    escaped
 }
 
-#[tokio::main]
-async fn main(){
-    init_logging();
-    dotenv().ok();
-    let config = load_config();
-    info!("Starting WhatsApp contact adder with trigger word: {}", config.trigger_word);
+// RFC 6350 section 3.2: content lines longer than 75 octets are folded onto continuation lines
+// starting with a space. Cuts fall between characters, never inside a UTF-8 sequence; a cluster
+// like a flag emoji may span a fold, but unfolding joins it back together.
+fn fold_vcard_line(line: &str) -> String {
+    const MAX_OCTETS: usize = 75;
+    let mut folded = String::with_capacity(line.len() + line.len() / MAX_OCTETS * 2);
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_OCTETS {
+            folded.push_str("\n ");
+            // The leading space counts towards the continuation line
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded
+}
 
-    //Initializes infobip wozap client
-let mut configuration = Configuration::from_env_api_key()
-    .expect("Failed to load Infobip configuration from environment");
-// Set the base URL directly
-configuration.base_url = config.infobip_base_url.clone();
-let client = WhatsAppClient::with_configuration(configuration);
+//Generate the vCard content
+fn generate_vcard(contact: &VCard, version: VCardVersion) -> String{
+    let mut vcard = match version {
+        VCardVersion::V3_0 => format!(
+            "BEGIN:VCARD\nVERSION:3.0\nN:{};{}\n",
+            escape_vcard_value(&contact.last_name),
+            escape_vcard_value(&contact.first_name)
+        ),
+        VCardVersion::V4_0 => format!(
+            "BEGIN:VCARD\nVERSION:4.0\nKIND:individual\nN:{};{}\n",
+            escape_vcard_value(&contact.last_name),
+            escape_vcard_value(&contact.first_name)
+        ),
+    };
+    for phone in &contact.phone_numbers {
+        let kind = phone.kind.vcard_type(version);
+        let number = escape_vcard_value(&phone.number);
+        match version {
+            VCardVersion::V3_0 => vcard.push_str(&format!("TEL;TYPE={}:{}\n", kind, number)),
+            // 4.0 carries the number as a tel: URI instead of free text
+            VCardVersion::V4_0 => vcard.push_str(&format!("TEL;VALUE=uri;TYPE={}:tel:{}\n", kind, number)),
+        }
+    }
+    if let Some(email) = &contact.email {
+        vcard.push_str(&format!("EMAIL:{}\n", escape_vcard_value(email)));
+    }
+    if let Some(organization) = &contact.organization {
+        vcard.push_str(&format!("ORG:{}\n", escape_vcard_value(organization)));
+    }
+    if let Some(address) = &contact.address {
+        // RFC 6350 order: PO box;extended;street;locality;region;postal code;country
+        vcard.push_str(&format!(
+            "ADR:;;{};{};{};{};{}\n",
+            escape_vcard_value(&address.street),
+            escape_vcard_value(&address.city),
+            escape_vcard_value(&address.region),
+            escape_vcard_value(&address.postal_code),
+            escape_vcard_value(&address.country)
+        ));
+    }
+    if let Some(title) = &contact.title {
+        vcard.push_str(&format!("TITLE:{}\n", escape_vcard_value(title)));
+    }
+    if let Some(url) = &contact.url {
+        vcard.push_str(&format!("URL:{}\n", escape_vcard_value(url)));
+    }
+    // Line breaks in the note are escaped to \n, so it stays on one content line
+    if let Some(note) = &contact.note {
+        vcard.push_str(&format!("NOTE:{}\n", escape_vcard_value(note)));
+    }
+    // The list separator is a bare comma; commas inside a category are escaped
+    if !contact.categories.is_empty() {
+        let categories: Vec<String> = contact.categories.iter().map(|category| escape_vcard_value(category)).collect();
+        vcard.push_str(&format!("CATEGORIES:{}\n", categories.join(",")));
+    }
+    vcard.push_str("END:VCARD");
+    vcard.split('\n').map(fold_vcard_line).collect::<Vec<_>>().join("\n")
+}
+
+// Upper bound on how long a single message may spend retrying before we give up
+const SEND_DEADLINE: Duration = Duration::from_secs(60);
+
+// One vCard delivery to one recipient
+struct Outbound<'a> {
+    contact: &'a VCard,
+    recipient: &'a str,
+    // Whether the recipient messaged us within WhatsApp's 24h session window
+    in_session: bool,
+    // Sent as the Infobip messageId so a repeated send of the same delivery is dropped upstream
+    idempotency_key: Option<String>,
+    // Sent first when set
+    media: Option<&'a Media>,
+}
+
+// Stable per-delivery key: the same inbound message to the same recipient always gives the same
+// key, across retries and restarts. Truncated to fit Infobip's 50 character messageId limit.
+fn idempotency_key(message_id: &str, recipient: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(message_id.as_bytes())
+        .chain_update(b"\n")
+        .chain_update(recipient.trim_start_matches('+').as_bytes())
+        .finalize();
+    hex::encode(&digest[..20])
+}
+
+// Send the contact to the recipient, as a native contact card unless send_as_text is set.
+// Transient failures are retried with exponential backoff up to config.max_retries. A card the
+// recipient can't receive is sent as text instead.
+async fn send_vcard(
+    client: &impl MessageSender,
+    config: &some_module::Config,
+    metrics: &Metrics,
+    send: &Outbound<'_>,
+) -> Result<(), BotError>{
+    let Outbound { contact, recipient, .. } = *send;
+    // Chosen once so the media, every text part and any retries come from the same number
+    let from = config.sender_router.pick(recipient);
+    let message_id = send.idempotency_key.as_deref();
+    // Outside the 24h session WhatsApp only delivers pre-approved templates
+    let use_template = !send.in_session && config.template_name.is_some();
+    let render_text = || {
+        let vcard = generate_vcard(contact, config.vcard_version);
+        split_message(&render_vcard_message(config, contact, &vcard), config.max_message_chars)
+    };
+    // Split (or refuse) an over-long text up front; that fails the same way on every retry
+    let mut text_parts = if config.send_as_text && !use_template { render_text()? } else { Vec::new() };
+    let mut as_text = config.send_as_text;
+    let mut text_id = message_id.map(str::to_string);
+    let card_unsupported = |e: &BotError| {
+        e.infobip_error_id().is_some_and(|id| config.contact_fallback_errors.iter().any(|known| known == id))
+    };
+    if config.dry_run {
+        let vcard = generate_vcard(contact, config.vcard_version);
+        if use_template {
+            info!(
+                "[dry run] Would send template {:?} to {} with placeholders {:?}",
+                config.template_name,
+                redact::phone(recipient),
+                template_placeholder_values(config, contact)
+            );
+        } else if config.send_as_text {
+            info!(
+                "[dry run] Would send vCard to {} as text in {} message(s):\n{}",
+                redact::phone(recipient),
+                text_parts.len(),
+                redact::text(&text_parts.join("\n---\n"))
+            );
+        } else {
+            info!("[dry run] Would send contact card to {}:\n{}", redact::phone(recipient), redact::text(&vcard));
+        }
+        if let Some(media) = send.media.filter(|_| !use_template) {
+            info!("[dry run] Would first send {:?} {} to {}", media.kind, media.url, redact::phone(recipient));
+        }
+        if let Some(caption) = config.contact_caption.as_deref().filter(|_| !use_template && !as_text) {
+            info!("[dry run] Would send the caption {:?} before the card", redact::text(caption).to_string());
+        }
+        return Ok(());
+    }
 
-    let (tx, mut rx) = mpsc::channel::<WhatsAppMessage>(100);
+    let deadline = Instant::now() + SEND_DEADLINE;
+    let mut attempt = 0;
+    // Templates can't be preceded by free-form media outside the session window
+    let mut media = send.media.filter(|_| !use_template);
+    // The caption goes with a native card only; text vCards carry MESSAGE_TEMPLATE instead
+    let mut caption = config.contact_caption.as_deref().filter(|_| !use_template && !as_text);
+    let caption_id = message_id.map(|key| idempotency_key(key, "caption"));
+    loop {
+        let timer = metrics.send_latency.start_timer();
+        // Once the media is out a retry only repeats the contact
+        let media_result = match media {
+            Some(media) => send_media(client, config, from, media, recipient, message_id).await,
+            None => Ok(()),
+        };
+        if media_result.is_ok() {
+            media = None;
+        }
+        // Likewise the caption, which goes right before the card
+        let caption_result = match (&media_result, caption) {
+            (Ok(()), Some(caption)) => send_text_message(client, config, from, caption, recipient, caption_id.as_deref()).await,
+            _ => Ok(()),
+        };
+        if media_result.is_ok() && caption_result.is_ok() {
+            caption = None;
+        }
+        let result = if let Err(e) = media_result.and(caption_result) {
+            Err(e)
+        } else if use_template {
+            send_template_message(client, config, from, contact, recipient, message_id).await
+        } else if as_text {
+            send_vcard_text(client, config, from, &text_parts, recipient, text_id.as_deref()).await
+        } else {
+            match send_contact(client, from, contact, recipient, message_id).await {
+                Err(e) if card_unsupported(&e) => {
+                    warn!("{} can't receive contact cards ({}), sending the vCard as text instead", redact::phone(recipient), e);
+                    metrics.contact_text_fallbacks.inc();
+                    text_parts = render_text()?;
+                    as_text = true;
+                    // A messageId of its own, the rejected card may have used up the original
+                    text_id = message_id.map(|key| idempotency_key(key, "text fallback"));
+                    send_vcard_text(client, config, from, &text_parts, recipient, text_id.as_deref()).await
+                }
+                result => result,
+            }
+        };
+        timer.observe_duration();
 
-    //Spawn a task to process messages with rate limiting
-    let client_clone = client.clone();
-    let config_clone = config.clone();
-    tokio::spawn(async move{
-        while let Some (message) = rx.recv().await{
-            if let Err(e) = handle_webhook(message, config_clone, client_clone).await{
-                error!("Error processing webhook: {:?}", e);
+        let delay = match result {
+            Ok(()) => {
+                metrics.vcards_sent.inc();
+                return Ok(());
             }
+            Err(e) => {
+                if attempt >= config.max_retries || !e.is_transient() {
+                    metrics.send_failures.inc();
+                    return Err(e);
+                }
+                let delay = retry_delay(&e, config, attempt);
+                if Instant::now() + delay > deadline {
+                    warn!("Giving up on {} after {} attempts, retry deadline reached", redact::phone(recipient), attempt + 1);
+                    metrics.send_failures.inc();
+                    return Err(e);
+                }
+                warn!(
+                    "Transient error sending to {} (attempt {}/{}): {}; retrying in {:?}",
+                    redact::phone(recipient), attempt + 1, config.max_retries + 1, e, delay
+                );
+                delay
+            }
+        };
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+// Infobip's Retry-After beats our guess, within reason
+fn retry_delay(error: &BotError, config: &some_module::Config, attempt: u32) -> Duration {
+    match error.retry_after() {
+        Some(retry_after) => retry_after.min(Duration::from_secs(config.max_retry_after_secs)),
+        None => backoff_delay(config.base_backoff_ms, attempt),
+    }
+}
+
+// Exponential backoff with up to one base interval of random jitter
+fn backoff_delay(base_backoff_ms: u64, attempt: u32) -> Duration {
+    let exponential = base_backoff_ms.saturating_mul(1u64 << attempt.min(16));
+    let jitter = rand::thread_rng().gen_range(0..=base_backoff_ms);
+    Duration::from_millis(exponential.saturating_add(jitter))
+}
+
+// "First Last", or just the first name when there is no last name
+fn full_name(contact: &VCard) -> String {
+    format!("{} {}", contact.first_name, contact.last_name).trim().to_string()
+}
+
+// Map our VCard onto the Infobip contact model so WhatsApp renders a tappable card
+fn to_infobip_contact(contact: &VCard) -> Contact {
+    let formatted_name = full_name(contact);
+    let mut name = ContactName::new(&contact.first_name, &formatted_name);
+    if !contact.last_name.is_empty() {
+        name.last_name = Some(contact.last_name.clone());
+    }
+
+    let mut infobip_contact = Contact::new(name);
+    infobip_contact.phones = Some(
+        contact
+            .phone_numbers
+            .iter()
+            .map(|phone| ContactPhone {
+                phone: Some(phone.number.clone()),
+                phone_type: Some(phone.kind.infobip_type()),
+                wa_id: None,
+            })
+            .collect(),
+    );
+    if let Some(email) = &contact.email {
+        infobip_contact.emails = Some(vec![ContactEmail {
+            email: Some(email.clone()),
+            email_type: None,
+        }]);
+    }
+    if contact.organization.is_some() || contact.title.is_some() {
+        infobip_contact.org = Some(ContactOrganization {
+            company: contact.organization.clone(),
+            department: None,
+            title: contact.title.clone(),
+        });
+    }
+    if let Some(url) = &contact.url {
+        infobip_contact.urls = Some(vec![ContactUrl {
+            url: Some(url.clone()),
+            url_type: None,
+        }]);
+    }
+    // WhatsApp contact cards have no notes field, the note only travels in the text vCard
+    if let Some(address) = &contact.address {
+        infobip_contact.addresses = Some(vec![ContactAddress {
+            street: Some(address.street.clone()),
+            city: Some(address.city.clone()),
+            state: Some(address.region.clone()),
+            zip: Some(address.postal_code.clone()),
+            country: Some(address.country.clone()),
+            ..Default::default()
+        }]);
+    }
+    infobip_contact
+}
+
+async fn send_contact(
+    client: &impl MessageSender,
+    from: &str,
+    contact: &VCard,
+    recipient: &str,
+    message_id: Option<&str>,
+) -> Result<(), BotError>{
+    let mut request_body = SendContactRequestBody::new(
+        from,
+        recipient,
+        ContactContent::new(vec![to_infobip_contact(contact)]),
+    );
+    request_body.message_id = message_id.map(str::to_string);
 
-            // rate limiting of one sec between messages
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    match client.send_contact(request_body).await {
+        Ok(_) => {
+            info!("Contact sent successfully to {}", redact::phone(recipient));
+            Ok(())
         }
-    });
-    let webhook = warp::post()
-        .and(warp::path("webhook"))
-        .and(warp::body::json())
-        .and(warp::any().map(move || config.clone()))
-        .and(warp::any().map(move || client.clone()))
-        .and_then(handle_webhook);
-
-    info!("WhatsApp contact adder is running...");
-    warp::serve(webhook).run(([0, 0, 0, 0], 8080)).await;
-}
\ No newline at end of file
+        Err(e) => {
+            error!("Failed to send contact: {}", e);
+            Err(e)
+        }
+    }
+}
+
+// Send the configured image or document, with its own messageId derived from the contact's
+async fn send_media(
+    client: &impl MessageSender,
+    config: &some_module::Config,
+    from: &str,
+    media: &Media,
+    recipient: &str,
+    message_id: Option<&str>,
+) -> Result<(), BotError>{
+    let message_id = message_id.map(|key| idempotency_key(key, "media"));
+    let result = match media.kind {
+        MediaKind::Image => {
+            let mut content = ImageContent::new(&media.url);
+            content.caption = config.media_caption.clone();
+            let mut request_body = SendImageRequestBody::new(from, recipient, content);
+            request_body.message_id = message_id;
+            client.send_image(request_body).await
+        }
+        MediaKind::Document => {
+            let mut content = DocumentContent::new(&media.url);
+            content.caption = config.media_caption.clone();
+            content.filename = media.filename();
+            let mut request_body = SendDocumentRequestBody::new(from, recipient, content);
+            request_body.message_id = message_id;
+            client.send_document(request_body).await
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            info!("Media sent successfully to {}", redact::phone(recipient));
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to send media: {}", e);
+            Err(e)
+        }
+    }
+}
+
+// Send the rendered text vCard, already split to the length limit, as consecutive messages
+async fn send_vcard_text(
+    client: &impl MessageSender,
+    config: &some_module::Config,
+    from: &str,
+    parts: &[String],
+    recipient: &str,
+    message_id: Option<&str>,
+) -> Result<(), BotError>{
+    // Sent one after another so they arrive in order. Each part gets its own messageId, so a
+    // retry after a partial send only delivers the parts that are missing.
+    let mut result = Ok(());
+    for (index, part) in parts.iter().enumerate() {
+        let part_id = match message_id {
+            Some(key) if index > 0 => Some(idempotency_key(key, &format!("part {}", index))),
+            key => key.map(str::to_string),
+        };
+        result = send_text_message(client, config, from, part, recipient, part_id.as_deref()).await;
+        if result.is_err() {
+            break;
+        }
+    }
+
+    match result {
+        Ok(()) => {
+            info!("vCard sent successfully to {}", redact::phone(recipient));
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to send vCard: {}", e);
+            Err(e)
+        }
+    }
+}
+
+// Send the configured pre-approved template, for recipients outside the session window
+async fn send_template_message(
+    client: &impl MessageSender,
+    config: &some_module::Config,
+    from: &str,
+    contact: &VCard,
+    recipient: &str,
+    message_id: Option<&str>,
+) -> Result<(), BotError>{
+    let template_name = config
+        .template_name
+        .as_deref()
+        .ok_or_else(|| BotError::Config("TEMPLATE_NAME is not set".to_string()))?;
+    let content = TemplateContent {
+        template_name: template_name.to_string(),
+        template_data: TemplateData::new(TemplateBodyContent::new(template_placeholder_values(config, contact))),
+        language: config.template_language.clone(),
+    };
+    let mut message = FailoverMessage::new(from, recipient, content);
+    message.message_id = message_id.map(str::to_string);
+
+    match client.send_template(SendTemplateRequestBody::new(vec![message])).await {
+        Ok(()) => {
+            info!("Template {} sent successfully to {}", template_name, redact::phone(recipient));
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to send template: {}", e);
+            Err(e)
+        }
+    }
+}
+
+// The configured template placeholders, filled in for this contact
+fn template_placeholder_values(config: &some_module::Config, contact: &VCard) -> Vec<String> {
+    config
+        .template_placeholders
+        .iter()
+        .map(|placeholder| {
+            render_template(
+                placeholder,
+                &[
+                    ("first_name", &contact.first_name),
+                    ("last_name", &contact.last_name),
+                    ("phone_number", contact.primary_phone()),
+                ],
+            )
+        })
+        .collect()
+}
+
+// Fill the message template for a contact and its rendered vCard
+fn render_vcard_message(config: &some_module::Config, contact: &VCard, vcard: &str) -> String {
+    let message = render_template(
+        &config.message_template,
+        &[
+            ("first_name", &contact.first_name),
+            ("last_name", &contact.last_name),
+            ("phone_number", contact.primary_phone()),
+            ("vcard", vcard),
+        ],
+    );
+    // Added before splitting, so it counts towards MAX_MESSAGE_CHARS and ends the last part
+    match &config.bot_signature {
+        Some(signature) => format!("{}\n{}", message, signature),
+        None => message,
+    }
+}
+
+// Send a plain WhatsApp text message
+async fn send_text_message(
+    client: &impl MessageSender,
+    config: &some_module::Config,
+    from: &str,
+    text: &str,
+    recipient: &str,
+    message_id: Option<&str>,
+) -> Result<(), BotError>{
+    if config.dry_run {
+        info!("[dry run] Would send text to {}:\n{}", redact::phone(recipient), redact::text(text));
+        return Ok(());
+    }
+    let request_body = SendTextRequestBody {
+        from: from.to_string(),
+        to: recipient.to_string(),
+        content: TextContent {
+            text: text.to_string(),
+            preview_url: Some(false),
+        },
+        message_id: message_id.map(str::to_string),
+        ..Default::default()
+    };
+
+    client.send_text(request_body).await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct MissingSignature;
+impl warp::reject::Reject for MissingSignature {}
+
+#[derive(Debug)]
+struct InvalidSignature;
+impl warp::reject::Reject for InvalidSignature {}
+
+// Missing, unreadable or outside WEBHOOK_MAX_SKEW_SECS
+#[derive(Debug)]
+struct StaleTimestamp;
+impl warp::reject::Reject for StaleTimestamp {}
+
+#[derive(Debug)]
+struct InvalidBody(String);
+impl warp::reject::Reject for InvalidBody {}
+
+#[derive(Debug)]
+struct Unauthorized;
+impl warp::reject::Reject for Unauthorized {}
+
+// Guards the admin routes: lets a request through only with "Authorization: Bearer <admin
+// token>". Without an ADMIN_TOKEN, routes marked `open_without_token` stay reachable as before
+// and the rest refuse everyone.
+fn admin_auth(
+    config: Arc<ConfigHandle>,
+    open_without_token: bool,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let admin_token = config.current().admin_token.clone();
+            async move {
+                let presented = header.as_deref().and_then(|value| value.strip_prefix("Bearer ")).map(str::trim);
+                match (admin_token, presented) {
+                    (Some(expected), Some(presented)) if constant_time_eq(expected.as_bytes(), presented.as_bytes()) => {
+                        Ok(())
+                    }
+                    (None, _) if open_without_token => Ok(()),
+                    _ => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Compare secrets without returning early on the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// Yields the raw request body once its HMAC-SHA256 signature has been checked against the
+// shared secret. The hash is taken over the bytes as received, before any JSON parsing.
+fn verified_body(
+    config: Arc<ConfigHandle>,
+    max_body_bytes: u64,
+) -> impl Filter<Extract = (Bytes,), Error = warp::Rejection> + Clone {
+    warp::header::headers_cloned()
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(warp::body::bytes())
+        .and_then(move |headers: HeaderMap, body: Bytes| {
+            let config = config.current();
+            async move {
+                let Some(secret) = &config.webhook_secret else {
+                    return Ok(body);
+                };
+                let signature = headers
+                    .get(config.webhook_signature_header.as_str())
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or_else(|| warp::reject::custom(MissingSignature))?;
+                let timestamp = match config.webhook_max_skew_secs {
+                    Some(max_skew_secs) => {
+                        let timestamp = headers
+                            .get(config.webhook_timestamp_header.as_str())
+                            .and_then(|value| value.to_str().ok())
+                            .ok_or_else(|| warp::reject::custom(StaleTimestamp))?;
+                        if !timestamp_in_window(timestamp, max_skew_secs, chrono::Utc::now().timestamp()) {
+                            warn!("Rejecting webhook with timestamp {:?} outside the {}s window", timestamp, max_skew_secs);
+                            return Err(warp::reject::custom(StaleTimestamp));
+                        }
+                        Some(timestamp)
+                    }
+                    None => None,
+                };
+                if verify_signature(secret.as_bytes(), timestamp, &body, signature) {
+                    Ok(body)
+                } else {
+                    warn!("Rejecting webhook with invalid signature");
+                    Err(warp::reject::custom(InvalidSignature))
+                }
+            }
+        })
+}
+
+// Whether a timestamp header (Unix seconds or RFC 3339) is within `max_skew_secs` of `now`,
+// either side, so neither a replayed request nor a sender clock running ahead gets through
+fn timestamp_in_window(timestamp: &str, max_skew_secs: u64, now: i64) -> bool {
+    let timestamp = timestamp.trim();
+    let Some(sent_at) = timestamp
+        .parse::<i64>()
+        .ok()
+        .or_else(|| chrono::DateTime::parse_from_rfc3339(timestamp).ok().map(|time| time.timestamp()))
+    else {
+        return false;
+    };
+    now.abs_diff(sent_at) <= max_skew_secs
+}
+
+// Check a hex encoded HMAC-SHA256 signature (optionally prefixed with "sha256=") in constant time.
+// With a timestamp the signed payload is "{timestamp}.{body}", so it can't be swapped for a fresh one.
+fn verify_signature(secret: &[u8], timestamp: Option<&str>, body: &[u8], signature: &str) -> bool {
+    let signature = signature.trim();
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    let Ok(expected) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    if let Some(timestamp) = timestamp {
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+    }
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+async fn parse_body<T: DeserializeOwned>(body: Bytes) -> Result<T, warp::Rejection> {
+    serde_json::from_slice(&body).map_err(|e| warp::reject::custom(InvalidBody(e.to_string())))
+}
+
+// Body of a 400/413 so a misconfigured sender can see what was wrong with the payload
+#[derive(Serialize)]
+struct BodyError {
+    error: &'static str,
+    message: String,
+}
+
+fn body_error(status: warp::http::StatusCode, error: &'static str, message: String) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&BodyError { error, message }), status).into_response()
+}
+
+// Turn our webhook rejections into proper status codes, leaving the rest to warp
+async fn handle_rejection(err: warp::Rejection) -> Result<warp::reply::Response, warp::Rejection> {
+    if err.find::<MissingSignature>().is_some() {
+        Ok(body_error(
+            warp::http::StatusCode::UNAUTHORIZED,
+            "missing_signature",
+            "the webhook signature header is missing".to_string(),
+        ))
+    } else if err.find::<InvalidSignature>().is_some() {
+        Ok(body_error(
+            warp::http::StatusCode::UNAUTHORIZED,
+            "invalid_signature",
+            "the webhook signature does not match the body".to_string(),
+        ))
+    } else if err.find::<StaleTimestamp>().is_some() {
+        Ok(body_error(
+            warp::http::StatusCode::UNAUTHORIZED,
+            "stale_timestamp",
+            "the webhook timestamp is missing or outside the allowed window".to_string(),
+        ))
+    } else if err.find::<Unauthorized>().is_some() {
+        Ok(body_error(
+            warp::http::StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "a valid admin bearer token is required".to_string(),
+        ))
+    } else if let Some(InvalidBody(reason)) = err.find::<InvalidBody>() {
+        Ok(body_error(
+            warp::http::StatusCode::BAD_REQUEST,
+            "invalid_body",
+            format!("invalid webhook body: {}", reason),
+        ))
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        Ok(body_error(
+            warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            "webhook body exceeds the configured size limit".to_string(),
+        ))
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        Ok(body_error(
+            warp::http::StatusCode::LENGTH_REQUIRED,
+            "length_required",
+            "webhook requests must send a Content-Length header".to_string(),
+        ))
+    } else {
+        Err(err)
+    }
+}
+
+// First path segments of the admin routes, the ones CORS applies to
+const ADMIN_PATHS: &[&str] = &["info", "metrics", "selftest", "qr", "vcard", "deadletters", "queue", "test", "contacts"];
+
+// Passes requests for one of ADMIN_PATHS. warp's CORS layer answers any preflight that reaches
+// it, so this keeps it from answering for the webhook, which is called server to server.
+fn admin_path() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::path::peek()
+        .and_then(|path: warp::path::Peek| async move {
+            match path.segments().next() {
+                Some(segment) if ADMIN_PATHS.contains(&segment) => Ok(()),
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one()
+}
+
+// The CORS policy for the admin routes, when CORS_ALLOWED_ORIGINS is set
+fn admin_cors(config: &some_module::Config) -> Option<warp::cors::Builder> {
+    if config.cors_allowed_origins.is_empty() {
+        return None;
+    }
+    let cors = warp::cors()
+        .allow_methods(config.cors_allowed_methods.iter().map(String::as_str))
+        .allow_headers(config.cors_allowed_headers.iter().map(String::as_str));
+    Some(if config.cors_allowed_origins.iter().any(|origin| origin == "*") {
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(config.cors_allowed_origins.iter().map(String::as_str))
+    })
+}
+
+// Match a slash-separated path such as "api/v1/whatsapp" exactly
+fn route_path(path: &str) -> BoxedFilter<()> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .fold(warp::any().boxed(), |filter, segment| {
+            filter.and(warp::path(segment.to_string())).boxed()
+        })
+        .and(warp::path::end())
+        .boxed()
+}
+
+// GET handshake used by providers to confirm we own the webhook URL: echo the challenge back
+// when the verify token matches. Accepts both the hub.* and plain parameter names.
+fn verify_webhook(
+    query: HashMap<String, String>,
+    verify_token: Option<String>,
+) -> warp::reply::WithStatus<String> {
+    let lookup = |name: &str| {
+        query
+            .get(&format!("hub.{}", name))
+            .or_else(|| query.get(name))
+            .cloned()
+    };
+    let token = lookup("verify_token");
+    let challenge = lookup("challenge").unwrap_or_default();
+
+    match (verify_token, token) {
+        (Some(expected), Some(token)) if constant_time_eq(expected.as_bytes(), token.as_bytes()) => {
+            info!("Webhook verification handshake succeeded");
+            warp::reply::with_status(challenge, warp::http::StatusCode::OK)
+        }
+        _ => {
+            warn!("Webhook verification handshake failed, verify token mismatch");
+            warp::reply::with_status("Forbidden".to_string(), warp::http::StatusCode::FORBIDDEN)
+        }
+    }
+}
+
+const SELFTEST_TEXT: &str = "Bot online: this is a self-test message from the WhatsApp contact adder.";
+
+#[derive(Serialize)]
+struct SelftestResult {
+    recipient: String,
+    sent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SelftestReport {
+    dry_run: bool,
+    results: Vec<SelftestResult>,
+}
+
+// Send a fixed text to each configured recipient to prove the credentials and sender number
+// work. Answers 200 when every send went through, otherwise with the first failure's status.
+async fn run_selftest<S: MessageSender>(worker: Arc<Worker<S>>) -> Result<impl warp::Reply, warp::Rejection> {
+    let config = worker.config.current();
+    let mut status = warp::http::StatusCode::OK;
+    let mut results = Vec::new();
+    for recipient in &config.recipient_phone_numbers {
+        worker.limiter.acquire().await;
+        let result = send_text_message(&worker.client, &config, config.sender_router.pick(recipient), SELFTEST_TEXT, recipient, None).await;
+        if let Err(e) = &result {
+            warn!("Self-test send to {} failed: {}", redact::phone(recipient), e);
+            if status.is_success() {
+                status = e.status_code();
+            }
+        } else {
+            info!("Self-test send to {} succeeded", redact::phone(recipient));
+        }
+        results.push(SelftestResult {
+            recipient: recipient.clone(),
+            sent: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+    let report = SelftestReport { dry_run: config.dry_run, results };
+    Ok(warp::reply::with_status(warp::reply::json(&report), status))
+}
+
+#[derive(Debug, Deserialize)]
+struct QueueQuery {
+    #[serde(default = "default_queue_sample")]
+    limit: usize,
+}
+
+fn default_queue_sample() -> usize {
+    20
+}
+
+// Depth and the oldest waiting messages. The flush token names the newest one, so a flush
+// made with it drops only what was there to look at.
+fn queue_status(query: QueueQuery, pending: Arc<PendingQueue>) -> warp::reply::Response {
+    let (depth, messages, last_seq) = pending.snapshot(query.limit.min(1000));
+    let body = serde_json::json!({
+        "depth": depth,
+        "messages": messages,
+        "flush_token": format!("flush-{}", last_seq),
+    });
+    warp::reply::json(&body).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct FlushQuery {
+    confirm: Option<String>,
+}
+
+// Drop everything queued up to the flush token from GET /queue
+fn flush_queue(query: FlushQuery, pending: Arc<PendingQueue>) -> warp::reply::Response {
+    let Some(through) = query.confirm.as_deref().and_then(|token| token.strip_prefix("flush-")?.parse::<u64>().ok())
+    else {
+        return body_error(
+            warp::http::StatusCode::BAD_REQUEST,
+            "confirmation_required",
+            "pass confirm=<flush_token> from GET /queue".to_string(),
+        );
+    };
+    let flushed = pending.flush(through);
+    warn!("Flushed {} queued message(s) through #{}", flushed, through);
+    warp::reply::json(&serde_json::json!({ "flushed": flushed })).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct ParseTestRequest {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct ParseTestReport {
+    matched: bool,
+    trigger: Option<String>,
+    command: Option<String>,
+    // A bare trigger word would start the guided flow rather than be parsed
+    guided_flow: bool,
+    contact: Option<VCard>,
+    error: Option<String>,
+}
+
+// Run a message text through the same trigger matching and command parsing a webhook gets,
+// without sending anything
+async fn parse_test<S: MessageSender>(request: ParseTestRequest, worker: Arc<Worker<S>>) -> Result<impl warp::Reply, warp::Rejection> {
+    let config = worker.config.current();
+    let text = request.text.nfc().collect::<String>();
+    let mut report = ParseTestReport {
+        matched: false,
+        trigger: None,
+        command: None,
+        guided_flow: false,
+        contact: None,
+        error: None,
+    };
+    if let Some(trigger_word) = matched_trigger(&config, &text) {
+        let command = strip_trigger(&config.trigger_patterns, &text);
+        report.matched = true;
+        report.trigger = Some(trigger_word);
+        report.command = Some(command.to_string());
+        if config.guided_flow && command.is_empty() {
+            report.guided_flow = true;
+        } else {
+            match resolve_contact(&worker, command) {
+                Ok(contact) => report.contact = Some(contact),
+                Err(e) => report.error = Some(e.contact_problem()),
+            }
+        }
+    }
+    Ok(warp::reply::json(&report))
+}
+
+#[derive(Debug, Deserialize)]
+struct DeadLetterQuery {
+    #[serde(default = "default_dead_letter_limit")]
+    limit: usize,
+}
+
+fn default_dead_letter_limit() -> usize {
+    50
+}
+
+// Most recent dead letters, newest first
+async fn list_dead_letters<S: MessageSender>(
+    query: DeadLetterQuery,
+    worker: Arc<Worker<S>>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match worker.dead_letters.list(query.limit.min(1000)) {
+        Ok(entries) => Ok(warp::reply::json(&entries).into_response()),
+        Err(e) => {
+            error!("Failed to list dead letters: {}", e);
+            Ok(body_error(e.status_code(), "storage_error", e.to_string()))
+        }
+    }
+}
+
+// What /contacts answers when CONTACTS_CSV isn't set
+fn directory_not_configured() -> warp::reply::Response {
+    body_error(
+        warp::http::StatusCode::NOT_FOUND,
+        "not_configured",
+        "set CONTACTS_CSV to manage the contact directory".to_string(),
+    )
+}
+
+async fn list_contacts<S: MessageSender>(worker: Arc<Worker<S>>) -> Result<warp::reply::Response, warp::Rejection> {
+    Ok(match &worker.directory {
+        Some(directory) => warp::reply::json(&directory.entries()).into_response(),
+        None => directory_not_configured(),
+    })
+}
+
+async fn get_contact<S: MessageSender>(alias: String, worker: Arc<Worker<S>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(directory) = &worker.directory else {
+        return Ok(directory_not_configured());
+    };
+    Ok(match directory.entry(&alias) {
+        Some(entry) => warp::reply::json(&entry).into_response(),
+        None => body_error(
+            warp::http::StatusCode::NOT_FOUND,
+            "not_found",
+            ParseError::UnknownAlias(alias).to_string(),
+        ),
+    })
+}
+
+// Create or replace a contact: 201 when the alias is new, 200 when it was updated
+async fn put_contact<S: MessageSender>(entry: Entry, worker: Arc<Worker<S>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(directory) = &worker.directory else {
+        return Ok(directory_not_configured());
+    };
+    let alias = entry.alias.trim().to_lowercase();
+    match directory.upsert(entry) {
+        Ok(Ok(created)) => {
+            info!("{} contact '{}' in the directory", if created { "Added" } else { "Updated" }, alias);
+            let status = if created { warp::http::StatusCode::CREATED } else { warp::http::StatusCode::OK };
+            let entry = directory.entry(&alias);
+            Ok(warp::reply::with_status(warp::reply::json(&entry), status).into_response())
+        }
+        Ok(Err(e)) => Ok(body_error(warp::http::StatusCode::BAD_REQUEST, "invalid_contact", e.contact_problem())),
+        Err(e) => {
+            error!("Failed to save contact '{}': {}", alias, e);
+            Ok(body_error(e.status_code(), "storage_error", e.to_string()))
+        }
+    }
+}
+
+async fn delete_contact<S: MessageSender>(alias: String, worker: Arc<Worker<S>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(directory) = &worker.directory else {
+        return Ok(directory_not_configured());
+    };
+    match directory.remove(&alias) {
+        Ok(true) => {
+            info!("Removed contact '{}' from the directory", alias.trim().to_lowercase());
+            Ok(warp::http::StatusCode::NO_CONTENT.into_response())
+        }
+        Ok(false) => Ok(body_error(
+            warp::http::StatusCode::NOT_FOUND,
+            "not_found",
+            ParseError::UnknownAlias(alias).to_string(),
+        )),
+        Err(e) => {
+            error!("Failed to remove contact '{}': {}", alias, e);
+            Ok(body_error(e.status_code(), "storage_error", e.to_string()))
+        }
+    }
+}
+
+// Queue a dead-lettered message through the worker again, as if it had just arrived
+async fn replay_dead_letter<S: MessageSender>(
+    id: i64,
+    worker: Arc<Worker<S>>,
+    tx: LaneSender,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let mut message = match worker.dead_letters.claim_for_replay(id) {
+        Ok(Ok(message)) => message,
+        Ok(Err(ReplayError::NotFound)) => {
+            return Ok(body_error(
+                warp::http::StatusCode::NOT_FOUND,
+                "not_found",
+                format!("there is no dead letter #{}", id),
+            ));
+        }
+        Ok(Err(ReplayError::AlreadyReplayed)) => {
+            return Ok(body_error(
+                warp::http::StatusCode::CONFLICT,
+                "already_replayed",
+                format!("dead letter #{} has already been replayed", id),
+            ));
+        }
+        Err(e) => {
+            error!("Failed to read dead letter #{}: {}", id, e);
+            return Ok(body_error(e.status_code(), "storage_error", e.to_string()));
+        }
+    };
+    let _span = tracing::info_span!("message", correlation_id = %message.correlation_id).entered();
+    message.trace_context = Some(tracing::Span::current().context());
+    if let Some(store) = &worker.store {
+        match store.enqueue(&message) {
+            Ok(queue_id) => message.queue_id = Some(queue_id),
+            Err(e) => error!("Failed to persist replayed message, queueing it anyway: {}", e),
+        }
+    }
+    let correlation_id = message.correlation_id.clone();
+    if let Err(e) = enqueue_message(&tx, Lane::Normal, &worker.metrics, &worker.pending, message) {
+        if let Err(e) = worker.dead_letters.release(id) {
+            error!("Failed to release dead letter #{}: {}", id, e);
+        }
+        return Ok(body_error(e.status_code(), "not_queued", e.to_string()));
+    }
+    info!("Replaying dead letter #{}", id);
+    let body = serde_json::json!({ "id": id, "status": "queued", "correlation_id": correlation_id });
+    Ok(warp::reply::with_status(warp::reply::json(&body), warp::http::StatusCode::ACCEPTED).into_response())
+}
+
+// Contact for GET /qr and GET /vcard: a directory alias, or the fields to build one from
+#[derive(Debug, Deserialize)]
+struct ContactQuery {
+    alias: Option<String>,
+    first_name: Option<String>,
+    #[serde(default)]
+    last_name: String,
+    phone: Option<String>,
+    email: Option<String>,
+    organization: Option<String>,
+}
+
+fn query_contact(worker: &Worker<impl MessageSender>, query: ContactQuery) -> Result<VCard, BotError> {
+    if let Some(alias) = query.alias {
+        return Ok(worker
+            .directory
+            .as_ref()
+            .and_then(|directory| directory.get(&alias))
+            .ok_or(ParseError::UnknownAlias(alias))?);
+    }
+    let first_name = query.first_name.filter(|name| !name.trim().is_empty()).ok_or(ParseError::MissingName)?;
+    let phone = PhoneNumber {
+        number: validate_e164(query.phone.as_deref().ok_or(ParseError::MissingPhone)?)?,
+        kind: PhoneKind::Cell,
+    };
+    let mut contact = VCard::with_phone_numbers(first_name, query.last_name, vec![phone])?;
+    if let Some(email) = query.email {
+        if !is_valid_email(&email) {
+            return Err(ParseError::InvalidEmail(email).into());
+        }
+        contact.email = Some(email);
+    }
+    contact.organization = query.organization.filter(|org| !org.trim().is_empty());
+    FieldLimits::from_config(&worker.config.current()).check(&contact)?;
+    Ok(contact)
+}
+
+// Without an ADMIN_TOKEN, /qr and /vcard stay open for contacts built from the query, but a
+// directory alias would hand out stored entries to anyone
+fn alias_refused(worker: &Worker<impl MessageSender>, query: &ContactQuery) -> Option<warp::reply::Response> {
+    (query.alias.is_some() && worker.config.current().admin_token.is_none()).then(|| {
+        body_error(
+            warp::http::StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "looking up a directory alias needs ADMIN_TOKEN".to_string(),
+        )
+    })
+}
+
+fn invalid_contact_query(e: BotError) -> warp::reply::Response {
+    let status = match e {
+        BotError::Parse(ParseError::UnknownAlias(_)) => warp::http::StatusCode::NOT_FOUND,
+        _ => warp::http::StatusCode::BAD_REQUEST,
+    };
+    body_error(status, "invalid_contact", e.contact_problem())
+}
+
+// The contact's vCard as a QR code PNG. Nothing is sent.
+async fn qr_code<S: MessageSender>(query: ContactQuery, worker: Arc<Worker<S>>) -> Result<warp::reply::Response, warp::Rejection> {
+    if let Some(refused) = alias_refused(&worker, &query) {
+        return Ok(refused);
+    }
+    let contact = match query_contact(&worker, query) {
+        Ok(contact) => contact,
+        Err(e) => return Ok(invalid_contact_query(e)),
+    };
+    let vcard = generate_vcard(&contact, worker.config.current().vcard_version);
+    match qr::render_png(&vcard) {
+        Ok(png) => Ok(warp::reply::with_header(png, "Content-Type", "image/png").into_response()),
+        Err(qrcode::types::QrError::DataTooLong) => Ok(body_error(
+            warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+            "vcard_too_large",
+            format!("the vCard is {} bytes, too large to fit in a QR code", vcard.len()),
+        )),
+        Err(e) => Ok(body_error(
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            "qr_failed",
+            format!("could not encode the QR code: {}", e),
+        )),
+    }
+}
+
+// The contact's vCard as a .vcf download. Nothing is sent.
+async fn vcard_file<S: MessageSender>(query: ContactQuery, worker: Arc<Worker<S>>) -> Result<warp::reply::Response, warp::Rejection> {
+    if let Some(refused) = alias_refused(&worker, &query) {
+        return Ok(refused);
+    }
+    let contact = match query_contact(&worker, query) {
+        Ok(contact) => contact,
+        Err(e) => return Ok(invalid_contact_query(e)),
+    };
+    // Files get the CRLF line endings RFC 6350 asks for, last line included; messages keep
+    // plain newlines
+    let vcard = generate_vcard(&contact, worker.config.current().vcard_version).replace('\n', "\r\n") + "\r\n";
+    let reply = warp::reply::with_header(vcard, "Content-Type", "text/vcard; charset=utf-8");
+    Ok(warp::reply::with_header(reply, "Content-Disposition", "attachment; filename=\"contact.vcf\"").into_response())
+}
+
+// Contacts for GET /vcard/batch: comma-separated directory aliases, or a category to take every
+// contact in
+#[derive(Debug, Deserialize)]
+struct BatchQuery {
+    aliases: Option<String>,
+    group: Option<String>,
+}
+
+// Several contacts as one .vcf document, each a complete vCard of its own
+fn generate_vcard_batch(contacts: &[VCard], version: VCardVersion) -> String {
+    contacts
+        .iter()
+        .map(|contact| generate_vcard(contact, version))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Directory contacts as a single .vcf download, for importing in one go. Nothing is sent.
+async fn vcard_batch<S: MessageSender>(query: BatchQuery, worker: Arc<Worker<S>>) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(directory) = &worker.directory else {
+        return Ok(directory_not_configured());
+    };
+    let contacts = match (query.aliases, query.group) {
+        (Some(aliases), None) => {
+            let mut contacts = Vec::new();
+            for alias in aliases.split(',').map(str::trim).filter(|alias| !alias.is_empty()) {
+                match directory.get(alias) {
+                    Some(contact) => contacts.push(contact),
+                    None => return Ok(invalid_contact_query(ParseError::UnknownAlias(alias.to_string()).into())),
+                }
+            }
+            contacts
+        }
+        (None, Some(group)) => directory.group(&group),
+        _ => {
+            return Ok(body_error(
+                warp::http::StatusCode::BAD_REQUEST,
+                "invalid_query",
+                "give either aliases or group".to_string(),
+            ));
+        }
+    };
+    if contacts.is_empty() {
+        return Ok(body_error(
+            warp::http::StatusCode::BAD_REQUEST,
+            "no_contacts",
+            "no contacts matched, the batch would be empty".to_string(),
+        ));
+    }
+    let vcf = generate_vcard_batch(&contacts, worker.config.current().vcard_version).replace('\n', "\r\n") + "\r\n";
+    let reply = warp::reply::with_header(vcf, "Content-Type", "text/vcard; charset=utf-8");
+    Ok(warp::reply::with_header(reply, "Content-Disposition", "attachment; filename=\"contacts.vcf\"").into_response())
+}
+
+// Readiness probe: only report ready once main has finished setting up the worker and client
+fn readiness(ready: Arc<AtomicBool>) -> warp::reply::WithStatus<&'static str> {
+    if ready.load(Ordering::SeqCst) {
+        warp::reply::with_status("Ready", warp::http::StatusCode::OK)
+    } else {
+        warp::reply::with_status("Not ready", warp::http::StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+// What happened to one message in a webhook delivery
+#[derive(Serialize)]
+struct WebhookOutcome {
+    correlation_id: String,
+    // "queued", "duplicate" for a redelivery we already have, or "rejected"
+    status: &'static str,
+    triggered: bool,
+    // With DEBUG_ECHO on: the message as we parsed it and the trigger word it matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<WhatsAppMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trigger_matched: Option<String>,
+}
+
+#[derive(Serialize)]
+struct WebhookResponse {
+    // "queued", or "error" when a message was rejected and the delivery should be retried
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    messages: Vec<WebhookOutcome>,
+}
+
+fn webhook_response(outcomes: Vec<WebhookOutcome>, error: Option<BotError>) -> warp::reply::Response {
+    let status = error.as_ref().map_or(warp::http::StatusCode::OK, BotError::status_code);
+    let body = WebhookResponse {
+        status: if error.is_some() { "error" } else { "queued" },
+        error: error.map(|e| e.to_string()),
+        messages: outcomes,
+    };
+    warp::reply::with_status(warp::reply::json(&body), status).into_response()
+}
+
+// Webhook endpoint: queue each delivered message for the worker and acknowledge right away
+async fn enqueue_webhook<S: MessageSender>(
+    webhook: InboundWebhook,
+    tx: LaneSender,
+    dedup: Arc<DedupCache>,
+    worker: Arc<Worker<S>>,
+    config: Arc<some_module::Config>,
+    headers: HeaderMap,
+) -> Result<warp::reply::Response, warp::Rejection>{
+    let Worker { store, metrics, pending, .. } = &*worker;
+    // A child of the gateway's span when the request carries a traceparent. Nothing below awaits,
+    // so the span can stay entered for the whole request.
+    let request_span = tracing::info_span!("webhook", messages = webhook.results.len());
+    let _ = request_span.set_parent(remote_context(&headers));
+    let _request = request_span.enter();
+    let mut outcomes = Vec::new();
+    for result in webhook.results {
+        let sender_name = result.contact.as_ref().and_then(|c| c.name.clone()).unwrap_or("unknown".to_string());
+        let message_id = result.message_id.clone();
+        let mut message = WhatsAppMessage::from(result);
+        // No await happens while the span is entered
+        let _span = tracing::info_span!("message", correlation_id = %message.correlation_id).entered();
+        // Carried to the worker so its spans continue this trace
+        message.trace_context = Some(tracing::Span::current().context());
+        info!("Webhook delivered message {} from {} ({})", message_id, redact::phone(&message.from), sender_name);
+
+        let trigger_matched = message.text.as_deref().and_then(|text| matched_trigger(&config, text));
+        let mut outcome = WebhookOutcome {
+            correlation_id: message.correlation_id.clone(),
+            status: "queued",
+            triggered: trigger_matched.is_some(),
+            message: config.debug_echo.then(|| message.clone()),
+            trigger_matched: trigger_matched.filter(|_| config.debug_echo),
+        };
+
+        // Redeliveries of a message we already accepted are acknowledged but not processed again
+        if !dedup.insert(&message_id) {
+            info!("Skipping duplicate delivery of message {}", message_id);
+            outcome.status = "duplicate";
+            outcomes.push(outcome);
+            continue;
+        }
+
+        // Persist first so the message survives a crash between here and the worker
+        if let Some(store) = store {
+            match store.enqueue(&message) {
+                Ok(id) => message.queue_id = Some(id),
+                Err(e) => {
+                    error!("Failed to persist message from {}: {}", redact::phone(&message.from), e);
+                    dedup.remove(&message_id);
+                    outcome.status = "rejected";
+                    outcomes.push(outcome);
+                    return Ok(webhook_response(outcomes, Some(e)));
+                }
+            }
+        }
+        // The worker that takes the message sends the ack first, so the two can't race
+        if outcome.triggered {
+            message.ack = config.ack_message.clone();
+        }
+        let queue_id = message.queue_id;
+        if let Err(e) = enqueue_message(&tx, Lane::Priority, metrics, pending, message) {
+            dedup.remove(&message_id);
+            // We are answering with an error so the provider will redeliver; don't replay it too
+            if let (Some(store), Some(id)) = (store, queue_id)
+                && let Err(e) = store.mark_done(id, QueueStatus::Failed)
+            {
+                error!("Failed to update queued message {}: {}", id, e);
+            }
+            outcome.status = "rejected";
+            outcomes.push(outcome);
+            return Ok(webhook_response(outcomes, Some(e)));
+        }
+        outcomes.push(outcome);
+    }
+    Ok(webhook_response(outcomes, None))
+}
+
+// Hand a message to the worker without blocking the request. A full queue is answered with 503
+// so the provider redelivers later.
+fn enqueue_message(
+    tx: &LaneSender,
+    lane: Lane,
+    metrics: &Metrics,
+    pending: &PendingQueue,
+    mut message: WhatsAppMessage,
+) -> Result<(), BotError> {
+    let seq = pending.track(&message);
+    message.pending_seq = seq;
+    let result = tx.lane(lane).try_send(message);
+    if result.is_err() {
+        pending.untrack(seq);
+    }
+    match result {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(message)) => {
+            metrics.queue_full.inc();
+            warn!("Message queue is full, rejecting message from {}", redact::phone(&message.from));
+            Err(BotError::RateLimited { message: "message queue is full, try again later".to_string(), retry_after: None })
+        }
+        Err(TrySendError::Closed(message)) => {
+            error!("Worker is not running, dropping message from {}", redact::phone(&message.from));
+            Err(BotError::Send("worker unavailable".to_string()))
+        }
+    }
+}
+
+// Status callback: count each delivery report and record it against the send it belongs to.
+// With max_redeliveries set, a failed delivery is queued to that recipient again.
+async fn receive_delivery_reports(
+    reports: DeliveryReports,
+    tx: LaneSender,
+    store: Option<Arc<QueueStore>>,
+    metrics: Arc<Metrics>,
+    pending: Arc<PendingQueue>,
+    max_redeliveries: Option<u32>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    for report in reports.results {
+        let status = report.delivery_status();
+        metrics.delivery_reports.with_label_values(&[status.as_str()]).inc();
+        if status == DeliveryStatus::Failed {
+            tracing::warn!(
+                recipient = %redact::phone(&report.to),
+                status = status.as_str(),
+                "Delivery of {} to {} failed: {}",
+                report.message_id,
+                redact::phone(&report.to),
+                report.failure_reason()
+            );
+        } else {
+            tracing::info!(
+                recipient = %redact::phone(&report.to),
+                status = status.as_str(),
+                "Delivery report for {} to {}: {}",
+                report.message_id,
+                redact::phone(&report.to),
+                status.as_str()
+            );
+        }
+
+        let Some(store) = &store else {
+            continue;
+        };
+        let delivery = match store.update_delivery(&report.message_id, status) {
+            Ok(Some(delivery)) => delivery,
+            Ok(None) => {
+                info!("No recorded send for delivery report {}", report.message_id);
+                continue;
+            }
+            Err(e) => {
+                error!("Failed to record delivery report {}: {}", report.message_id, e);
+                continue;
+            }
+        };
+        if status != DeliveryStatus::Failed {
+            continue;
+        }
+        match max_redeliveries {
+            Some(limit) if delivery.attempt < limit => {}
+            Some(_) => {
+                warn!("Not redelivering to {}, redelivery limit reached", redact::phone(&delivery.recipient));
+                continue;
+            }
+            None => continue,
+        }
+        match store.message(delivery.queue_id) {
+            Ok(Some(mut message)) => {
+                // The original row already has its outcome, the redelivery is tracked on its own
+                message.queue_id = None;
+                message.redelivery = Some(Redelivery {
+                    queue_id: delivery.queue_id,
+                    recipient: delivery.recipient.clone(),
+                    attempt: delivery.attempt + 1,
+                });
+                match enqueue_message(&tx, Lane::Normal, &metrics, &pending, message) {
+                    Ok(()) => info!(
+                        "Queued redelivery {} of message {} to {}",
+                        delivery.attempt + 1,
+                        delivery.queue_id,
+                        redact::phone(&delivery.recipient)
+                    ),
+                    Err(e) => warn!("Could not queue redelivery to {}: {}", redact::phone(&delivery.recipient), e),
+                }
+            }
+            Ok(None) => warn!("Queued message {} is gone, not redelivering", delivery.queue_id),
+            Err(e) => error!("Failed to load queued message {}: {}", delivery.queue_id, e),
+        }
+    }
+    Ok(warp::reply::with_status("Reports received".to_string(), warp::http::StatusCode::OK))
+}
+
+// Pick who receives the vCard: the sender when reply_to_sender is on and their number looks
+// valid, otherwise every configured recipient
+fn select_recipients<'a>(config: &'a some_module::Config, from: &'a str) -> Vec<&'a str> {
+    if config.reply_to_sender {
+        if is_plausible_e164(from) {
+            return vec![from];
+        }
+        warn!("Sender '{}' is not a valid E.164 number, replying to the configured recipients", redact::phone(from));
+    }
+    config.recipient_phone_numbers.iter().map(String::as_str).collect()
+}
+
+// First configured trigger word in the text, lowercased, matched per TRIGGER_MATCH_MODE
+fn matched_trigger(config: &some_module::Config, text: &str) -> Option<String> {
+    find_trigger(&config.trigger_patterns, text)
+}
+
+// Blocked senders are always refused; with an allowlist configured only listed senders pass.
+// Infobip reports senders without the '+', so numbers are compared on their digits.
+fn sender_permitted(config: &some_module::Config, from: &str) -> bool {
+    let digits = from.trim_start_matches('+');
+    let listed = |numbers: &[String]| numbers.iter().any(|n| n.trim_start_matches('+') == digits);
+    if listed(&config.blocked_senders) {
+        return false;
+    }
+    config.allowed_senders.is_empty() || listed(&config.allowed_senders)
+}
+
+// E.164: optional leading '+', then up to 15 digits with a non-zero country code
+fn is_plausible_e164(number: &str) -> bool {
+    let digits = number.strip_prefix('+').unwrap_or(number);
+    (8..=15).contains(&digits.len())
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && !digits.starts_with('0')
+}
+
+// Process a queued WhatsApp message, sending the vCard when the trigger word is present
+async fn handle_webhook(message: WhatsAppMessage, worker: &Worker<impl MessageSender>) -> Result<(), BotError>{
+    let Worker { metrics, sessions, confirmations, builder, .. } = worker;
+    let config = worker.config.current();
+    if let Some(redelivery) = &message.redelivery {
+        return redeliver_vcard(worker, &message, redelivery).await;
+    }
+    if message.broadcast {
+        return send_broadcast(worker, &message).await;
+    }
+    match message.text.as_deref() {
+        Some(text) => tracing::info!(
+            from = %redact::phone(&message.from),
+            "Received message from {}: {:?}",
+            redact::phone(&message.from),
+            redact::text(text).to_string()
+        ),
+        None => tracing::info!(
+            from = %redact::phone(&message.from),
+            "Received message from {} without text",
+            redact::phone(&message.from)
+        ),
+    }
+    metrics.messages_received.inc();
+    sessions.record(&message.from);
+
+    if !sender_permitted(&config, &message.from) {
+        warn!("Ignoring message from {}, sender is not permitted", redact::phone(&message.from));
+        return Ok(());
+    }
+
+    // A lost ack isn't worth failing the message for, the contact is handled either way
+    if let Some(ack) = &message.ack
+        && let Err(e) = reply_to(worker, &message.from, ack).await
+    {
+        warn!("Failed to acknowledge the message from {}: {}", redact::phone(&message.from), e);
+    }
+
+    if let Some(location) = &message.location {
+        let shared = format!("{}, {}", location.latitude, location.longitude);
+        info!("{} shared a location ({})", redact::phone(&message.from), redact::text(&shared));
+        return Ok(());
+    }
+
+    // Images, button replies etc. carry no text to match against
+    let Some(text) = message.text.as_deref() else {
+        info!("Skipping non-text message from {}", redact::phone(&message.from));
+        return Ok(());
+    };
+
+    // Opt-out keywords are honoured before anything else looks at the text
+    let keyword = text.trim().to_lowercase();
+    if config.stop_keywords.contains(&keyword) {
+        worker.suppressions.suppress(&message.from)?;
+        info!("{} opted out, suppressing further sends", redact::phone(&message.from));
+        let reply = "You have been unsubscribed and will not receive further messages. Reply START to resubscribe.";
+        return send_reply(worker, &message.from, reply).await;
+    }
+    if config.start_keywords.contains(&keyword) && worker.suppressions.is_suppressed(&message.from) {
+        worker.suppressions.resubscribe(&message.from)?;
+        info!("{} opted back in", redact::phone(&message.from));
+        return send_reply(worker, &message.from, "You have been resubscribed.").await;
+    }
+
+    if config.location_keywords.contains(&keyword)
+        && let Some(location) = office_location(&config)
+    {
+        info!("Sending the location to {}", redact::phone(&message.from));
+        return reply_with_location(worker, &message.from, location).await;
+    }
+
+    // A sender in the middle of the guided flow is answering its questions
+    if config.guided_flow
+        && let Some(step) = builder.feed(&message.from, text, config.default_country_code.as_deref())
+    {
+        return match step {
+            Step::Prompt(prompt) => reply_to(worker, &message.from, &prompt).await,
+            Step::Complete(contact) => match FieldLimits::from_config(&config).check(&contact) {
+                Ok(()) => submit_contact(worker, &message, *contact).await,
+                Err(e) => {
+                    info!("Rejected the guided contact from {}: {}", redact::phone(&message.from), e);
+                    reply_to(worker, &message.from, &format!("Sorry, {}.", e)).await?;
+                    Err(e)
+                }
+            },
+            Step::Cancelled => {
+                info!("{} cancelled the guided contact flow", redact::phone(&message.from));
+                reply_to(worker, &message.from, "OK, cancelled.").await
+            }
+        };
+    }
+
+    if config.require_confirmation
+        && let Some(resolution) = confirmations.resolve(&message.from, text, message.quoted_message_id.as_deref())
+    {
+        return match resolution {
+            Resolution::Confirmed(contact) => {
+                info!("{} confirmed {}", redact::phone(&message.from), full_name(&contact));
+                deliver_vcard(worker, &message, &contact).await
+            }
+            Resolution::Declined(contact) => {
+                info!("{} declined {}", redact::phone(&message.from), full_name(&contact));
+                let reply = format!("OK, {} was not added.", full_name(&contact));
+                reply_to(worker, &message.from, &reply).await
+            }
+            Resolution::Expired(contact) => {
+                info!("Confirmation of {} from {} expired", full_name(&contact), redact::phone(&message.from));
+                let reply = "That request has expired, please send the contact again.";
+                reply_to(worker, &message.from, reply).await
+            }
+        };
+    }
+
+    if let Some(trigger_word) = matched_trigger(&config, text) {
+        tracing::info!(
+            from = %redact::phone(&message.from),
+            trigger_matched = %trigger_word,
+            "Trigger word '{}' detected from {}",
+            trigger_word,
+            redact::phone(&message.from)
+        );
+        metrics.triggers_matched.inc();
+
+        let command = strip_trigger(&config.trigger_patterns, text);
+        // A bare trigger word starts the guided flow instead of failing to parse
+        if config.guided_flow && command.is_empty() {
+            info!("Starting guided contact flow for {}", redact::phone(&message.from));
+            let prompt = builder.start(&message.from);
+            return reply_to(worker, &message.from, &prompt).await;
+        }
+
+        let contact = match resolve_contact(worker, command) {
+            Ok(contact) => contact,
+            Err(e) => {
+                info!("Could not parse contact command from {}: {}", redact::phone(&message.from), e.contact_problem());
+                let reply = format!("Sorry, {}.\n{}", e.contact_problem(), command::usage(&trigger_word));
+                reply_to(worker, &message.from, &reply).await?;
+                return Err(e);
+            }
+        };
+
+        submit_contact(worker, &message, contact).await?;
+    } else if config.reply_on_no_match {
+        info!("No trigger word in the message from {}, replying with a hint", redact::phone(&message.from));
+        let trigger = config.trigger_words.first().map_or("", String::as_str);
+        let reply = render_template(&config.no_match_message, &[("trigger", trigger)]);
+        reply_to(worker, &message.from, &reply).await?;
+    }
+    Ok(())
+}
+
+// With a directory loaded, a single word command is an alias for one of its contacts; anything
+// else is parsed as a full contact command. The trigger is already stripped.
+fn resolve_contact(worker: &Worker<impl MessageSender>, command: &str) -> Result<VCard, BotError> {
+    if let Some(directory) = &worker.directory {
+        let mut words = command.split_whitespace();
+        if let (Some(alias), None) = (words.next(), words.next())
+            && !alias.chars().any(|c| c.is_ascii_digit())
+        {
+            return Ok(directory
+                .get(alias)
+                .ok_or_else(|| ParseError::UnknownAlias(alias.to_string()))?);
+        }
+    }
+    let config = worker.config.current();
+    let contact = parse_contact_command(command, config.default_country_code.as_deref())?;
+    FieldLimits::from_config(&config).check(&contact)?;
+    Ok(contact)
+}
+
+// Ask the sender to confirm the contact when that's required, otherwise send it right away
+async fn submit_contact(worker: &Worker<impl MessageSender>, message: &WhatsAppMessage, contact: VCard) -> Result<(), BotError> {
+    if worker.config.current().require_confirmation {
+        let prompt = format!("Add {}? Reply YES to confirm or NO to cancel.", full_name(&contact));
+        // Our own messageId on the prompt lets a quoted reply be matched to it
+        let prompt_id = uuid::Uuid::new_v4().to_string();
+        worker.confirmations.request(&message.from, contact, &prompt_id);
+        return reply_to_as(worker, &message.from, &prompt, Some(&prompt_id)).await;
+    }
+    deliver_vcard(worker, message, &contact).await
+}
+
+// Send the vCard from an earlier message again, to the one recipient it failed to reach. The
+// command is parsed afresh from the stored text; the sender already went through the checks.
+async fn redeliver_vcard(
+    worker: &Worker<impl MessageSender>,
+    message: &WhatsAppMessage,
+    redelivery: &Redelivery,
+) -> Result<(), BotError> {
+    let text = message.text.as_deref().unwrap_or_default();
+    let config = worker.config.current();
+    if matched_trigger(&config, text).is_none() {
+        warn!("Redelivered message {} no longer matches a trigger word", redelivery.queue_id);
+        return Ok(());
+    }
+    let contact = resolve_contact(worker, strip_trigger(&config.trigger_patterns, text))?;
+    let outcome = fan_out_vcard(worker, message, &contact, &[redelivery.recipient.as_str()]).await;
+    match outcome.last_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Send a text back to `to` unless they opted out
+async fn reply_to(worker: &Worker<impl MessageSender>, to: &str, text: &str) -> Result<(), BotError> {
+    reply_to_as(worker, to, text, None).await
+}
+
+// reply_to, sending the text under the given messageId
+async fn reply_to_as(worker: &Worker<impl MessageSender>, to: &str, text: &str, message_id: Option<&str>) -> Result<(), BotError> {
+    if worker.suppressions.is_suppressed(to) {
+        info!("Not replying to {}, they opted out", redact::phone(to));
+        worker.metrics.suppressed_sends.inc();
+        return Ok(());
+    }
+    send_reply_as(worker, to, text, message_id).await
+}
+
+// The location configured for location_keywords, if there is one
+fn office_location(config: &some_module::Config) -> Option<LocationContent> {
+    let mut content = LocationContent::new(config.location_latitude?, config.location_longitude?);
+    content.name = config.location_name.clone();
+    content.address = config.location_address.clone();
+    Some(content)
+}
+
+// reply_to, for a location
+async fn reply_with_location(worker: &Worker<impl MessageSender>, to: &str, location: LocationContent) -> Result<(), BotError> {
+    if worker.suppressions.is_suppressed(to) {
+        info!("Not replying to {}, they opted out", redact::phone(to));
+        worker.metrics.suppressed_sends.inc();
+        return Ok(());
+    }
+    worker.recipient_limiter.acquire(to).await;
+    worker.limiter.acquire().await;
+    let config = worker.config.current();
+    if config.dry_run {
+        info!("[dry run] Would send the location to {}", redact::phone(to));
+        return Ok(());
+    }
+    let request_body = SendLocationRequestBody::new(config.sender_router.pick(to), to, location);
+    worker.client.send_location(request_body).await
+}
+
+// Send a text to `to`, waiting on the rate limiters like any other send
+async fn send_reply(worker: &Worker<impl MessageSender>, to: &str, text: &str) -> Result<(), BotError> {
+    send_reply_as(worker, to, text, None).await
+}
+
+async fn send_reply_as(worker: &Worker<impl MessageSender>, to: &str, text: &str, message_id: Option<&str>) -> Result<(), BotError> {
+    // Wait on the recipient's own budget first so we don't hold a global token meanwhile
+    worker.recipient_limiter.acquire(to).await;
+    worker.limiter.acquire().await;
+    let config = worker.config.current();
+    send_text_message(&worker.client, &config, config.sender_router.pick(to), text, to, message_id).await
+}
+
+// Send the parsed contact to everyone it is meant for. Fails only when nobody got it.
+async fn deliver_vcard(worker: &Worker<impl MessageSender>, message: &WhatsAppMessage, contact: &VCard) -> Result<(), BotError> {
+    let config = worker.config.current();
+    let day = current_day(config.daily_cap_reset_hour);
+    if let Some(cap) = config.max_sends_per_sender_per_day
+        && !worker.send_counts.try_take(&message.from, &day, cap)?
+    {
+        info!("{} reached the daily limit of {} vCard(s), not sending {}", redact::phone(&message.from), cap, full_name(contact));
+        worker.metrics.daily_cap_reached.inc();
+        let reply = format!("Sorry, you've reached the daily limit of {} contact(s). Please try again tomorrow.", cap);
+        return reply_to(worker, &message.from, &reply).await;
+    }
+    let recipients = select_recipients(&config, &message.from);
+    let outcome = fan_out_vcard(worker, message, contact, &recipients).await;
+    // Nobody got it, so it doesn't count against the sender; a replay would count it again
+    if config.max_sends_per_sender_per_day.is_some()
+        && outcome.succeeded == 0
+        && outcome.last_error.is_some()
+        && let Err(e) = worker.send_counts.give_back(&message.from, &day)
+    {
+        error!("Failed to return the daily send for {}: {}", redact::phone(&message.from), e);
+    }
+    info!(
+        "vCard from {} delivered to {}/{} recipients, {} skipped as duplicates",
+        redact::phone(&message.from),
+        outcome.succeeded,
+        recipients.len(),
+        outcome.skipped
+    );
+    // Only report the message as failed when nobody got the card, now or recently
+    match outcome.last_error {
+        Some(e) if outcome.succeeded + outcome.skipped == 0 => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// When each send of a fan-out may start
+enum Pacing {
+    // A random pause of this many milliseconds before every send but the first
+    Jitter { min_ms: u64, max_ms: u64 },
+    // Send i goes out at a random point in the i-th of the equal slots `spread` is divided into,
+    // so the whole broadcast takes about `spread`
+    Spread { started: tokio::time::Instant, slot: Duration },
+}
+
+impl Pacing {
+    fn new(config: &some_module::Config, broadcast: bool, recipients: usize) -> Self {
+        match config.broadcast_spread_minutes {
+            Some(minutes) if broadcast => Pacing::Spread {
+                started: tokio::time::Instant::now(),
+                slot: Duration::from_secs(minutes * 60) / recipients.max(1) as u32,
+            },
+            _ => Pacing::Jitter { min_ms: config.send_jitter_ms_min, max_ms: config.send_jitter_ms_max },
+        }
+    }
+
+    // Sleep until recipient `index` is due; `sent` is how many sends this fan-out started so far
+    async fn wait(&self, index: usize, sent: usize) {
+        match *self {
+            Pacing::Jitter { max_ms: 0, .. } => {}
+            Pacing::Jitter { .. } if sent == 0 => {}
+            Pacing::Jitter { min_ms, max_ms } => {
+                let pause = rand::thread_rng().gen_range(min_ms..=max_ms);
+                tokio::time::sleep(Duration::from_millis(pause)).await;
+            }
+            Pacing::Spread { started, slot } => {
+                let offset = slot.mul_f64(rand::thread_rng().gen_range(0.0..1.0));
+                tokio::time::sleep_until(started + slot * index as u32 + offset).await;
+            }
+        }
+    }
+}
+
+// How a fan-out went: number of recipients reached, those skipped because they got the same
+// contact recently, and the last failure, if any
+struct FanOutOutcome {
+    succeeded: usize,
+    skipped: usize,
+    last_error: Option<BotError>,
+}
+
+// A recipient's contact key in the dedup cache while its send is under way. Unless the send
+// went out, dropping it forgets the key, so a repeat of the request is tried; that includes a
+// send that panicked and is about to be retried.
+struct ContactClaim<'a> {
+    dedup: &'a DedupCache,
+    key: String,
+    sent: bool,
+}
+
+impl Drop for ContactClaim<'_> {
+    fn drop(&mut self) {
+        if !self.sent {
+            self.dedup.remove(&self.key);
+        }
+    }
+}
+
+// Send the vCard to each recipient in turn. A failure is logged and the remaining recipients
+// are still tried.
+async fn fan_out_vcard(
+    worker: &Worker<impl MessageSender>,
+    message: &WhatsAppMessage,
+    contact: &VCard,
+    recipients: &[&str],
+) -> FanOutOutcome {
+    let from = message.from.as_str();
+    let mut outcome = FanOutOutcome { succeeded: 0, skipped: 0, last_error: None };
+    let config = worker.config.current();
+    let pacing = Pacing::new(&config, message.broadcast, recipients.len());
+    let mut sent = 0;
+    for (index, recipient) in recipients.iter().enumerate() {
+        if worker.suppressions.is_suppressed(recipient) {
+            tracing::info!(from = %redact::phone(from), recipient = %redact::phone(recipient), status = "suppressed", "Not sending vCard to {}, they opted out", redact::phone(recipient));
+            worker.metrics.suppressed_sends.inc();
+            continue;
+        }
+        // The same person sent to the same recipient again recently; redeliveries are exempt as
+        // the earlier send never arrived, and broadcasts repeat the same card by design. Kept in
+        // the webhook dedup cache, prefixed so a key can't collide with a messageId.
+        let contact_key = format!("contact\n{}\n{}", recipient.trim_start_matches('+'), contact.normalized_key());
+        let exempt = message.redelivery.is_some() || message.broadcast;
+        let window = Duration::from_secs(config.contact_dedup_window_secs);
+        if !exempt && !worker.dedup.insert_for(&contact_key, window) {
+            tracing::info!(
+                from = %redact::phone(from),
+                recipient = %redact::phone(recipient),
+                status = "duplicate",
+                "Not sending {} to {} again, an identical contact went out recently",
+                full_name(contact),
+                redact::phone(recipient)
+            );
+            worker.metrics.duplicate_contacts.inc();
+            outcome.skipped += 1;
+            continue;
+        }
+        let mut claim = ContactClaim { dedup: &worker.dedup, key: contact_key, sent: false };
+        // The rate limits still apply after the pause, so they stay the upper bound
+        pacing.wait(index, sent).await;
+        sent += 1;
+        // Wait on the recipient's own budget first so we don't hold a global token meanwhile
+        worker.recipient_limiter.acquire(recipient).await;
+        worker.limiter.acquire().await;
+        let send = Outbound {
+            contact,
+            recipient,
+            in_session: worker.sessions.in_window(recipient),
+            idempotency_key: delivery_key(message, recipient),
+            media: worker.media.as_ref(),
+        };
+        let send_span = tracing::info_span!("send", recipient = %redact::phone(recipient), outcome = tracing::field::Empty);
+        let result = send_vcard(&worker.client, &config, &worker.metrics, &send)
+            .instrument(send_span.clone())
+            .await;
+        send_span.record("outcome", if result.is_ok() { "sent" } else { "failed" });
+        // A long broadcast holds its worker, but isn't a stall
+        worker.note_progress();
+        match result {
+            Ok(()) => {
+                tracing::info!(from = %redact::phone(from), recipient = %redact::phone(recipient), status = "sent", "Sent vCard to {}", redact::phone(recipient));
+                outcome.succeeded += 1;
+                claim.sent = true;
+                record_delivery(worker, message, recipient, send.idempotency_key.as_deref());
+            }
+            Err(e) => {
+                tracing::warn!(from = %redact::phone(from), recipient = %redact::phone(recipient), status = "failed", "Failed to send vCard to {}: {}", redact::phone(recipient), e);
+                outcome.last_error = Some(e);
+            }
+        }
+    }
+    outcome
+}
+
+// The messageId for this message's send to `recipient`. Redeliveries need a key of their own,
+// Infobip would drop a repeat of the one that failed.
+fn delivery_key(message: &WhatsAppMessage, recipient: &str) -> Option<String> {
+    let message_id = message.message_id.as_deref()?;
+    Some(match &message.redelivery {
+        Some(redelivery) => idempotency_key(&format!("{}/{}", message_id, redelivery.attempt), recipient),
+        None => idempotency_key(message_id, recipient),
+    })
+}
+
+// Note a successful send in the persisted queue so its delivery report can be matched up
+fn record_delivery(worker: &Worker<impl MessageSender>, message: &WhatsAppMessage, recipient: &str, key: Option<&str>) {
+    let Some(store) = &worker.store else {
+        return;
+    };
+    let (queue_id, attempt) = match &message.redelivery {
+        Some(redelivery) => (Some(redelivery.queue_id), redelivery.attempt),
+        None => (message.queue_id, 0),
+    };
+    let (Some(queue_id), Some(key)) = (queue_id, key) else {
+        return;
+    };
+    let delivery = Delivery { queue_id, recipient: recipient.to_string(), attempt };
+    if let Err(e) = store.record_delivery(key, &delivery) {
+        error!("Failed to record delivery of message {} to {}: {}", queue_id, redact::phone(recipient), e);
+    }
+}
+
+// State shared by the worker tasks
+struct Worker<S> {
+    // Shared with the HTTP filters and the SIGHUP handler
+    config: Arc<ConfigHandle>,
+    client: S,
+    limiter: RateLimiter,
+    recipient_limiter: KeyedRateLimiter,
+    metrics: Arc<Metrics>,
+    sessions: SessionTracker,
+    confirmations: ConfirmationStore,
+    builder: ContactBuilder,
+    suppressions: SuppressionList,
+    directory: Option<ContactDirectory>,
+    // Shared with the webhook: inbound messageIds, and contacts recently sent to a recipient
+    dedup: Arc<DedupCache>,
+    store: Option<Arc<QueueStore>>,
+    inbound_log: Option<InboundLog>,
+    dead_letters: DeadLetterStore,
+    send_counts: SendCounter,
+    pending: Arc<PendingQueue>,
+    // MEDIA_URL, checked at startup
+    media: Option<Media>,
+    processed: Arc<AtomicUsize>,
+    // When a worker last took, finished or sent part of a message, for the watchdog
+    last_progress: std::sync::Mutex<Instant>,
+    // A broadcast is queued or being sent; the next one waits for it to finish
+    broadcast_running: AtomicBool,
+}
+
+// Times a message that panicked is processed again before it is given up on
+const PANIC_RETRIES: u32 = 2;
+
+// Take messages off the shared queue until it is closed and drained
+async fn run_worker<S: MessageSender + 'static>(worker: Arc<Worker<S>>, rx: Arc<tokio::sync::Mutex<LaneReceiver>>) {
+    loop {
+        // Only the receive holds the lock, so other workers can pick up the next message
+        let Some(message) = rx.lock().await.recv().await else {
+            break;
+        };
+        worker.note_progress();
+        if !worker.pending.take(message.pending_seq) {
+            info!("Dropping flushed message {} from {}", message.correlation_id, redact::phone(&message.from));
+            if let (Some(store), Some(queue_id)) = (&worker.store, message.queue_id)
+                && let Err(e) = store.mark_done(queue_id, QueueStatus::Failed)
+            {
+                error!("Failed to mark flushed message {} as failed: {}", queue_id, e);
+            }
+            worker.processed.fetch_add(1, Ordering::SeqCst);
+            continue;
+        }
+        let span = tracing::info_span!(
+            "message",
+            correlation_id = %message.correlation_id,
+            from = %redact::phone(&message.from),
+            outcome = tracing::field::Empty,
+        );
+        if let Some(context) = message.trace_context.clone() {
+            let _ = span.set_parent(context);
+        }
+        process_isolated(&worker, message).instrument(span).await;
+        worker.processed.fetch_add(1, Ordering::SeqCst);
+        worker.note_progress();
+    }
+}
+
+impl<S> Worker<S> {
+    fn note_progress(&self) {
+        *self.last_progress.lock().expect("progress lock poisoned") = Instant::now();
+    }
+}
+
+// The worker tasks, shared by main (to drain them at shutdown) and the watchdog (to replace them)
+type WorkerTasks = Arc<std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>>;
+
+// Every `interval`, check that messages waiting in the queue are being picked up. A stall is
+// logged and /ready reports not ready until progress resumes; with WATCHDOG_ACTION=respawn the
+// worker tasks are also aborted and started afresh. A message task that is itself stuck isn't
+// aborted, it runs on its own, but the new workers carry on with the rest of the queue.
+async fn run_watchdog<S: MessageSender + 'static>(
+    worker: Arc<Worker<S>>,
+    rx: Arc<tokio::sync::Mutex<LaneReceiver>>,
+    tasks: WorkerTasks,
+    depth_tx: WeakLaneSender,
+    ready: Arc<AtomicBool>,
+    interval: Duration,
+) {
+    let mut stalled = false;
+    loop {
+        tokio::time::sleep(interval).await;
+        // The queue is closed at shutdown, and the workers are expected to wind down
+        let Some(tx) = depth_tx.upgrade() else {
+            break;
+        };
+        let depth = tx.depth();
+        drop(tx);
+        let idle = worker.last_progress.lock().expect("progress lock poisoned").elapsed();
+        if depth == 0 || idle < interval {
+            if stalled {
+                info!("Workers are taking messages again, reporting ready");
+                ready.store(true, Ordering::SeqCst);
+                stalled = false;
+            }
+            continue;
+        }
+        if !stalled {
+            error!("No progress for {:?} with {} message(s) queued, the workers look stalled", idle, depth);
+            worker.metrics.worker_stalls.inc();
+            ready.store(false, Ordering::SeqCst);
+            stalled = true;
+        }
+        if worker.config.current().watchdog_action == WatchdogAction::Respawn {
+            let mut tasks = tasks.lock().expect("worker tasks lock poisoned");
+            for task in tasks.iter() {
+                task.abort();
+            }
+            let count = tasks.len();
+            *tasks = (0..count).map(|_| tokio::spawn(run_worker(worker.clone(), rx.clone()))).collect();
+            warn!("Restarted {} worker task(s)", count);
+            // They get a full interval to show progress before the next restart
+            worker.note_progress();
+        }
+    }
+}
+
+// Process the message in a task of its own, so a panic takes down only that task and the worker
+// carries on with the next message. One that keeps panicking is marked failed and dead-lettered.
+async fn process_isolated<S: MessageSender + 'static>(worker: &Arc<Worker<S>>, message: WhatsAppMessage) {
+    for attempt in 0..=PANIC_RETRIES {
+        let task_worker = worker.clone();
+        let task_message = message.clone();
+        let task = tokio::spawn(
+            async move { process_message(&task_worker, task_message).await }.instrument(tracing::Span::current()),
+        );
+        let panic = match task.await {
+            Ok(()) => return,
+            Err(e) => match e.try_into_panic() {
+                Ok(payload) => payload,
+                Err(e) => {
+                    error!("Message task was cancelled: {}", e);
+                    return;
+                }
+            },
+        };
+        worker.metrics.worker_panics.inc();
+        let reason = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        if attempt < PANIC_RETRIES {
+            warn!("Processing message from {} panicked ({}), trying again", redact::phone(&message.from), reason);
+        } else {
+            error!(
+                "Processing message from {} panicked {} times, giving up: {}",
+                redact::phone(&message.from),
+                PANIC_RETRIES + 1,
+                reason
+            );
+            tracing::Span::current().record("outcome", "panicked");
+            dead_letter(worker, &message, &format!("panicked: {}", reason));
+        }
+    }
+    // process_message never got to settle it
+    if message.broadcast {
+        worker.broadcast_running.store(false, Ordering::SeqCst);
+    }
+    if let (Some(store), Some(id)) = (&worker.store, message.queue_id)
+        && let Err(e) = store.mark_done(id, QueueStatus::Failed)
+    {
+        error!("Failed to update queued message {}: {}", id, e);
+    }
+}
+
+// Log, handle and settle one message from the queue
+async fn process_message<S: MessageSender>(worker: &Worker<S>, message: WhatsAppMessage) {
+    // Broadcasts were never received, so they have no place in the inbound log
+    if let Some(inbound_log) = &worker.inbound_log
+        && !message.broadcast
+    {
+        let trigger = message.text.as_deref().and_then(|text| matched_trigger(&worker.config.current(), text));
+        if let Err(e) = inbound_log.record(&message, trigger.as_deref()) {
+            error!("Failed to log inbound message from {}: {}", redact::phone(&message.from), e);
+        }
+    }
+    let queue_id = message.queue_id;
+    let from = message.from.clone();
+    let broadcast = message.broadcast;
+    let original = message.clone();
+    let result = handle_webhook(message, worker).await;
+    if broadcast {
+        worker.broadcast_running.store(false, Ordering::SeqCst);
+    }
+    if let (Some(store), Some(id)) = (&worker.store, queue_id) {
+        let status = if result.is_ok() { QueueStatus::Sent } else { QueueStatus::Failed };
+        if let Err(e) = store.mark_done(id, status) {
+            error!("Failed to update queued message {}: {}", id, e);
+        }
+    }
+    let Err(e) = result else {
+        tracing::Span::current().record("outcome", "ok");
+        return;
+    };
+    let outcome = match &e {
+        // The sender already got a usage hint, nothing more to do
+        e @ (BotError::Parse(_) | BotError::FieldTooLong { .. }) => {
+            tracing::info!(from = %redact::phone(&from), status = "unparseable", "Dropped unparseable contact command: {}", e);
+            "unparseable"
+        }
+        e if e.is_transient() => {
+            tracing::warn!(from = %redact::phone(&from), status = "dropped", "Dropping message after retries were exhausted: {}", e);
+            "dropped"
+        }
+        e => {
+            tracing::error!(from = %redact::phone(&from), status = "failed", "Error sending vCard: {}", e);
+            "failed"
+        }
+    };
+    tracing::Span::current().record("outcome", outcome);
+    dead_letter(worker, &original, &e.to_string());
+}
+
+// Keep a message we gave up on, for inspection and replay
+fn dead_letter(worker: &Worker<impl MessageSender>, message: &WhatsAppMessage, reason: &str) {
+    match worker.dead_letters.record(message, reason) {
+        Ok(id) => info!("Message from {} dead-lettered as #{}", redact::phone(&message.from), id),
+        Err(e) => error!("Failed to dead-letter message from {}: {}", redact::phone(&message.from), e),
+    }
+}
+
+// The broadcast contact to every configured recipient, for a message queued by the schedule
+async fn send_broadcast(worker: &Worker<impl MessageSender>, message: &WhatsAppMessage) -> Result<(), BotError> {
+    let config = worker.config.current();
+    let Some(text) = config.broadcast_contact.as_deref() else {
+        warn!("BROADCAST_CONTACT was removed, dropping the queued broadcast");
+        return Ok(());
+    };
+    let contact = resolve_contact(worker, text)?;
+    let recipients: Vec<&str> = config.recipient_phone_numbers.iter().map(String::as_str).collect();
+    let outcome = fan_out_vcard(worker, message, &contact, &recipients).await;
+    info!("Broadcast of {} reached {}/{} recipients", full_name(&contact), outcome.succeeded, recipients.len());
+    match outcome.last_error {
+        Some(e) if outcome.succeeded == 0 => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Queue a broadcast each time the schedule fires. It goes through the worker like any message,
+// so the rate limits, suppressions and retries apply. A run is skipped while the previous one
+// is still queued or sending.
+async fn run_broadcasts<S: MessageSender>(schedule: cron::Schedule, worker: Arc<Worker<S>>, tx: WeakLaneSender) {
+    while let Some((fire_at, wait)) = next_fire(&schedule) {
+        tokio::time::sleep(wait).await;
+        // Holding only a weak sender lets the queue close at shutdown
+        let Some(tx) = tx.upgrade() else {
+            break;
+        };
+        if worker.broadcast_running.swap(true, Ordering::SeqCst) {
+            warn!("Skipping the {} broadcast, the previous one is still going out", fire_at.to_rfc3339());
+            continue;
+        }
+        // The fire time makes each run's idempotency keys unique and stable
+        let id = format!("broadcast-{}", fire_at.timestamp());
+        let mut message = WhatsAppMessage {
+            from: "broadcast".to_string(),
+            text: None,
+            message_id: Some(id.clone()),
+            location: None,
+            quoted_message_id: None,
+            correlation_id: id,
+            queue_id: None,
+            redelivery: None,
+            broadcast: true,
+            ack: None,
+            trace_context: None,
+            pending_seq: 0,
+        };
+        let _span = tracing::info_span!("message", correlation_id = %message.correlation_id).entered();
+        message.trace_context = Some(tracing::Span::current().context());
+        if let Some(store) = &worker.store {
+            match store.enqueue(&message) {
+                Ok(queue_id) => message.queue_id = Some(queue_id),
+                Err(e) => error!("Failed to persist the broadcast, queueing it anyway: {}", e),
+            }
+        }
+        match enqueue_message(&tx, Lane::Normal, &worker.metrics, &worker.pending, message) {
+            Ok(()) => info!("Queued the {} broadcast", fire_at.to_rfc3339()),
+            Err(e) => {
+                warn!("Could not queue the {} broadcast: {}", fire_at.to_rfc3339(), e);
+                worker.broadcast_running.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+// Run a startup step up to `attempts` times, `delay` apart. For resources that can be briefly
+// unavailable while a deploy is rolling over, like a database still locked by the old instance.
+// Returns the last error once the attempts run out.
+async fn retry_startup<T>(
+    what: &str,
+    attempts: u32,
+    delay: Duration,
+    mut init: impl FnMut() -> Result<T, BotError>,
+) -> Result<T, BotError> {
+    let mut attempt = 1;
+    loop {
+        match init() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < attempts => {
+                warn!("Failed to open {} (attempt {}/{}), retrying in {:?}: {}", what, attempt, attempts, delay, e);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// The service without its listener: the routes, and the worker behind them already taking
+// messages off the queue. Tests can build one around a mock sender and drive the routes with
+// warp::test.
+struct App<S> {
+    routes: BoxedFilter<(warp::reply::Response,)>,
+    worker: Arc<Worker<S>>,
+    // Kept only to measure the queue depth at shutdown; the routes hold the other senders
+    queue_tx: LaneSender,
+    // For main to dead-letter what is still queued when the drain times out
+    queue_rx: Arc<tokio::sync::Mutex<LaneReceiver>>,
+    workers: WorkerTasks,
+    // Set by main once the listener is bound; cleared by the watchdog while the workers are stalled
+    ready: Arc<AtomicBool>,
+}
+
+// One access log line per request, under the tool::access target so it can be filtered on its
+// own. The path leaves out the query string, where a verify token or contact details may be.
+fn log_request(info: warp::log::Info) {
+    let correlation_id = info
+        .request_headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("-");
+    tracing::info!(
+        target: concat!(env!("CARGO_CRATE_NAME"), "::access"),
+        method = %info.method(),
+        path = info.path(),
+        status = info.status().as_u16(),
+        duration_ms = info.elapsed().as_secs_f64() * 1000.0,
+        correlation_id,
+        "{} {} {} in {:?}",
+        info.method(),
+        info.path(),
+        info.status().as_u16(),
+        info.elapsed()
+    );
+}
+
+// Open a database for one of the stores, retrying per STARTUP_RETRY_ATTEMPTS
+async fn open_database<T>(
+    what: &str,
+    config: &some_module::Config,
+    open: impl FnMut() -> Result<T, BotError>,
+) -> Result<T, BotError> {
+    let attempts = config.startup_retry_attempts;
+    retry_startup(what, attempts, Duration::from_secs(config.startup_retry_delay_secs), open)
+        .await
+        .map_err(|e| {
+            BotError::Config(format!(
+                "failed to open {} {} after {} attempt(s): {}",
+                what, config.database_url, attempts, e
+            ))
+        })
+}
+
+// Open the stores, start the worker tasks and assemble the routes around `client`
+async fn build_app<S: MessageSender + 'static>(
+    config: some_module::Config,
+    client: S,
+    metrics: Arc<Metrics>,
+) -> Result<App<S>, BotError> {
+    let pending = Arc::new(PendingQueue::new());
+    // Only read at startup; everything else goes through the shared config handle
+    let max_body_bytes = config.max_body_bytes;
+    let webhook_path = config.webhook_path.clone();
+
+    let store = if config.persist_queue {
+        Some(Arc::new(open_database("queue database", &config, || QueueStore::open(&config.database_url)).await?))
+    } else {
+        None
+    };
+    let inbound_log = if config.log_inbound {
+        Some(open_database("inbound log database", &config, || InboundLog::open(&config.database_url)).await?)
+    } else {
+        None
+    };
+    let media = match &config.media_url {
+        Some(url) => {
+            let media = media::probe(url, Duration::from_secs(config.send_timeout_secs)).await?;
+            info!("Sending {:?} {} ahead of each contact", media.kind, media.url);
+            Some(media)
+        }
+        None => None,
+    };
+    let directory = match &config.contacts_csv {
+        Some(path) => {
+            let directory = ContactDirectory::load(path, FieldLimits::from_config(&config))?;
+            info!("Loaded {} contact(s) from {}", directory.len(), path);
+            Some(directory)
+        }
+        None => None,
+    };
+    // Opt-outs must be honoured whatever else is enabled, so this database is always opened
+    let suppressions = open_database("suppression list database", &config, || SuppressionList::open(&config.database_url)).await?;
+    let dead_letters = open_database("dead letter database", &config, || DeadLetterStore::open(&config.database_url)).await?;
+    let send_counts = open_database("daily send counts database", &config, || SendCounter::open(&config.database_url)).await?;
+    // Anything still pending was queued before the last shutdown or crash
+    let mut recovered = Vec::new();
+    if let Some(store) = &store {
+        let mut last_id = 0;
+        loop {
+            match store.next_pending(last_id) {
+                Ok(Some((id, message))) => {
+                    last_id = id;
+                    recovered.push(message);
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Failed to read pending messages: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    let dedup = Arc::new(DedupCache::new(
+        Duration::from_secs(config.dedup_window_secs),
+        config.dedup_capacity,
+    ));
+    // Recovered messages count as seen so a late redelivery doesn't send them twice
+    for message in &recovered {
+        if let Some(id) = &message.message_id {
+            dedup.insert(id);
+        }
+    }
+
+    let (tx, rx) = lanes(config.queue_capacity);
+    let queue_tx = tx.clone();
+    let depth_tx = tx.downgrade();
+    let ready = Arc::new(AtomicBool::new(false));
+
+    //Spawn worker_count tasks that share the queue; the limiters are shared too, so the total
+    //send rate stays bounded however many workers there are
+    let worker_count = config.worker_count;
+    let shared_config = Arc::new(ConfigHandle::new(config.clone()));
+    let worker = Arc::new(Worker {
+        limiter: RateLimiter::new(config.rate_per_second, config.burst_size),
+        recipient_limiter: KeyedRateLimiter::new(
+            config.per_recipient_rate_per_minute / 60.0,
+            config.per_recipient_burst,
+            Duration::from_secs(config.per_recipient_idle_ttl_secs),
+        ),
+        confirmations: ConfirmationStore::new(Duration::from_secs(config.confirmation_ttl_secs)),
+        suppressions,
+        directory,
+        dedup: dedup.clone(),
+        builder: ContactBuilder::new(Duration::from_secs(config.guided_flow_ttl_secs)),
+        config: shared_config.clone(),
+        client,
+        metrics: metrics.clone(),
+        sessions: SessionTracker::new(SESSION_WINDOW),
+        store: store.clone(),
+        inbound_log,
+        dead_letters,
+        processed: Arc::new(AtomicUsize::new(0)),
+        last_progress: std::sync::Mutex::new(Instant::now()),
+        broadcast_running: AtomicBool::new(false),
+        media,
+        send_counts,
+        pending: pending.clone(),
+    });
+    let rx = Arc::new(tokio::sync::Mutex::new(rx));
+    let workers: WorkerTasks = Arc::new(std::sync::Mutex::new(
+        (0..worker_count).map(|_| tokio::spawn(run_worker(worker.clone(), rx.clone()))).collect(),
+    ));
+    if let Some(interval) = config.watchdog_interval_secs {
+        tokio::spawn(run_watchdog(
+            worker.clone(),
+            rx.clone(),
+            workers.clone(),
+            tx.downgrade(),
+            ready.clone(),
+            Duration::from_secs(interval),
+        ));
+    }
+    if let Some(expression) = &config.broadcast_schedule {
+        let schedule = parse_schedule(expression).expect("validated in load_config");
+        tokio::spawn(run_broadcasts(schedule, worker.clone(), tx.downgrade()));
+    }
+    if !recovered.is_empty() {
+        info!("Recovered {} pending message(s) from the persisted queue", recovered.len());
+        let recovery_tx = tx.clone();
+        let recovery_pending = pending.clone();
+        tokio::spawn(async move {
+            for mut message in recovered {
+                message.pending_seq = recovery_pending.track(&message);
+                if recovery_tx.lane(Lane::Normal).send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    let status_tx = tx.clone();
+    let replay_tx = tx.clone();
+    let status_store = store.clone();
+    let status_metrics = metrics.clone();
+    let status_pending = pending.clone();
+    let status_config = shared_config.clone();
+    let status = warp::post()
+        .and(warp::path("status"))
+        .and(warp::path::end())
+        .and(verified_body(shared_config.clone(), max_body_bytes))
+        .and_then(parse_body::<DeliveryReports>)
+        .and(warp::any().map(move || status_tx.clone()))
+        .and(warp::any().map(move || status_store.clone()))
+        .and(warp::any().map(move || status_metrics.clone()))
+        .and(warp::any().map(move || status_pending.clone()))
+        .and(warp::any().map(move || {
+            let config = status_config.current();
+            config.retry_on_failed_delivery.then_some(config.max_retries)
+        }))
+        .and_then(receive_delivery_reports);
+    let webhook_worker = worker.clone();
+    let webhook_config = shared_config.clone();
+    let webhook = warp::post()
+        .and(route_path(&webhook_path))
+        .and(verified_body(shared_config.clone(), max_body_bytes))
+        .and_then(parse_body::<InboundWebhook>)
+        .and(warp::any().map(move || tx.clone()))
+        .and(warp::any().map(move || dedup.clone()))
+        .and(warp::any().map(move || webhook_worker.clone()))
+        .and(warp::any().map(move || webhook_config.current()))
+        .and(warp::header::headers_cloned())
+        .and_then(enqueue_webhook);
+    let verification = warp::get()
+        .and(route_path(&webhook_path))
+        .and(warp::query::<HashMap<String, String>>())
+        .and({
+            let config = shared_config.clone();
+            warp::any().map(move || config.current().verify_token.clone())
+        })
+        .map(verify_webhook);
+    let metrics_route = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .and(admin_auth(shared_config.clone(), false))
+        .map(move || {
+            // A weak handle, so the route doesn't keep the queue open at shutdown
+            if let Some(tx) = depth_tx.upgrade() {
+                metrics.queue_depth.set(tx.depth() as i64);
+            }
+            warp::reply::with_header(metrics.render(), "Content-Type", "text/plain; version=0.0.4")
+        });
+    let health = warp::get()
+        .and(warp::path("health"))
+        .and(warp::path::end())
+        .map(|| warp::reply::with_status("OK", warp::http::StatusCode::OK));
+    let info = warp::get()
+        .and(warp::path("info"))
+        .and(warp::path::end())
+        .and(admin_auth(shared_config.clone(), false))
+        .and({
+            let config = shared_config.clone();
+            warp::any().map(move || config.current())
+        })
+        .map(|config: Arc<some_module::Config>| warp::reply::json(&BuildInfo::new(&config)));
+    let selftest_worker = worker.clone();
+    let selftest = warp::post()
+        .and(warp::path("selftest"))
+        .and(warp::path::end())
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::any().map(move || selftest_worker.clone()))
+        .and_then(run_selftest);
+    let dead_letter_worker = worker.clone();
+    let dead_letter_route = warp::get()
+        .and(warp::path("deadletters"))
+        .and(warp::path::end())
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::query::<DeadLetterQuery>())
+        .and(warp::any().map(move || dead_letter_worker.clone()))
+        .and_then(list_dead_letters);
+    let replay_worker = worker.clone();
+    let replay_route = warp::post()
+        .and(warp::path!("deadletters" / i64 / "replay"))
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::any().map(move || replay_worker.clone()))
+        .and(warp::any().map(move || replay_tx.clone()))
+        .and_then(replay_dead_letter);
+    let queue_pending = pending.clone();
+    let queue_route = warp::get()
+        .and(warp::path("queue"))
+        .and(warp::path::end())
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::query::<QueueQuery>())
+        .and(warp::any().map(move || queue_pending.clone()))
+        .map(queue_status);
+    let flush_pending = pending.clone();
+    let flush_route = warp::post()
+        .and(warp::path!("queue" / "flush"))
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::query::<FlushQuery>())
+        .and(warp::any().map(move || flush_pending.clone()))
+        .map(flush_queue);
+    let parse_test_worker = worker.clone();
+    let parse_test_route = warp::post()
+        .and(warp::path!("test" / "parse"))
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(warp::body::bytes())
+        .and_then(parse_body::<ParseTestRequest>)
+        .and(warp::any().map(move || parse_test_worker.clone()))
+        .and_then(parse_test);
+    let contacts_worker = worker.clone();
+    let contacts_list = warp::get()
+        .and(warp::path("contacts"))
+        .and(warp::path::end())
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::any().map(move || contacts_worker.clone()))
+        .and_then(list_contacts);
+    let contact_worker = worker.clone();
+    let contact_get = warp::get()
+        .and(warp::path!("contacts" / String))
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::any().map(move || contact_worker.clone()))
+        .and_then(get_contact);
+    let contact_put_worker = worker.clone();
+    let contact_put = warp::post()
+        .and(warp::path("contacts"))
+        .and(warp::path::end())
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(warp::body::bytes())
+        .and_then(parse_body::<Entry>)
+        .and(warp::any().map(move || contact_put_worker.clone()))
+        .and_then(put_contact);
+    let contact_delete_worker = worker.clone();
+    let contact_delete = warp::delete()
+        .and(warp::path!("contacts" / String))
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::any().map(move || contact_delete_worker.clone()))
+        .and_then(delete_contact);
+    let qr_worker = worker.clone();
+    let qr = warp::get()
+        .and(warp::path("qr"))
+        .and(warp::path::end())
+        .and(admin_auth(shared_config.clone(), true))
+        .and(warp::query::<ContactQuery>())
+        .and(warp::any().map(move || qr_worker.clone()))
+        .and_then(qr_code);
+    let vcard_worker = worker.clone();
+    let vcard_route = warp::get()
+        .and(warp::path("vcard"))
+        .and(warp::path::end())
+        .and(admin_auth(shared_config.clone(), true))
+        .and(warp::query::<ContactQuery>())
+        .and(warp::any().map(move || vcard_worker.clone()))
+        .and_then(vcard_file);
+    let vcard_batch_worker = worker.clone();
+    let vcard_batch_route = warp::get()
+        .and(warp::path!("vcard" / "batch"))
+        .and(admin_auth(shared_config.clone(), false))
+        .and(warp::query::<BatchQuery>())
+        .and(warp::any().map(move || vcard_batch_worker.clone()))
+        .and_then(vcard_batch);
+    let ready_state = ready.clone();
+    let readiness_probe = warp::get()
+        .and(warp::path("ready"))
+        .and(warp::path::end())
+        .map(move || readiness(ready_state.clone()));
+    let admin_routes = info
+        .or(metrics_route)
+        .or(selftest)
+        .or(qr)
+        .or(vcard_route)
+        .or(vcard_batch_route)
+        .or(dead_letter_route)
+        .or(replay_route)
+        .or(queue_route)
+        .or(flush_route)
+        .or(parse_test_route)
+        .or(contacts_list)
+        .or(contact_get)
+        .or(contact_put)
+        .or(contact_delete)
+        .map(Reply::into_response)
+        .boxed();
+    let admin_routes = match admin_cors(&config) {
+        Some(cors) => admin_path().and(admin_routes.with(cors)).map(Reply::into_response).boxed(),
+        None => admin_routes,
+    };
+    let routes = health
+        .or(readiness_probe)
+        .or(verification)
+        .or(webhook)
+        .or(status)
+        .or(admin_routes)
+        .recover(handle_rejection)
+        .with(warp::log::custom(log_request))
+        .map(Reply::into_response)
+        .boxed();
+
+    Ok(App { routes, worker, queue_tx, queue_rx: rx, workers, ready })
+}
+
+// Certificate and key to serve HTTPS with; plain HTTP without them. load_config has checked
+// they are set together.
+fn tls_paths(config: &some_module::Config) -> Option<(String, String)> {
+    config.tls_cert_path.clone().zip(config.tls_key_path.clone())
+}
+
+// The API key as load_config found it, read from the key file when that is how it is given
+fn api_key(config: &some_module::Config) -> Result<String, BotError> {
+    match (&config.infobip_api_key_file, &config.infobip_api_key) {
+        (Some(path), _) => read_key_file(path),
+        (None, Some(key)) => Ok(key.clone()),
+        (None, None) => Err(BotError::Config("INFOBIP_API_KEY or INFOBIP_API_KEY_FILE must be set".to_string())),
+    }
+}
+
+// The key to start with. A key file can be missing for a moment while a secret store mounts
+// it, so its read is retried like the database opens.
+async fn startup_api_key(config: &some_module::Config) -> Result<String, BotError> {
+    let delay = Duration::from_secs(config.startup_retry_delay_secs);
+    retry_startup("the API key file", config.startup_retry_attempts, delay, || api_key(config)).await
+}
+
+// Where a refused key is read again from: the key file, or else the settings, of which only the
+// config file can have changed since startup
+fn key_source(cli: &Cli, config: &some_module::Config) -> Option<KeySource> {
+    if !config.refresh_credentials_on_auth_error {
+        return None;
+    }
+    Some(match config.infobip_api_key_file.clone() {
+        Some(path) => Box::new(move || read_key_file(&path)),
+        None => {
+            let cli = cli.clone();
+            Box::new(move || load_settings(&cli)?.required("INFOBIP_API_KEY"))
+        }
+    })
+}
+
+// Look up each sender number's templates, which Infobip refuses for a number that isn't
+// provisioned on the account. Skipped for dry runs; only a strict check fails startup.
+async fn check_senders(client: &impl MessageSender, config: &some_module::Config) -> Result<(), BotError> {
+    if !config.startup_checks || config.dry_run {
+        return Ok(());
+    }
+    for sender in config.sender_router.senders() {
+        match client.check_sender(sender).await {
+            Ok(()) => info!("Sender {} is registered with Infobip", sender),
+            Err(e) if config.strict_startup_checks => {
+                return Err(BotError::Config(format!("sender {} failed the startup check: {}", sender, e)));
+            }
+            Err(e) => warn!("Sender {} failed the startup check, sends from it will probably fail: {}", sender, e),
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), BotError> {
+    dotenv().ok();
+    let cli = Cli::parse();
+    let config = load_settings(&cli).and_then(|settings| load_config(&settings)).inspect_err(|e| {
+        // No usable config, so fall back to plain text to report why
+        init_logging(LogFormat::Text, None, None);
+        error!("{}", e);
+    })?;
+    redact::set_enabled(config.redact_pii);
+    let tracer_provider = init_logging(config.log_format, config.log_level, config.otlp_endpoint.as_deref());
+    info!("Starting WhatsApp contact adder with trigger words: {}", config.trigger_words.join(", "));
+    warn_duplicate_triggers(&config);
+
+    //Initializes infobip wozap client
+    let api_key = startup_api_key(&config).await.inspect_err(|e| error!("{}", e))?;
+    let read_key = key_source(&cli, &config);
+    let metrics = Arc::new(Metrics::new());
+    let client = CircuitBreaker::new(
+        InFlightLimit::new(
+            TimeoutSender::new(
+                RefreshingClient::new(config.infobip_base_url.clone(), api_key, read_key),
+                Duration::from_secs(config.send_timeout_secs),
+            ),
+            config.max_in_flight,
+        ),
+        config.breaker_failure_threshold,
+        Duration::from_secs(config.breaker_cooldown_secs),
+        metrics.circuit_breaker_state.clone(),
+    );
+
+    if config.dry_run {
+        warn!("DRY_RUN is on: messages will be logged, nothing will be sent to WhatsApp");
+    }
+    check_senders(&client, &config).await.inspect_err(|e| error!("{}", e))?;
+    if config.debug_echo {
+        warn!("DEBUG_ECHO is on: webhook responses include the parsed messages");
+    }
+    if config.admin_token.is_none() {
+        warn!(
+            "ADMIN_TOKEN is not set, /info, /metrics, /selftest, /deadletters, /queue, /test/parse, /contacts and /vcard/batch are disabled and /qr and /vcard won't look up aliases"
+        );
+    }
+    if config.webhook_secret.is_none() {
+        warn!("WEBHOOK_SECRET is not set, webhook signatures will not be verified");
+    }
+    let addr = listen_addr(&config.bind_address, config.port).inspect_err(|e| error!("{}", e))?;
+    let webhook_path = config.webhook_path.clone();
+    let tls_paths = tls_paths(&config);
+    let App { routes, worker, queue_tx, queue_rx, workers, ready } =
+        build_app(config, client, metrics).await.inspect_err(|e| error!("{}", e))?;
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(cli, worker.clone()));
+
+    // Both modes shut down the same way: stop accepting on the signal, finish in-flight requests
+    let bound = match &tls_paths {
+        Some((cert_path, key_path)) => warp::serve(routes)
+            .tls()
+            .cert_path(cert_path)
+            .key_path(key_path)
+            .try_bind_with_graceful_shutdown(addr, shutdown_signal())
+            .map(|(addr, server)| (addr, Box::pin(server) as Pin<Box<dyn Future<Output = ()>>>)),
+        None => warp::serve(routes)
+            .try_bind_with_graceful_shutdown(addr, shutdown_signal())
+            .map(|(addr, server)| (addr, Box::pin(server) as Pin<Box<dyn Future<Output = ()>>>)),
+    };
+    let (addr, server) = bound
+        .map_err(|e| BotError::Config(format!("failed to bind {}: {}", addr, e)))
+        .inspect_err(|e| error!("{}", e))?;
+    // Configuration is loaded, the client is built and the worker is running
+    ready.store(true, Ordering::SeqCst);
+    let scheme = if tls_paths.is_some() { "https" } else { "http" };
+    info!(
+        "WhatsApp contact adder is running on {}://{}, webhook at /{}...",
+        scheme,
+        addr,
+        webhook_path.trim_matches('/')
+    );
+    server.await;
+
+    let drain_timeout = Duration::from_secs(worker.config.current().drain_timeout_secs);
+    let clean = drain(&worker, queue_tx, &workers, &queue_rx, drain_timeout).await;
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        warn!("Failed to flush the last spans to the OTLP endpoint: {}", e);
+    }
+    // Orchestrators can tell a clean drain from one that gave up by the exit code
+    if !clean {
+        return Err(BotError::DrainTimeout(drain_timeout));
+    }
+    Ok(())
+}
+
+// Let the workers finish what is queued, for up to `drain_timeout`; what they don't get to is
+// dead-lettered. True when the queue drained in time.
+async fn drain(
+    worker: &Worker<impl MessageSender>,
+    queue_tx: LaneSender,
+    workers: &WorkerTasks,
+    queue_rx: &tokio::sync::Mutex<LaneReceiver>,
+    drain_timeout: Duration,
+) -> bool {
+    // The server has stopped taking requests and dropped its senders; once ours is gone too
+    // the worker sees the channel close after it has drained what is left
+    let pending = queue_tx.depth();
+    drop(queue_tx);
+    let processed_before = worker.processed.load(Ordering::SeqCst);
+    info!("Draining {} queued message(s), waiting up to {:?}", pending, drain_timeout);
+
+    let handles = std::mem::take(&mut *workers.lock().expect("worker tasks lock poisoned"));
+    let aborts: Vec<_> = handles.iter().map(|handle| handle.abort_handle()).collect();
+    let join_all = async {
+        for handle in handles {
+            if let Err(e) = handle.await {
+                error!("Worker task failed: {}", e);
+            }
+        }
+    };
+    let timed_out = tokio::time::timeout(drain_timeout, join_all).await.is_err();
+    let drained = (worker.processed.load(Ordering::SeqCst) - processed_before).min(pending);
+    if timed_out {
+        // Stop the workers taking more, then keep what they didn't get to for a replay
+        for abort in aborts {
+            abort.abort();
+        }
+        let dead_lettered = dead_letter_remaining(worker, queue_rx).await;
+        warn!(
+            "Drain timeout reached: drained {} message(s), dead-lettered {}, abandoned {} in flight",
+            drained,
+            dead_lettered,
+            pending.saturating_sub(drained + dead_lettered)
+        );
+    } else {
+        info!("Shutdown complete: drained {} message(s), dropped 0", drained);
+    }
+    !timed_out
+}
+
+// Dead-letter every message still in the queue, marking any persisted copy failed so it isn't
+// recovered on the next start as well. Returns how many there were.
+async fn dead_letter_remaining(worker: &Worker<impl MessageSender>, rx: &tokio::sync::Mutex<LaneReceiver>) -> usize {
+    let mut rx = rx.lock().await;
+    let mut count = 0;
+    while let Some(message) = rx.try_recv() {
+        if !worker.pending.take(message.pending_seq) {
+            continue;
+        }
+        if let (Some(store), Some(queue_id)) = (&worker.store, message.queue_id)
+            && let Err(e) = store.mark_done(queue_id, QueueStatus::Failed)
+        {
+            error!("Failed to mark queued message {} as failed: {}", queue_id, e);
+        }
+        dead_letter(worker, &message, "not processed before the drain timeout");
+        count += 1;
+    }
+    count
+}
+
+// Reload the configuration on every SIGHUP for as long as the process runs
+#[cfg(unix)]
+async fn reload_on_sighup<S: MessageSender>(cli: Cli, worker: Arc<Worker<S>>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP, config reload is unavailable: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading configuration");
+        reload_config(&cli, &worker).await;
+    }
+}
+
+// Load the config from scratch and swap it in. A config that fails to load or validate is
+// rejected as a whole and the running one stays.
+async fn reload_config(cli: &Cli, worker: &Worker<impl MessageSender>) {
+    let next = match load_settings(cli).and_then(|settings| load_config(&settings)) {
+        Ok(next) => next,
+        Err(e) => {
+            error!("Config reload failed, keeping the running configuration: {}", e);
+            return;
+        }
+    };
+    warn_duplicate_triggers(&next);
+    let reload = worker.config.replace(next);
+    // The limiters were built from the old rates
+    let config = worker.config.current();
+    redact::set_enabled(config.redact_pii);
+    worker.limiter.set_rate(config.rate_per_second, config.burst_size).await;
+    worker
+        .recipient_limiter
+        .set_rate(config.per_recipient_rate_per_minute / 60.0, config.per_recipient_burst)
+        .await;
+    if !reload.needs_restart.is_empty() {
+        warn!("Not applied until restart: {}", reload.needs_restart.join(", "));
+    }
+    if reload.changed.is_empty() {
+        info!("Configuration reloaded, nothing changed");
+    } else {
+        info!("Configuration reloaded, changed: {}", reload.changed.join(", "));
+    }
+}
+
+// Resolves on SIGINT or SIGTERM so the server can stop accepting new requests
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutdown signal received, no longer accepting webhooks");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sender::tests::MockSender;
+
+    pub const SENDER: &str = "+15557654321";
+
+    fn test_settings(settings: &[(&'static str, &str)]) -> Settings {
+        let mut overrides: HashMap<&'static str, String> = HashMap::from([
+            ("INFOBIP_API_KEY", "test-key".to_string()),
+            ("INFOBIP_BASE_URL", "http://127.0.0.1:9".to_string()),
+            ("WHATSAPP_PHONE_NUMBER_ID", "447860099299".to_string()),
+            ("RECIPIENT_PHONE_NUMBER", "+15551234567".to_string()),
+            ("DATABASE_URL", ":memory:".to_string()),
+            // Out of the way unless a test is about the limits
+            ("RATE_PER_SECOND", "1000".to_string()),
+            ("BURST_SIZE", "100".to_string()),
+            ("PER_RECIPIENT_RATE_PER_MINUTE", "60000".to_string()),
+            ("PER_RECIPIENT_BURST", "100".to_string()),
+        ]);
+        overrides.extend(settings.iter().map(|(name, value)| (*name, value.to_string())));
+        Settings::new(overrides)
+    }
+
+    pub fn test_config(settings: &[(&'static str, &str)]) -> some_module::Config {
+        load_config(&test_settings(settings)).expect("test config")
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let config = test_config(&[("MAX_RETRY_AFTER_SECS", "10")]);
+        let limited = |secs| BotError::RateLimited { message: "slow down".to_string(), retry_after: Some(Duration::from_secs(secs)) };
+        assert_eq!(retry_delay(&limited(3), &config, 0), Duration::from_secs(3));
+        assert_eq!(retry_delay(&limited(300), &config, 0), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn retry_without_retry_after_backs_off() {
+        let config = test_config(&[("BASE_BACKOFF_MS", "100")]);
+        let limited = BotError::RateLimited { message: "slow down".to_string(), retry_after: None };
+        let delay = retry_delay(&limited, &config, 2);
+        assert!(delay >= Duration::from_millis(400) && delay <= Duration::from_millis(500), "{:?}", delay);
+    }
+
+    pub fn jane() -> VCard {
+        let phone = PhoneNumber { number: "+15551230000".to_string(), kind: PhoneKind::Cell };
+        VCard::with_phone_numbers("Jane".to_string(), "Smith".to_string(), vec![phone]).unwrap()
+    }
+
+    // Property name (with parameters) to value, for each content line of a generated card
+    fn vcard_lines(vcard: &str) -> Vec<(String, String)> {
+        vcard
+            .lines()
+            .map(|line| {
+                let (property, value) = line.split_once(':').expect("content line has a colon");
+                (property.to_string(), value.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vcard_round_trips_email_organization_and_address() {
+        let mut contact = jane();
+        contact.email = Some("jane@example.com".to_string());
+        contact.organization = Some("Acme".to_string());
+        contact.address = Some(Address {
+            street: "1 Main St".to_string(),
+            city: "Springfield".to_string(),
+            region: "IL".to_string(),
+            postal_code: "62701".to_string(),
+            country: "USA".to_string(),
+        });
+        let lines = vcard_lines(&generate_vcard(&contact, VCardVersion::V3_0));
+        let expected = [
+            ("BEGIN", "VCARD"),
+            ("VERSION", "3.0"),
+            ("N", "Smith;Jane"),
+            ("TEL;TYPE=CELL", "+15551230000"),
+            ("EMAIL", "jane@example.com"),
+            ("ORG", "Acme"),
+            ("ADR", ";;1 Main St;Springfield;IL;62701;USA"),
+            ("END", "VCARD"),
+        ];
+        let expected: Vec<(String, String)> =
+            expected.iter().map(|(property, value)| (property.to_string(), value.to_string())).collect();
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn vcard_leaves_out_unset_fields() {
+        let vcard = generate_vcard(&jane(), VCardVersion::V3_0);
+        assert!(!vcard.contains("EMAIL") && !vcard.contains("ORG") && !vcard.contains("ADR"), "{}", vcard);
+    }
+
+    async fn test_app(settings: &[(&'static str, &str)]) -> App<MockSender> {
+        build_app(test_config(settings), MockSender::new(), Arc::new(Metrics::new())).await.expect("test app")
+    }
+
+    // An Infobip inbound webhook body carrying one text message
+    pub fn inbound(message_id: &str, text: &str) -> serde_json::Value {
+        serde_json::json!({
+            "results": [{
+                "from": SENDER,
+                "messageId": message_id,
+                "message": { "type": "TEXT", "text": text },
+            }]
+        })
+    }
+
+    pub fn text_message(message_id: &str, text: &str) -> WhatsAppMessage {
+        let webhook: InboundWebhook = serde_json::from_value(inbound(message_id, text)).unwrap();
+        webhook.results.into_iter().next().unwrap().into()
+    }
+
+    fn webhook_request(path: &str, body: &serde_json::Value) -> warp::test::RequestBuilder {
+        warp::test::request()
+            .method("POST")
+            .path(path)
+            .header("content-type", "application/json")
+            .body(body.to_string())
+    }
+
+    async fn post_webhook(app: &App<MockSender>, body: &serde_json::Value) -> warp::http::Response<Bytes> {
+        webhook_request("/webhook", body).reply(&app.routes).await
+    }
+
+    fn response_json(response: &warp::http::Response<Bytes>) -> serde_json::Value {
+        serde_json::from_slice(response.body()).expect("JSON body")
+    }
+
+    #[tokio::test]
+    async fn handle_webhook_sends_once_for_a_trigger() {
+        let app = test_app(&[]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 1, "{:?}", sent);
+        assert_eq!(sent[0].kind, "contact");
+        assert_eq!(sent[0].to(), "+15551234567");
+    }
+
+    #[tokio::test]
+    async fn handle_webhook_ignores_text_without_a_trigger() {
+        let app = test_app(&[]).await;
+        handle_webhook(text_message("m1", "hello there"), &app.worker).await.unwrap();
+        assert!(app.worker.client.sent().iter().all(|sent| sent.kind != "contact"));
+    }
+
+    #[tokio::test]
+    async fn webhook_post_sends_the_contact() {
+        let app = test_app(&[]).await;
+        let response = post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+        let sent = app.worker.client.wait_for(1).await;
+        assert_eq!(sent[0].kind, "contact");
+        assert_eq!(sent[0].body["content"]["contacts"][0]["name"]["firstName"], "Jane");
+    }
+
+    #[test]
+    fn vcard_escapes_commas_in_names() {
+        let mut contact = jane();
+        contact.last_name = "Doe, Jr.".to_string();
+        let vcard = generate_vcard(&contact, VCardVersion::V3_0);
+        assert!(vcard.contains("\nN:Doe\\, Jr.;Jane\n"), "{}", vcard);
+    }
+
+    #[test]
+    fn vcard_keeps_a_multi_line_note_on_one_line() {
+        let mut contact = jane();
+        contact.note = Some("first line\nsecond; line\r\nthird \\ line".to_string());
+        let vcard = generate_vcard(&contact, VCardVersion::V3_0);
+        assert!(vcard.contains("\nNOTE:first line\\nsecond\\; line\\nthird \\\\ line\n"), "{}", vcard);
+        assert_eq!(vcard.lines().filter(|line| line.starts_with("NOTE")).count(), 1);
+    }
+
+    #[test]
+    fn vcard_3_0_header_and_tel_line() {
+        let vcard = generate_vcard(&jane(), VCardVersion::V3_0);
+        assert!(vcard.starts_with("BEGIN:VCARD\nVERSION:3.0\nN:Smith;Jane\n"), "{}", vcard);
+        assert!(vcard.contains("\nTEL;TYPE=CELL:+15551230000\n"), "{}", vcard);
+    }
+
+    #[test]
+    fn vcard_4_0_header_and_tel_line() {
+        let vcard = generate_vcard(&jane(), VCardVersion::V4_0);
+        assert!(vcard.starts_with("BEGIN:VCARD\nVERSION:4.0\nKIND:individual\nN:Smith;Jane\n"), "{}", vcard);
+        assert!(vcard.contains("\nTEL;VALUE=uri;TYPE=cell:tel:+15551230000\n"), "{}", vcard);
+    }
+
+    #[test]
+    fn vcard_version_parses() {
+        assert_eq!("3.0".parse::<VCardVersion>(), Ok(VCardVersion::V3_0));
+        assert_eq!("4".parse::<VCardVersion>(), Ok(VCardVersion::V4_0));
+        assert!("2.1".parse::<VCardVersion>().is_err());
+    }
+
+    fn outbound(contact: &VCard) -> Outbound<'_> {
+        Outbound { contact, recipient: "+15551234567", in_session: true, idempotency_key: None, media: None }
+    }
+
+    fn timeout() -> BotError {
+        BotError::Timeout(Duration::from_secs(1))
+    }
+
+    #[tokio::test]
+    async fn send_vcard_retries_then_succeeds() {
+        let config = test_config(&[("BASE_BACKOFF_MS", "1")]);
+        let client = MockSender::new();
+        client.then(Err(timeout())).then(Err(timeout()));
+        let metrics = Metrics::new();
+        let contact = jane();
+        send_vcard(&client, &config, &metrics, &outbound(&contact)).await.unwrap();
+        assert_eq!(client.sent().len(), 3);
+        assert_eq!(metrics.vcards_sent.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_vcard_gives_up_after_max_retries() {
+        let config = test_config(&[("BASE_BACKOFF_MS", "1"), ("MAX_RETRIES", "2")]);
+        let client = MockSender::new();
+        client.then(Err(timeout())).then(Err(timeout())).then(Err(timeout())).then(Ok(()));
+        let metrics = Metrics::new();
+        let contact = jane();
+        let result = send_vcard(&client, &config, &metrics, &outbound(&contact)).await;
+        assert!(matches!(result, Err(BotError::Timeout(_))), "{:?}", result);
+        assert_eq!(client.sent().len(), 3);
+        assert_eq!(metrics.send_failures.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_vcard_does_not_retry_permanent_errors() {
+        let config = test_config(&[("BASE_BACKOFF_MS", "1")]);
+        let client = MockSender::new();
+        client.then(Err(BotError::Send("rejected".to_string())));
+        let contact = jane();
+        assert!(send_vcard(&client, &config, &Metrics::new(), &outbound(&contact)).await.is_err());
+        assert_eq!(client.sent().len(), 1);
+    }
+
+    #[test]
+    fn enqueued_message_reaches_the_receiver() {
+        let (tx, mut rx) = lanes(4);
+        let pending = PendingQueue::new();
+        enqueue_message(&tx, Lane::Normal, &Metrics::new(), &pending, text_message("m1", "addcontact Jane")).unwrap();
+        let received = rx.try_recv().expect("message on the queue");
+        assert_eq!(received.message_id.as_deref(), Some("m1"));
+        assert_eq!(received.text.as_deref(), Some("addcontact Jane"));
+        assert!(pending.take(received.pending_seq));
+    }
+
+    // An inbound webhook body as Infobip sends it: a text, then an image we don't handle
+    const SAMPLE_INBOUND: &str = r#"{
+        "results": [
+            {
+                "from": "385916242493",
+                "to": "385921004026",
+                "integrationType": "WHATSAPP",
+                "receivedAt": "2019-07-19T11:23:50.000+0000",
+                "messageId": "ABEGOFl3YCQjAhCWuW8o7n8fqgc",
+                "pairedMessageId": null,
+                "callbackData": null,
+                "message": { "type": "TEXT", "text": "addcontact Jane Smith +15551230000" },
+                "contact": { "name": "Frank" },
+                "price": { "pricePerMessage": 0, "currency": "EUR" }
+            },
+            {
+                "from": "385916242493",
+                "to": "385921004026",
+                "integrationType": "WHATSAPP",
+                "receivedAt": "2019-07-19T11:24:02.000+0000",
+                "messageId": "ABEGOFl3YCQjAhCWuW8o7n8fqgd",
+                "message": { "type": "IMAGE", "url": "https://example.com/image.jpg", "caption": "" },
+                "contact": { "name": "Frank" },
+                "price": { "pricePerMessage": 0, "currency": "EUR" }
+            }
+        ],
+        "messageCount": 2,
+        "pendingMessageCount": 0
+    }"#;
+
+    #[test]
+    fn parses_a_captured_inbound_payload() {
+        let webhook: InboundWebhook = serde_json::from_str(SAMPLE_INBOUND).unwrap();
+        assert_eq!(webhook.results.len(), 2);
+        let text = &webhook.results[0];
+        assert_eq!(text.from, "385916242493");
+        assert_eq!(text.message_id, "ABEGOFl3YCQjAhCWuW8o7n8fqgc");
+        assert!(matches!(&text.message, InboundMessage::Text { text } if text == "addcontact Jane Smith +15551230000"));
+        assert_eq!(text.contact.as_ref().and_then(|contact| contact.name.as_deref()), Some("Frank"));
+        assert!(matches!(webhook.results[1].message, InboundMessage::Unsupported));
+
+        let messages: Vec<WhatsAppMessage> = webhook.results.into_iter().map(WhatsAppMessage::from).collect();
+        assert_eq!(messages[0].correlation_id, "ABEGOFl3YCQjAhCWuW8o7n8fqgc");
+        assert_eq!(messages[0].text.as_deref(), Some("addcontact Jane Smith +15551230000"));
+        assert_eq!(messages[1].text, None);
+    }
+
+    fn sign(secret: &str, signed: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(signed.as_bytes());
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn signature_checks() {
+        let body = br#"{"results":[]}"#;
+        let signature = sign("s3cret", r#"{"results":[]}"#);
+        assert!(verify_signature(b"s3cret", None, body, &signature));
+        assert!(verify_signature(b"s3cret", None, body, signature.trim_start_matches("sha256=")));
+        assert!(!verify_signature(b"other", None, body, &signature));
+        assert!(!verify_signature(b"s3cret", None, br#"{"results":[{}]}"#, &signature));
+        assert!(!verify_signature(b"s3cret", None, body, "sha256=not-hex"));
+    }
+
+    #[tokio::test]
+    async fn webhook_accepts_a_valid_signature() {
+        let app = test_app(&[("WEBHOOK_SECRET", "s3cret"), ("WEBHOOK_SIGNATURE_HEADER", "X-Signature")]).await;
+        let body = inbound("m1", "addcontact Jane Smith +15551230000");
+        let response = webhook_request("/webhook", &body)
+            .header("X-Signature", sign("s3cret", &body.to_string()))
+            .reply(&app.routes)
+            .await;
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+        app.worker.client.wait_for(1).await;
+    }
+
+    #[tokio::test]
+    async fn webhook_rejects_an_invalid_signature() {
+        let app = test_app(&[("WEBHOOK_SECRET", "s3cret"), ("WEBHOOK_SIGNATURE_HEADER", "X-Signature")]).await;
+        let body = inbound("m1", "addcontact Jane Smith +15551230000");
+        let response = webhook_request("/webhook", &body)
+            .header("X-Signature", sign("wrong", &body.to_string()))
+            .reply(&app.routes)
+            .await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(response_json(&response)["error"], "invalid_signature");
+    }
+
+    #[tokio::test]
+    async fn webhook_rejects_a_missing_signature() {
+        let app = test_app(&[("WEBHOOK_SECRET", "s3cret"), ("WEBHOOK_SIGNATURE_HEADER", "X-Signature")]).await;
+        let body = inbound("m1", "addcontact Jane Smith +15551230000");
+        // Under the default header name, not the configured one
+        let response = webhook_request("/webhook", &body)
+            .header("X-Hub-Signature-256", sign("s3cret", &body.to_string()))
+            .reply(&app.routes)
+            .await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(response_json(&response)["error"], "missing_signature");
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[test]
+    fn listen_addr_from_address_and_port() {
+        assert_eq!(listen_addr("0.0.0.0", 8080).unwrap(), "0.0.0.0:8080".parse::<SocketAddr>().unwrap());
+        assert_eq!(listen_addr(" 127.0.0.1 ", 3000).unwrap(), "127.0.0.1:3000".parse::<SocketAddr>().unwrap());
+        assert_eq!(listen_addr("[::1]", 8443).unwrap(), "[::1]:8443".parse::<SocketAddr>().unwrap());
+        assert!(matches!(listen_addr("localhost", 8080), Err(BotError::Config(_))));
+    }
+
+    #[test]
+    fn single_trigger_word() {
+        let config = test_config(&[("TRIGGER_WORDS", "vcard")]);
+        assert_eq!(config.trigger_words, ["vcard"]);
+        assert_eq!(matched_trigger(&config, "please VCARD me").as_deref(), Some("vcard"));
+        assert_eq!(matched_trigger(&config, "addcontact Jane"), None);
+    }
+
+    #[test]
+    fn several_trigger_words() {
+        let config = test_config(&[("TRIGGER_WORDS", "addcontact, vcard ,share")]);
+        assert_eq!(config.trigger_words, ["addcontact", "vcard", "share"]);
+        assert_eq!(matched_trigger(&config, "share it").as_deref(), Some("share"));
+        assert_eq!(matched_trigger(&config, "addcontact Jane").as_deref(), Some("addcontact"));
+        assert_eq!(matched_trigger(&config, "hello"), None);
+    }
+
+    #[test]
+    fn empty_trigger_words_fall_back_to_the_default() {
+        let config = test_config(&[("TRIGGER_WORDS", " , ")]);
+        assert_eq!(config.trigger_words, ["addcontact"]);
+        assert_eq!(test_config(&[]).trigger_words, ["addcontact"]);
+    }
+
+    async fn get(app: &App<MockSender>, path: &str) -> warp::http::Response<Bytes> {
+        warp::test::request().method("GET").path(path).reply(&app.routes).await
+    }
+
+    #[tokio::test]
+    async fn verification_echoes_the_challenge_for_a_matching_token() {
+        let app = test_app(&[("VERIFY_TOKEN", "let-me-in")]).await;
+        let response = get(&app, "/webhook?hub.mode=subscribe&hub.verify_token=let-me-in&hub.challenge=1158201444").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body().as_ref(), b"1158201444");
+    }
+
+    #[tokio::test]
+    async fn verification_refuses_a_wrong_or_missing_token() {
+        let app = test_app(&[("VERIFY_TOKEN", "let-me-in")]).await;
+        let response = get(&app, "/webhook?hub.verify_token=let-me-out&hub.challenge=1158201444").await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(get(&app, "/webhook?hub.challenge=1158201444").await.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn verification_refuses_everything_without_a_configured_token() {
+        let app = test_app(&[]).await;
+        assert_eq!(get(&app, "/webhook?hub.verify_token=&hub.challenge=1").await.status(), 403);
+    }
+
+    #[test]
+    fn recipients_are_the_configured_numbers_by_default() {
+        let config = test_config(&[("RECIPIENT_PHONE_NUMBER", "+15551234567,+15557654000")]);
+        assert_eq!(select_recipients(&config, SENDER), ["+15551234567", "+15557654000"]);
+    }
+
+    #[test]
+    fn recipient_is_the_sender_in_reply_mode() {
+        let config = test_config(&[("REPLY_TO_SENDER", "true")]);
+        assert_eq!(select_recipients(&config, SENDER), [SENDER]);
+        // Not a number we can send to; falls back to the configured recipient
+        assert_eq!(select_recipients(&config, "123"), ["+15551234567"]);
+    }
+
+    #[tokio::test]
+    async fn health_is_always_ok() {
+        let app = test_app(&[]).await;
+        let response = get(&app, "/health").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body().as_ref(), b"OK");
+    }
+
+    #[tokio::test]
+    async fn ready_once_the_listener_is_bound() {
+        let app = test_app(&[]).await;
+        assert_eq!(get(&app, "/ready").await.status(), 503);
+        app.ready.store(true, Ordering::SeqCst);
+        assert_eq!(get(&app, "/ready").await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn persisted_messages_are_sent_after_a_restart() {
+        let database = crate::migrations::tests::TempDatabase::new();
+        let url = database.url();
+        QueueStore::open(&url).unwrap().enqueue(&text_message("m1", "addcontact Jane Smith +15551230000")).unwrap();
+        let app = test_app(&[("PERSIST_QUEUE", "true"), ("DATABASE_URL", &url)]).await;
+        let sent = app.worker.client.wait_for(1).await;
+        assert_eq!(sent[0].kind, "contact");
+    }
+
+    #[tokio::test]
+    async fn redelivered_message_is_sent_once() {
+        let app = test_app(&[]).await;
+        let body = inbound("m1", "addcontact Jane Smith +15551230000");
+        assert_eq!(post_webhook(&app, &body).await.status(), 200);
+        let response = post_webhook(&app, &body).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response_json(&response)["messages"][0]["status"], "duplicate");
+        app.worker.client.wait_for(1).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(app.worker.client.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_failed_recipient_does_not_stop_the_others() {
+        let app = test_app(&[("RECIPIENT_PHONE_NUMBER", "+15551230001,+15551230002,+15551230003")]).await;
+        app.worker.client.then(Ok(())).then(Err(BotError::Send("rejected".to_string())));
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        let recipients: Vec<String> = app.worker.client.sent().iter().map(|sent| sent.to().to_string()).collect();
+        assert_eq!(recipients, ["+15551230001", "+15551230002", "+15551230003"]);
+        assert_eq!(app.worker.metrics.vcards_sent.get(), 2);
+        assert_eq!(app.worker.metrics.send_failures.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_message_fails_when_no_recipient_got_it() {
+        let app = test_app(&[("RECIPIENT_PHONE_NUMBER", "+15551230001,+15551230002")]).await;
+        let rejected = || Err(BotError::Send("rejected".to_string()));
+        app.worker.client.then(rejected()).then(rejected());
+        assert!(handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.is_err());
+        assert_eq!(app.worker.client.sent().len(), 2);
+    }
+
+    const ADMIN_TOKEN: &str = "admin-s3cret";
+
+    async fn admin_get(app: &App<MockSender>, path: &str) -> warp::http::Response<Bytes> {
+        warp::test::request()
+            .method("GET")
+            .path(path)
+            .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+            .reply(&app.routes)
+            .await
+    }
+
+    // Poll until `check` holds, for what the workers finish after the mock has recorded a send
+    async fn eventually(check: impl Fn() -> bool) {
+        for _ in 0..200 {
+            if check() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met within 2s");
+    }
+
+    fn metric(body: &str, name: &str) -> f64 {
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+            .unwrap_or_else(|| panic!("{} not in\n{}", name, body))
+    }
+
+    #[tokio::test]
+    async fn metrics_count_a_triggered_send() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let before = String::from_utf8(admin_get(&app, "/metrics").await.body().to_vec()).unwrap();
+        assert_eq!(metric(&before, "vcards_sent_total"), 0.0);
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        eventually(|| app.worker.metrics.vcards_sent.get() == 1).await;
+
+        let response = admin_get(&app, "/metrics").await;
+        assert_eq!(response.status(), 200);
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+        let after = String::from_utf8(response.body().to_vec()).unwrap();
+        assert_eq!(metric(&after, "messages_received_total"), 1.0);
+        assert_eq!(metric(&after, "vcards_sent_total"), 1.0);
+    }
+
+    // A config file of its own for one test, removed again when dropped
+    struct TempConfigFile(PathBuf);
+
+    impl TempConfigFile {
+        fn new(contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("tool-test-{}.toml", uuid::Uuid::new_v4()));
+            std::fs::write(&path, contents).unwrap();
+            TempConfigFile(path)
+        }
+    }
+
+    impl Drop for TempConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    const SAMPLE_CONFIG: &str = r#"
+        infobip_api_key = "file-key"
+        infobip_base_url = "https://api.example.com"
+        whatsapp_phone_number_id = "447860099299"
+        recipient_phone_number = ["+15551234567", "+15557654000"]
+        port = 8000
+        trigger_words = ["addcontact", "vcard"]
+        reply_to_sender = true
+        rate_per_second = 2.5
+    "#;
+
+    #[test]
+    fn command_line_flags_override_the_config_file() {
+        let file = TempConfigFile::new(SAMPLE_CONFIG);
+        let cli = Cli::parse_from([
+            "tool",
+            "--config-file",
+            file.0.to_str().unwrap(),
+            "--port",
+            "9000",
+            "--trigger-word",
+            "share",
+        ]);
+        let config = load_config(&load_settings(&cli).unwrap()).unwrap();
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.trigger_words, ["share"]);
+        // Not on the command line, so the file's value stands
+        assert_eq!(config.recipient_phone_numbers, ["+15551234567", "+15557654000"]);
+    }
+
+    #[test]
+    fn command_line_without_flags_changes_nothing() {
+        let cli = Cli::parse_from(["tool"]);
+        assert!(cli.overrides().is_empty());
+        let cli = Cli::parse_from(["tool", "--recipient", "+15550000000", "--base-url", "http://localhost"]);
+        let overrides = cli.overrides();
+        assert_eq!(overrides["RECIPIENT_PHONE_NUMBER"], "+15550000000");
+        assert_eq!(overrides["INFOBIP_BASE_URL"], "http://localhost");
+    }
+
+    #[test]
+    fn config_file_values_are_loaded() {
+        let file = TempConfigFile::new(SAMPLE_CONFIG);
+        let config = load_config(&Settings::default().with_file(&file.0).unwrap()).unwrap();
+        assert_eq!(config.infobip_base_url, "https://api.example.com");
+        assert_eq!(config.port, 8000);
+        assert_eq!(config.trigger_words, ["addcontact", "vcard"]);
+        assert!(config.reply_to_sender);
+        assert_eq!(config.rate_per_second, 2.5);
+    }
+
+    #[test]
+    fn unreadable_config_file_is_a_config_error() {
+        let missing = std::env::temp_dir().join(format!("tool-test-{}.toml", uuid::Uuid::new_v4()));
+        assert!(matches!(Settings::default().with_file(&missing), Err(BotError::Config(_))));
+        let file = TempConfigFile::new("port = [[1]]");
+        assert!(matches!(Settings::default().with_file(&file.0), Err(BotError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn dry_run_never_calls_the_client() {
+        let app = test_app(&[("DRY_RUN", "true")]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert!(app.worker.client.sent().is_empty(), "{:?}", app.worker.client.sent());
+        assert_eq!(app.worker.metrics.vcards_sent.get(), 0);
+    }
+
+    #[test]
+    fn allowlisted_senders_pass_and_unlisted_ones_do_not() {
+        let config = test_config(&[("ALLOWED_SENDERS", "+15557654321,+15550000001")]);
+        assert!(sender_permitted(&config, "15557654321"));
+        assert!(sender_permitted(&config, "+15550000001"));
+        assert!(!sender_permitted(&config, "+15550000002"));
+    }
+
+    #[test]
+    fn blocked_senders_are_refused_even_when_allowlisted() {
+        let config = test_config(&[("ALLOWED_SENDERS", "+15557654321"), ("BLOCKED_SENDERS", "+15557654321")]);
+        assert!(!sender_permitted(&config, "15557654321"));
+        let config = test_config(&[("BLOCKED_SENDERS", "+15550000002")]);
+        assert!(!sender_permitted(&config, "15550000002"));
+        // Without an allowlist everyone else may send
+        assert!(sender_permitted(&config, "15550000003"));
+    }
+
+    #[tokio::test]
+    async fn messages_from_blocked_senders_are_ignored() {
+        let app = test_app(&[("BLOCKED_SENDERS", SENDER)]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn outside_the_session_window_a_template_is_sent() {
+        let config = test_config(&[("TEMPLATE_NAME", "contact_card"), ("TEMPLATE_PLACEHOLDERS", "{first_name},{phone_number}")]);
+        let client = MockSender::new();
+        let contact = jane();
+        let send = Outbound { in_session: false, ..outbound(&contact) };
+        send_vcard(&client, &config, &Metrics::new(), &send).await.unwrap();
+        let sent = client.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].kind, "template");
+        let message = &sent[0].body["messages"][0];
+        assert_eq!(message["to"], "+15551234567");
+        assert_eq!(message["content"]["templateName"], "contact_card");
+        assert_eq!(message["content"]["templateData"]["body"]["placeholders"], serde_json::json!(["Jane", "+15551230000"]));
+    }
+
+    #[tokio::test]
+    async fn inside_the_session_window_the_card_is_sent() {
+        let config = test_config(&[("TEMPLATE_NAME", "contact_card")]);
+        let client = MockSender::new();
+        let contact = jane();
+        send_vcard(&client, &config, &Metrics::new(), &outbound(&contact)).await.unwrap();
+        assert_eq!(client.sent()[0].kind, "contact");
+    }
+
+    #[tokio::test]
+    async fn without_a_template_the_card_is_sent_anyway() {
+        let client = MockSender::new();
+        let contact = jane();
+        let send = Outbound { in_session: false, ..outbound(&contact) };
+        send_vcard(&client, &test_config(&[]), &Metrics::new(), &send).await.unwrap();
+        assert_eq!(client.sent()[0].kind, "contact");
+    }
+
+    #[tokio::test]
+    async fn several_workers_process_every_message() {
+        let app = test_app(&[("WORKER_COUNT", "4")]).await;
+        assert_eq!(app.workers.lock().unwrap().len(), 4);
+        for i in 0..20 {
+            let body = inbound(&format!("m{}", i), &format!("addcontact Person{} Smith +1555123{:04}", i, i));
+            assert_eq!(post_webhook(&app, &body).await.status(), 200);
+        }
+        let sent = app.worker.client.wait_for(20).await;
+        let mut names: Vec<String> =
+            sent.iter().map(|sent| sent.body["content"]["contacts"][0]["name"]["firstName"].to_string()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 20);
+        eventually(|| app.worker.processed.load(Ordering::SeqCst) == 20).await;
+    }
+
+    #[tokio::test]
+    async fn processed_messages_are_logged() {
+        let database = crate::migrations::tests::TempDatabase::new();
+        let url = database.url();
+        let app = test_app(&[("LOG_INBOUND", "true"), ("DATABASE_URL", &url)]).await;
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        eventually(|| app.worker.processed.load(Ordering::SeqCst) == 1).await;
+        let conn = migrations::open(&url).unwrap();
+        let (sender, trigger): (String, Option<String>) = conn
+            .query_row("SELECT sender, trigger_matched FROM inbound_log", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(sender, SENDER);
+        assert_eq!(trigger.as_deref(), Some("addcontact"));
+    }
+
+    #[test]
+    fn idempotency_key_is_stable_per_message_and_recipient() {
+        let key = idempotency_key("ABEGOFl3YCQjAhCWuW8o7n8fqgc", "+15551234567");
+        assert_eq!(key, idempotency_key("ABEGOFl3YCQjAhCWuW8o7n8fqgc", "+15551234567"));
+        assert_ne!(key, idempotency_key("ABEGOFl3YCQjAhCWuW8o7n8fqgc", "+15557654000"));
+        assert_ne!(key, idempotency_key("ABEGOFl3YCQjAhCWuW8o7n8fqgd", "+15551234567"));
+        assert!(key.len() <= 50);
+        assert!(idempotency_key(&"x".repeat(200), "+15551234567").len() <= 50);
+    }
+
+    #[tokio::test]
+    async fn retries_carry_the_same_idempotency_key() {
+        let config = test_config(&[("BASE_BACKOFF_MS", "1")]);
+        let client = MockSender::new();
+        client.then(Err(timeout())).then(Err(timeout()));
+        let contact = jane();
+        let message = text_message("m1", "addcontact Jane Smith +15551230000");
+        let send = Outbound { idempotency_key: delivery_key(&message, "+15551234567"), ..outbound(&contact) };
+        send_vcard(&client, &config, &Metrics::new(), &send).await.unwrap();
+        let keys: Vec<serde_json::Value> = client.sent().iter().map(|sent| sent.body["messageId"].clone()).collect();
+        let expected = serde_json::json!(idempotency_key("m1", "+15551234567"));
+        assert_eq!(keys, [expected.clone(), expected.clone(), expected]);
+    }
+
+    #[tokio::test]
+    async fn confirmed_contact_is_sent() {
+        let app = test_app(&[("REQUIRE_CONFIRMATION", "true")]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to(), SENDER);
+        assert_eq!(sent[0].text(), "Add Jane Smith? Reply YES to confirm or NO to cancel.");
+
+        handle_webhook(text_message("m2", "YES"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].kind, "contact");
+        assert_eq!(sent[1].to(), "+15551234567");
+    }
+
+    #[tokio::test]
+    async fn declined_contact_is_not_sent() {
+        let app = test_app(&[("REQUIRE_CONFIRMATION", "true")]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        handle_webhook(text_message("m2", "no"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].text(), "OK, Jane Smith was not added.");
+        assert!(sent.iter().all(|sent| sent.kind == "text"));
+    }
+
+    #[test]
+    fn every_number_gets_a_tel_line_in_order() {
+        let contact = command::parse_contact_command("Jane Smith +15551230000 work:+15551230001 home: +15551230002", None).unwrap();
+        let tel = |version| -> Vec<String> {
+            generate_vcard(&contact, version).lines().filter(|line| line.starts_with("TEL")).map(str::to_string).collect()
+        };
+        assert_eq!(
+            tel(VCardVersion::V3_0),
+            ["TEL;TYPE=CELL:+15551230000", "TEL;TYPE=WORK:+15551230001", "TEL;TYPE=HOME:+15551230002"]
+        );
+        assert_eq!(
+            tel(VCardVersion::V4_0),
+            [
+                "TEL;VALUE=uri;TYPE=cell:tel:+15551230000",
+                "TEL;VALUE=uri;TYPE=work:tel:+15551230001",
+                "TEL;VALUE=uri;TYPE=home:tel:+15551230002",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_number_goes_into_the_contact_card() {
+        let contact = command::parse_contact_command("Jane +15551230000 work:+15551230001", None).unwrap();
+        let card = serde_json::to_value(to_infobip_contact(&contact)).unwrap();
+        assert_eq!(card["phones"][0]["phone"], "+15551230000");
+        assert_eq!(card["phones"][0]["type"], "CELL");
+        assert_eq!(card["phones"][1]["phone"], "+15551230001");
+        assert_eq!(card["phones"][1]["type"], "WORK");
+    }
+
+    #[tokio::test]
+    async fn oversized_body_is_refused() {
+        let app = test_app(&[("MAX_BODY_BYTES", "64")]).await;
+        let body = inbound("m1", &format!("addcontact Jane Smith +15551230000 {}", "x".repeat(100)));
+        let response = post_webhook(&app, &body).await;
+        assert_eq!(response.status(), 413);
+        assert_eq!(response_json(&response)["error"], "payload_too_large");
+    }
+
+    #[tokio::test]
+    async fn malformed_body_is_a_json_400() {
+        let app = test_app(&[]).await;
+        let response = warp::test::request()
+            .method("POST")
+            .path("/webhook")
+            .header("content-type", "application/json")
+            .body(r#"{"results": [{"from": "#)
+            .reply(&app.routes)
+            .await;
+        assert_eq!(response.status(), 400);
+        let body = response_json(&response);
+        assert_eq!(body["error"], "invalid_body");
+        assert!(body["message"].as_str().unwrap().starts_with("invalid webhook body: "));
+
+        let response = post_webhook(&app, &serde_json::json!({ "results": [{ "from": SENDER }] })).await;
+        assert_eq!(response.status(), 400);
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn webhook_on_a_custom_path() {
+        let app = test_app(&[("WEBHOOK_PATH", "/hooks/infobip/")]).await;
+        let body = inbound("m1", "addcontact Jane Smith +15551230000");
+        assert_eq!(webhook_request("/hooks/infobip", &body).reply(&app.routes).await.status(), 200);
+        app.worker.client.wait_for(1).await;
+        // The default path is no longer routed
+        let other = inbound("m2", "addcontact John Doe +15551230001");
+        assert!(webhook_request("/webhook", &other).reply(&app.routes).await.status().is_client_error());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(app.worker.client.sent().len(), 1);
+    }
+
+    #[test]
+    fn tls_when_both_paths_are_set() {
+        let cert = TempConfigFile::new("cert");
+        let key = TempConfigFile::new("key");
+        let (cert_path, key_path) = (cert.0.to_str().unwrap(), key.0.to_str().unwrap());
+        let config = test_config(&[("TLS_CERT_PATH", cert_path), ("TLS_KEY_PATH", key_path)]);
+        assert_eq!(tls_paths(&config), Some((cert_path.to_string(), key_path.to_string())));
+    }
+
+    #[test]
+    fn plain_http_without_tls_paths() {
+        assert_eq!(tls_paths(&test_config(&[])), None);
+    }
+
+    #[test]
+    fn tls_paths_must_be_set_together_and_readable() {
+        let cert = TempConfigFile::new("cert");
+        let cert_path = cert.0.to_str().unwrap();
+        assert!(matches!(load_config(&test_settings(&[("TLS_CERT_PATH", cert_path)])), Err(BotError::Config(_))));
+        let unreadable = test_settings(&[("TLS_CERT_PATH", cert_path), ("TLS_KEY_PATH", "/nonexistent/key.pem")]);
+        assert!(matches!(load_config(&unreadable), Err(BotError::Config(message)) if message.contains("TLS_KEY_PATH")));
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_report_bumps_the_failure_metric() {
+        let app = test_app(&[]).await;
+        let response = warp::test::request()
+            .method("POST")
+            .path("/status")
+            .header("content-type", "application/json")
+            .body(delivery::tests::SAMPLE_REPORTS)
+            .reply(&app.routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        let reports = &app.worker.metrics.delivery_reports;
+        assert_eq!(reports.with_label_values(&["failed"]).get(), 1);
+        assert_eq!(reports.with_label_values(&["delivered"]).get(), 1);
+        assert_eq!(reports.with_label_values(&["read"]).get(), 1);
+    }
+
+    #[tokio::test]
+    async fn info_reports_the_version_without_secrets() {
+        let app = test_app(&[
+            ("ADMIN_TOKEN", ADMIN_TOKEN),
+            ("INFOBIP_API_KEY", "very-secret-key"),
+            ("WEBHOOK_SECRET", "hook-secret"),
+        ])
+        .await;
+        let response = admin_get(&app, "/info").await;
+        assert_eq!(response.status(), 200);
+        let info = response_json(&response);
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["config"]["trigger_words"][0], "addcontact");
+        assert_eq!(info["config"]["signature_verification"], true);
+        let body = String::from_utf8_lossy(response.body());
+        for secret in ["very-secret-key", "hook-secret", ADMIN_TOKEN, "api_key"] {
+            assert!(!body.contains(secret), "{} leaked into {}", secret, body);
+        }
+    }
+
+    #[tokio::test]
+    async fn info_needs_the_admin_token() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        assert_eq!(get(&app, "/info").await.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn stop_blocks_sends_until_start() {
+        let app = test_app(&[("REPLY_TO_SENDER", "true")]).await;
+        let client = &app.worker.client;
+        handle_webhook(text_message("m1", "STOP"), &app.worker).await.unwrap();
+        assert!(client.sent()[0].text().starts_with("You have been unsubscribed"));
+
+        handle_webhook(text_message("m2", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert_eq!(client.sent().len(), 1);
+        assert_eq!(app.worker.metrics.suppressed_sends.get(), 1);
+
+        handle_webhook(text_message("m3", "start"), &app.worker).await.unwrap();
+        assert_eq!(client.sent()[1].text(), "You have been resubscribed.");
+        handle_webhook(text_message("m4", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        let sent = client.sent();
+        assert_eq!(sent.len(), 3);
+        assert_eq!((sent[2].kind, sent[2].to()), ("contact", SENDER));
+    }
+
+    async fn admin_post(app: &App<MockSender>, path: &str) -> warp::http::Response<Bytes> {
+        warp::test::request()
+            .method("POST")
+            .path(path)
+            .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+            .reply(&app.routes)
+            .await
+    }
+
+    #[tokio::test]
+    async fn selftest_sends_one_ping() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let response = admin_post(&app, "/selftest").await;
+        assert_eq!(response.status(), 200);
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].kind, sent[0].to(), sent[0].text()), ("text", "+15551234567", SELFTEST_TEXT));
+        let report = response_json(&response);
+        assert_eq!(report["results"][0]["sent"], true);
+        assert_eq!(report["dry_run"], false);
+    }
+
+    #[tokio::test]
+    async fn selftest_reports_a_failed_send() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        app.worker.client.then(Err(BotError::Timeout(Duration::from_secs(10))));
+        let response = admin_post(&app, "/selftest").await;
+        assert_eq!(response.status(), 504);
+        assert_eq!(response_json(&response)["results"][0]["sent"], false);
+    }
+
+    #[tokio::test]
+    async fn selftest_is_never_open() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let unauthenticated = warp::test::request().method("POST").path("/selftest").reply(&app.routes).await;
+        assert_eq!(unauthenticated.status(), 401);
+        // Without a token configured the route is disabled rather than open
+        let app = test_app(&[]).await;
+        let response = warp::test::request().method("POST").path("/selftest").reply(&app.routes).await;
+        assert!(response.status().is_client_error());
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn equivalent_contacts_are_sent_once() {
+        let app = test_app(&[]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        handle_webhook(text_message("m2", "addcontact  jane SMITH +1 555 123 0000"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent().len(), 1);
+        assert_eq!(app.worker.metrics.duplicate_contacts.get(), 1);
+        // Someone else is a different contact
+        handle_webhook(text_message("m3", "addcontact Jane Smyth +15551230000"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_failed_contact_send_is_not_remembered() {
+        let app = test_app(&[]).await;
+        app.worker.client.then(Err(BotError::Send("rejected".to_string())));
+        assert!(handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.is_err());
+        handle_webhook(text_message("m2", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent().len(), 2);
+        assert_eq!(app.worker.metrics.duplicate_contacts.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn contact_dedup_can_be_turned_off() {
+        let app = test_app(&[("CONTACT_DEDUP_WINDOW_SECS", "0")]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        handle_webhook(text_message("m2", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent().len(), 2);
+    }
+
+    #[test]
+    fn title_url_and_note_only_when_set() {
+        let properties = |contact: &VCard| -> Vec<String> {
+            vcard_lines(&generate_vcard(contact, VCardVersion::V3_0)).into_iter().map(|(property, _)| property).collect()
+        };
+        let plain = properties(&jane());
+        for property in ["TITLE", "URL", "NOTE"] {
+            assert!(!plain.iter().any(|name| name == property), "{} in {:?}", property, plain);
+        }
+        let mut contact = jane();
+        contact.title = Some("CTO".to_string());
+        assert!(properties(&contact).contains(&"TITLE".to_string()));
+        assert!(!properties(&contact).contains(&"NOTE".to_string()));
+        contact.url = Some("https://example.com".to_string());
+        contact.note = Some("Met at the conference".to_string());
+        // After the identity fields, in a fixed order
+        assert_eq!(properties(&contact), ["BEGIN", "VERSION", "N", "TEL;TYPE=CELL", "TITLE", "URL", "NOTE", "END"]);
+    }
+
+    #[test]
+    fn title_url_and_note_are_escaped() {
+        let mut contact = jane();
+        contact.title = Some("VP, Sales; EMEA".to_string());
+        contact.url = Some("https://example.com/a,b".to_string());
+        contact.note = Some("Line one\nLine two\r\nC:\\path".to_string());
+        let vcard = generate_vcard(&contact, VCardVersion::V3_0);
+        let lines = vcard_lines(&vcard);
+        let value = |name: &str| lines.iter().find(|(property, _)| property == name).map(|(_, value)| value.as_str());
+        assert_eq!(value("TITLE"), Some("VP\\, Sales\\; EMEA"));
+        assert_eq!(value("URL"), Some("https://example.com/a\\,b"));
+        // The multi-line note is still one content line
+        assert_eq!(value("NOTE"), Some("Line one\\nLine two\\nC:\\\\path"));
+    }
+
+    #[tokio::test]
+    async fn a_reloaded_trigger_word_takes_effect() {
+        let app = test_app(&[]).await;
+        app.worker.config.replace(test_config(&[("TRIGGER_WORDS", "newcontact")]));
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert!(app.worker.client.sent().is_empty());
+        handle_webhook(text_message("m2", "newcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].kind, "contact");
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_secrets() {
+        assert!(constant_time_eq(b"admin-s3cret", b"admin-s3cret"));
+        assert!(!constant_time_eq(b"admin-s3cret", b"admin-s3creT"));
+        assert!(!constant_time_eq(b"admin-s3cret", b"admin"));
+        assert!(!constant_time_eq(b"", b"admin"));
+    }
+
+    #[tokio::test]
+    async fn admin_routes_need_the_bearer_token() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        assert_eq!(admin_get(&app, "/metrics").await.status(), 200);
+        let with_header = |value: &str| {
+            warp::test::request().method("GET").path("/metrics").header("authorization", value.to_string()).reply(&app.routes)
+        };
+        assert_eq!(with_header("Bearer wrong-token").await.status(), 401);
+        assert_eq!(with_header(&format!("Basic {}", ADMIN_TOKEN)).await.status(), 401);
+        assert_eq!(with_header(ADMIN_TOKEN).await.status(), 401);
+        let response = get(&app, "/metrics").await;
+        assert_eq!(response.status(), 401);
+        assert!(!String::from_utf8_lossy(response.body()).contains("vcards_sent"));
+    }
+
+    #[tokio::test]
+    async fn health_stays_open_with_an_admin_token() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        assert_eq!(get(&app, "/health").await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn admin_routes_are_closed_without_an_admin_token() {
+        let app = test_app(&[]).await;
+        assert_eq!(get(&app, "/metrics").await.status(), 401);
+        // Even for a request that sends one
+        assert_eq!(admin_get(&app, "/metrics").await.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn an_alias_sends_the_directory_contact() {
+        let csv = directory::tests::TempCsv::new(directory::tests::SAMPLE_CSV);
+        let app = test_app(&[("CONTACTS_CSV", csv.path())]).await;
+        handle_webhook(text_message("m1", "addcontact Jane"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent[0].kind, "contact");
+        assert_eq!(sent[0].body["content"]["contacts"][0]["phones"][0]["phone"], "+15551230000");
+    }
+
+    #[tokio::test]
+    async fn an_unknown_alias_sends_no_contact() {
+        let csv = directory::tests::TempCsv::new(directory::tests::SAMPLE_CSV);
+        let app = test_app(&[("CONTACTS_CSV", csv.path())]).await;
+        let _ = handle_webhook(text_message("m1", "addcontact alice"), &app.worker).await;
+        assert!(app.worker.client.sent().iter().all(|sent| sent.kind != "contact"));
+    }
+
+    // Log output written while a LogCapture is the thread's subscriber, for asserting on what
+    // got logged. Tests run on a current-thread runtime, so spawned workers log here too.
+    #[derive(Clone, Default)]
+    struct LogCapture(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogCapture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for LogCapture {
+        type Writer = LogCapture;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    impl LogCapture {
+        // Capture until the guard is dropped. `log` records reach it through the LogTracer that
+        // the first call installs, as init_logging does for the real server.
+        fn start(&self) -> tracing::subscriber::DefaultGuard {
+            static LOG_BRIDGE: std::sync::Once = std::sync::Once::new();
+            LOG_BRIDGE.call_once(|| {
+                let _ = tracing_subscriber::registry().try_init();
+            });
+            let layer = tracing_subscriber::fmt::layer().with_writer(self.clone()).with_ansi(false);
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(layer))
+        }
+
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).lines().map(str::to_string).collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn the_correlation_id_follows_the_message_from_receive_to_send() {
+        let logs = LogCapture::default();
+        let _capture = logs.start();
+        let app = test_app(&[]).await;
+        let body = inbound("ABEGOFl3YCQjAhCWuW8o7n8fqgc", "addcontact Jane Smith +15551230000");
+        assert_eq!(post_webhook(&app, &body).await.status(), 200);
+        app.worker.client.wait_for(1).await;
+        eventually(|| logs.lines().iter().any(|line| line.contains("Sent vCard to"))).await;
+
+        let lines = logs.lines();
+        let tagged = |text: &str| {
+            lines
+                .iter()
+                .find(|line| line.contains(text))
+                .unwrap_or_else(|| panic!("no '{}' line in {:#?}", text, lines))
+                .contains("correlation_id=ABEGOFl3YCQjAhCWuW8o7n8fqgc")
+        };
+        assert!(tagged("Webhook delivered message"));
+        assert!(tagged("Sent vCard to"));
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_is_answered_with_503() {
+        let app = test_app(&[("QUEUE_CAPACITY", "1"), ("WORKER_COUNT", "1"), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        // The worker takes the first message and hangs on its send, the second fills the queue
+        app.worker.client.delay_by(Duration::from_secs(30));
+        assert_eq!(post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await.status(), 200);
+        app.worker.client.wait_for(1).await;
+        assert_eq!(post_webhook(&app, &inbound("m2", "addcontact Jane Smith +15551230001")).await.status(), 200);
+
+        let response = post_webhook(&app, &inbound("m3", "addcontact Jane Smith +15551230002")).await;
+        assert_eq!(response.status(), 503);
+        assert_eq!(response_json(&response)["messages"][0]["status"], "rejected");
+        assert_eq!(app.worker.metrics.queue_full.get(), 1);
+        let metrics = String::from_utf8(admin_get(&app, "/metrics").await.body().to_vec()).unwrap();
+        assert_eq!(metric(&metrics, "queue_full_total"), 1.0);
+        assert_eq!(metric(&metrics, "queue_depth"), 1.0);
+        // Not remembered as delivered: the provider's redelivery is tried again, not skipped as
+        // a duplicate
+        let response = post_webhook(&app, &inbound("m3", "addcontact Jane Smith +15551230002")).await;
+        assert_eq!(response_json(&response)["messages"][0]["status"], "rejected");
+    }
+
+    #[tokio::test]
+    async fn an_over_long_text_vcard_is_sent_in_parts() {
+        let app = test_app(&[("SEND_AS_TEXT", "true"), ("MAX_MESSAGE_CHARS", "80")]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["text", "text"]);
+        assert_eq!(sent[0].text(), "Here is the contact vCard:");
+        assert!(sent[1].text().starts_with("BEGIN:VCARD") && sent[1].text().ends_with("END:VCARD"));
+    }
+
+    #[tokio::test]
+    async fn a_text_vcard_that_cannot_be_split_is_not_sent() {
+        let app = test_app(&[("SEND_AS_TEXT", "true"), ("MAX_MESSAGE_CHARS", "40")]).await;
+        let result = handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await;
+        assert!(matches!(result, Err(BotError::MessageTooLong { max: 40, .. })), "{:?}", result);
+        assert!(app.worker.client.sent().iter().all(|sent| !sent.text().contains("BEGIN:VCARD")));
+    }
+
+    #[tokio::test]
+    async fn debug_echo_returns_the_parsed_message() {
+        let app = test_app(&[("DEBUG_ECHO", "true")]).await;
+        let response = post_webhook(&app, &inbound("m1", "Please ADDCONTACT Jane Smith +15551230000")).await;
+        assert_eq!(response.status(), 200);
+        let outcome = &response_json(&response)["messages"][0];
+        assert_eq!(outcome["message"]["from"], SENDER);
+        assert_eq!(outcome["message"]["text"], "Please ADDCONTACT Jane Smith +15551230000");
+        assert_eq!(outcome["message"]["message_id"], "m1");
+        assert_eq!(outcome["message"]["correlation_id"], "m1");
+        assert_eq!(outcome["trigger_matched"], "addcontact");
+        // Processed as usual as well
+        app.worker.client.wait_for(1).await;
+    }
+
+    #[tokio::test]
+    async fn no_echo_by_default() {
+        let app = test_app(&[]).await;
+        let response = post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        let outcome = response_json(&response)["messages"][0].clone();
+        assert!(outcome.get("message").is_none(), "{}", outcome);
+        assert!(outcome.get("trigger_matched").is_none(), "{}", outcome);
+    }
+
+    #[test]
+    fn every_log_level_parses() {
+        let levels = [
+            ("trace", LogLevel::Trace),
+            ("debug", LogLevel::Debug),
+            ("info", LogLevel::Info),
+            ("WARN", LogLevel::Warn),
+            (" error ", LogLevel::Error),
+        ];
+        for (value, level) in levels {
+            assert_eq!(value.parse::<LogLevel>(), Ok(level));
+            assert_eq!(level.as_str(), value.trim().to_lowercase());
+            assert_eq!(test_config(&[("LOG_LEVEL", value)]).log_level, Some(level));
+        }
+        assert_eq!(test_config(&[]).log_level, None);
+    }
+
+    #[test]
+    fn an_invalid_log_level_fails_at_startup() {
+        assert!("verbose".parse::<LogLevel>().unwrap_err().contains("unsupported log level 'verbose'"));
+        let result = load_config(&test_settings(&[("LOG_LEVEL", "verbose")]));
+        assert!(matches!(result, Err(BotError::Config(message)) if message.contains("LOG_LEVEL")));
+    }
+
+    #[tokio::test]
+    async fn qr_returns_a_png_of_the_contact() {
+        let app = test_app(&[]).await;
+        let response = get(&app, "/qr?first_name=Jane&last_name=Smith&phone=%2B15551230000").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "image/png");
+        assert!(response.body().len() > 100);
+        assert!(response.body().starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn qr_refuses_an_invalid_contact() {
+        let app = test_app(&[]).await;
+        let response = get(&app, "/qr?first_name=Jane&phone=12345").await;
+        assert_eq!(response.status(), 400);
+        assert_eq!(response_json(&response)["error"], "invalid_contact");
+        assert_eq!(get(&app, "/qr?phone=%2B15551230000").await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn qr_refuses_a_vcard_too_large_for_a_code() {
+        let app = test_app(&[("MAX_FIELD_CHARS", "5000")]).await;
+        let response = get(&app, &format!("/qr?first_name=Jane&phone=%2B15551230000&organization={}", "x".repeat(3000))).await;
+        assert_eq!(response.status(), 413);
+        assert_eq!(response_json(&response)["error"], "vcard_too_large");
+    }
+
+    #[tokio::test]
+    async fn webhook_json_for_a_triggered_message() {
+        let app = test_app(&[]).await;
+        let response = post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(
+            response_json(&response),
+            serde_json::json!({
+                "status": "queued",
+                "messages": [{ "correlation_id": "m1", "status": "queued", "triggered": true }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn webhook_json_for_a_message_without_a_trigger() {
+        let app = test_app(&[]).await;
+        let response = post_webhook(&app, &inbound("m1", "hello there")).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response_json(&response),
+            serde_json::json!({
+                "status": "queued",
+                "messages": [{ "correlation_id": "m1", "status": "queued", "triggered": false }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn webhook_json_for_a_rejected_message() {
+        let app = test_app(&[("QUEUE_CAPACITY", "1"), ("WORKER_COUNT", "1")]).await;
+        app.worker.client.delay_by(Duration::from_secs(30));
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        app.worker.client.wait_for(1).await;
+        post_webhook(&app, &inbound("m2", "hello")).await;
+        let response = post_webhook(&app, &inbound("m3", "addcontact Jane Smith +15551230001")).await;
+        assert_eq!(response.status(), 503);
+        assert_eq!(
+            response_json(&response),
+            serde_json::json!({
+                "status": "error",
+                "error": BotError::RateLimited { message: "message queue is full, try again later".to_string(), retry_after: None }.to_string(),
+                "messages": [{ "correlation_id": "m3", "status": "rejected", "triggered": true }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn a_scheduled_broadcast_is_queued_for_every_recipient() {
+        let app = test_app(&[
+            ("BROADCAST_SCHEDULE", "* * * * * *"),
+            ("BROADCAST_CONTACT", "Jane Smith +15551230000"),
+            ("RECIPIENT_PHONE_NUMBER", "+15551230001,+15551230002"),
+        ])
+        .await;
+        let sent = app.worker.client.wait_for(2).await;
+        let mut recipients: Vec<&str> = sent[..2].iter().map(|sent| sent.to()).collect();
+        recipients.sort();
+        assert_eq!(recipients, ["+15551230001", "+15551230002"]);
+        assert!(sent[..2].iter().all(|sent| sent.kind == "contact"));
+    }
+
+    #[tokio::test]
+    async fn a_broadcast_is_skipped_while_the_previous_one_is_sending() {
+        let app = test_app(&[
+            ("BROADCAST_SCHEDULE", "* * * * * *"),
+            ("BROADCAST_CONTACT", "Jane Smith +15551230000"),
+            ("WORKER_COUNT", "2"),
+        ])
+        .await;
+        // The first run hangs on its send; a second run would reach the idle worker
+        app.worker.client.delay_by(Duration::from_secs(30));
+        app.worker.client.wait_for(1).await;
+        tokio::time::sleep(Duration::from_millis(2200)).await;
+        assert_eq!(app.worker.client.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_worker_survives_a_panicking_send() {
+        let app = test_app(&[("WORKER_COUNT", "1")]).await;
+        let client = &app.worker.client;
+        for _ in 0..=PANIC_RETRIES {
+            client.then_panic();
+        }
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        post_webhook(&app, &inbound("m2", "addcontact Jane Smith +15551230001")).await;
+        // Every attempt at the first message, then the next message on the same worker
+        let sent = client.wait_for(PANIC_RETRIES as usize + 2).await;
+        assert_eq!(sent.last().unwrap().body["content"]["contacts"][0]["phones"][0]["phone"], "+15551230001");
+        assert_eq!(app.worker.metrics.worker_panics.get(), u64::from(PANIC_RETRIES) + 1);
+        eventually(|| app.worker.dead_letters.list(10).unwrap().len() == 1).await;
+        let dead = app.worker.dead_letters.list(10).unwrap();
+        assert_eq!(dead[0].message.correlation_id, "m1");
+        assert_eq!(dead[0].reason, "panicked: mock sender told to panic");
+    }
+
+    #[tokio::test]
+    async fn a_message_that_panicked_once_is_tried_again() {
+        let app = test_app(&[("WORKER_COUNT", "1")]).await;
+        app.worker.client.then_panic();
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        let sent = app.worker.client.wait_for(2).await;
+        assert_eq!(sent[1].kind, "contact");
+        assert_eq!(app.worker.metrics.worker_panics.get(), 1);
+        assert!(app.worker.dead_letters.list(10).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_send_failing_past_max_retries_is_dead_lettered() {
+        let app = test_app(&[("MAX_RETRIES", "2"), ("BASE_BACKOFF_MS", "1"), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        app.worker.client.then(Err(timeout())).then(Err(timeout())).then(Err(timeout()));
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        assert_eq!(app.worker.client.wait_for(3).await.len(), 3);
+        eventually(|| !app.worker.dead_letters.list(10).unwrap().is_empty()).await;
+
+        let response = admin_get(&app, "/deadletters").await;
+        assert_eq!(response.status(), 200);
+        let listed = response_json(&response);
+        let entries = listed.as_array().expect("a list of dead letters");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["message"]["correlation_id"], "m1");
+        assert_eq!(entries[0]["reason"], timeout().to_string());
+        // Nothing tries it again by itself
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(app.worker.client.sent().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_send_that_recovers_is_not_dead_lettered() {
+        let app = test_app(&[("MAX_RETRIES", "2"), ("BASE_BACKOFF_MS", "1")]).await;
+        app.worker.client.then(Err(timeout())).then(Err(timeout()));
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        app.worker.client.wait_for(3).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(app.worker.dead_letters.list(10).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_replayed_dead_letter_reaches_the_sender() {
+        let app = test_app(&[("MAX_RETRIES", "0"), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        app.worker.client.then(Err(timeout()));
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        app.worker.client.wait_for(1).await;
+        eventually(|| !app.worker.dead_letters.list(10).unwrap().is_empty()).await;
+        let id = app.worker.dead_letters.list(1).unwrap()[0].id;
+
+        let response = admin_post(&app, &format!("/deadletters/{}/replay", id)).await;
+        assert_eq!(response.status(), 202);
+        assert_eq!(response_json(&response)["correlation_id"], "m1");
+        let sent = app.worker.client.wait_for(2).await;
+        assert_eq!(sent[1].kind, "contact");
+        assert_eq!(sent[1].to(), "+15551234567");
+
+        assert_eq!(admin_post(&app, &format!("/deadletters/{}/replay", id)).await.status(), 409);
+        assert_eq!(admin_post(&app, &format!("/deadletters/{}/replay", id + 1)).await.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn replay_needs_the_admin_token() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let id = app.worker.dead_letters.record(&text_message("m1", "addcontact Jane Smith +15551230000"), "rejected").unwrap();
+        let path = format!("/deadletters/{}/replay", id);
+        assert_eq!(warp::test::request().method("POST").path(&path).reply(&app.routes).await.status(), 401);
+        assert!(app.worker.dead_letters.list(1).unwrap()[0].replayed_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn word_boundary_mode_ignores_the_trigger_inside_a_word() {
+        let app = test_app(&[("TRIGGER_WORDS", "add"), ("TRIGGER_MATCH_MODE", "word_boundary")]).await;
+        handle_webhook(text_message("m1", "paddle Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert!(app.worker.client.sent().is_empty());
+        handle_webhook(text_message("m2", "add Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent()[0].kind, "contact");
+    }
+
+    #[tokio::test]
+    async fn a_processed_message_produces_spans() {
+        use crate::telemetry::tests::{SpanCapture, attribute};
+
+        let capture = SpanCapture::default();
+        let _tracing = capture.start();
+        let app = test_app(&[]).await;
+        let response = webhook_request("/webhook", &inbound("m1", "addcontact Jane Smith +15551230000"))
+            .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .reply(&app.routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        app.worker.client.wait_for(1).await;
+        eventually(|| capture.spans().iter().filter(|span| span.name == "message").count() == 2).await;
+
+        let spans = capture.spans();
+        let send = spans.iter().find(|span| span.name == "send").expect("a send span");
+        assert_eq!(attribute(send, "recipient").as_deref(), Some("+15551234567"));
+        assert_eq!(attribute(send, "outcome").as_deref(), Some("sent"));
+        let processed = spans.iter().find(|span| span.name == "message" && attribute(span, "from").is_some()).unwrap();
+        assert_eq!(attribute(processed, "correlation_id").as_deref(), Some("m1"));
+        assert_eq!(attribute(processed, "from").as_deref(), Some(SENDER));
+        assert_eq!(send.parent_span_id, processed.span_context.span_id());
+        // The whole message is one trace, continuing the caller's
+        for name in ["webhook", "message", "send"] {
+            let span = spans.iter().find(|span| span.name == name).unwrap();
+            assert_eq!(span.span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736", "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_startup_succeeds_once_init_does() {
+        let mut calls = 0;
+        let result = retry_startup("test database", 3, Duration::from_millis(1), || {
+            calls += 1;
+            if calls < 3 { Err(BotError::Send("locked".to_string())) } else { Ok(calls) }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_startup_gives_up_after_the_attempts() {
+        let mut calls = 0;
+        let result: Result<(), BotError> = retry_startup("test database", 3, Duration::from_millis(1), || {
+            calls += 1;
+            Err(BotError::Send(format!("locked {}", calls)))
+        })
+        .await;
+        // The last error is the one returned
+        assert!(matches!(result, Err(BotError::Send(message)) if message == "locked 3"));
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn retry_startup_waits_between_attempts() {
+        let started = Instant::now();
+        let mut calls = 0;
+        let _ = retry_startup("test database", 3, Duration::from_millis(30), || {
+            calls += 1;
+            Err::<(), _>(BotError::Send("locked".to_string()))
+        })
+        .await;
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        // One attempt is no retry at all
+        let result = retry_startup("test database", 1, Duration::from_secs(60), || Err::<(), _>(BotError::Send("locked".to_string())));
+        assert!(tokio::time::timeout(Duration::from_secs(1), result).await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn media_goes_out_ahead_of_the_contact() {
+        let config = test_config(&[("MEDIA_CAPTION", "Acme Support")]);
+        let client = MockSender::new();
+        let contact = jane();
+        let media = Media { url: "https://example.com/logo.png".to_string(), kind: MediaKind::Image };
+        let send = Outbound { media: Some(&media), ..outbound(&contact) };
+        send_vcard(&client, &config, &Metrics::new(), &send).await.unwrap();
+        let sent = client.sent();
+        assert_eq!(sent.iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["image", "contact"]);
+        assert_eq!(sent[0].body["content"]["mediaUrl"], "https://example.com/logo.png");
+        assert_eq!(sent[0].body["content"]["caption"], "Acme Support");
+        assert_eq!(sent[0].to(), "+15551234567");
+    }
+
+    #[tokio::test]
+    async fn a_document_is_sent_with_its_filename() {
+        let config = test_config(&[]);
+        let client = MockSender::new();
+        let contact = jane();
+        let media = Media { url: "https://example.com/files/brochure.pdf".to_string(), kind: MediaKind::Document };
+        send_vcard(&client, &config, &Metrics::new(), &Outbound { media: Some(&media), ..outbound(&contact) }).await.unwrap();
+        let sent = client.sent();
+        assert_eq!(sent.iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["document", "contact"]);
+        assert_eq!(sent[0].body["content"]["filename"], "brochure.pdf");
+    }
+
+    #[tokio::test]
+    async fn only_the_contact_without_media() {
+        let app = test_app(&[]).await;
+        assert!(app.worker.media.is_none());
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent().iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["contact"]);
+    }
+
+    #[test]
+    fn media_url_must_be_https() {
+        let result = load_config(&test_settings(&[("MEDIA_URL", "http://example.com/logo.png")]));
+        assert!(matches!(result, Err(BotError::Config(message)) if message.contains("MEDIA_URL must be an https URL")));
+    }
+
+    #[tokio::test]
+    async fn the_send_past_the_daily_cap_is_refused() {
+        let app = test_app(&[("MAX_SENDS_PER_SENDER_PER_DAY", "2")]).await;
+        for (id, number) in [("m1", "+15551230001"), ("m2", "+15551230002")] {
+            handle_webhook(text_message(id, &format!("addcontact Jane Smith {}", number)), &app.worker).await.unwrap();
+        }
+        let response = post_webhook(&app, &inbound("m3", "addcontact Jane Smith +15551230003")).await;
+        assert_eq!(response.status(), 200);
+        let sent = app.worker.client.wait_for(3).await;
+        assert_eq!(sent.iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["contact", "contact", "text"]);
+        assert_eq!((sent[2].to(), sent[2].text()), (SENDER, "Sorry, you've reached the daily limit of 2 contact(s). Please try again tomorrow."));
+        assert_eq!(app.worker.metrics.daily_cap_reached.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_send_nobody_got_does_not_count_against_the_cap() {
+        let app = test_app(&[("MAX_SENDS_PER_SENDER_PER_DAY", "1")]).await;
+        app.worker.client.then(Err(BotError::Send("rejected".to_string())));
+        assert!(handle_webhook(text_message("m1", "addcontact Jane Smith +15551230001"), &app.worker).await.is_err());
+        handle_webhook(text_message("m2", "addcontact Jane Smith +15551230002"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent().iter().filter(|sent| sent.kind == "contact").count(), 2);
+        assert_eq!(app.worker.metrics.daily_cap_reached.get(), 0);
+    }
+
+    #[test]
+    fn timestamps_within_the_skew_either_side() {
+        let now = 1_760_000_000;
+        assert!(timestamp_in_window("1760000000", 300, now));
+        assert!(timestamp_in_window("1759999700", 300, now));
+        assert!(timestamp_in_window("1760000300", 300, now));
+        assert!(!timestamp_in_window("1759999699", 300, now));
+        assert!(!timestamp_in_window("1760000301", 300, now));
+        assert!(timestamp_in_window("2025-10-09T08:53:20Z", 300, now));
+        assert!(!timestamp_in_window("yesterday", 300, now));
+    }
+
+    // A webhook signed over "{timestamp}.{body}", sent with the timestamp header
+    async fn post_timestamped(app: &App<MockSender>, timestamp: i64) -> warp::http::Response<Bytes> {
+        let body = inbound(&format!("m{}", timestamp), "addcontact Jane Smith +15551230000");
+        let timestamp = timestamp.to_string();
+        webhook_request("/webhook", &body)
+            .header("X-Hub-Signature-256", sign("s3cret", &format!("{}.{}", timestamp, body)))
+            .header("X-Timestamp", timestamp)
+            .reply(&app.routes)
+            .await
+    }
+
+    #[tokio::test]
+    async fn webhook_timestamps_outside_the_window_are_refused() {
+        let app = test_app(&[("WEBHOOK_SECRET", "s3cret"), ("WEBHOOK_MAX_SKEW_SECS", "300")]).await;
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(post_timestamped(&app, now - 10).await.status(), 200);
+        let too_old = post_timestamped(&app, now - 600).await;
+        assert_eq!(too_old.status(), 401);
+        assert_eq!(response_json(&too_old)["error"], "stale_timestamp");
+        assert_eq!(post_timestamped(&app, now + 600).await.status(), 401);
+        app.worker.client.wait_for(1).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(app.worker.client.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_webhook_without_its_timestamp_is_refused() {
+        let app = test_app(&[("WEBHOOK_SECRET", "s3cret"), ("WEBHOOK_MAX_SKEW_SECS", "300")]).await;
+        let body = inbound("m1", "addcontact Jane Smith +15551230000");
+        let response = webhook_request("/webhook", &body)
+            .header("X-Hub-Signature-256", sign("s3cret", &body.to_string()))
+            .reply(&app.routes)
+            .await;
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn sends_go_out_from_the_routed_sender() {
+        let app = test_app(&[
+            ("SENDER_NUMBERS", "447860099300"),
+            ("SENDER_ROUTING", "round_robin"),
+            ("RECIPIENT_PHONE_NUMBER", "+15551230001,+15551230002,+15551230003"),
+        ])
+        .await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        let from: Vec<String> = app.worker.client.sent().iter().map(|sent| sent.body["from"].as_str().unwrap().to_string()).collect();
+        assert_eq!(from, ["447860099299", "447860099300", "447860099299"]);
+    }
+
+    async fn admin_request(app: &App<MockSender>, method: &str, path: &str, body: Option<serde_json::Value>) -> warp::http::Response<Bytes> {
+        let mut request = warp::test::request()
+            .method(method)
+            .path(path)
+            .header("authorization", format!("Bearer {}", ADMIN_TOKEN));
+        if let Some(body) = body {
+            request = request.header("content-type", "application/json").body(body.to_string());
+        }
+        request.reply(&app.routes).await
+    }
+
+    #[tokio::test]
+    async fn contacts_can_be_created_fetched_updated_and_deleted() {
+        let csv = directory::tests::TempCsv::new(directory::tests::SAMPLE_CSV);
+        let app = test_app(&[("CONTACTS_CSV", csv.path()), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let alice = |phone: &str| serde_json::json!({ "alias": "Alice", "first_name": "Alice", "phone": phone });
+
+        let created = admin_request(&app, "POST", "/contacts", Some(alice("+15551230009"))).await;
+        assert_eq!(created.status(), 201);
+        assert_eq!(response_json(&created)["alias"], "alice");
+        let fetched = admin_get(&app, "/contacts/alice").await;
+        assert_eq!(fetched.status(), 200);
+        assert_eq!(response_json(&fetched)["phone"], "+15551230009");
+        let listed = response_json(&admin_get(&app, "/contacts").await);
+        assert_eq!(listed.as_array().unwrap().len(), 3);
+
+        let updated = admin_request(&app, "POST", "/contacts", Some(alice("+1 555 123 0010"))).await;
+        assert_eq!(updated.status(), 200);
+        assert_eq!(response_json(&admin_get(&app, "/contacts/ALICE").await)["phone"], "+15551230010");
+        // The worker sees the change straight away
+        handle_webhook(text_message("m1", "addcontact alice"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent()[0].body["content"]["contacts"][0]["phones"][0]["phone"], "+15551230010");
+
+        assert_eq!(admin_request(&app, "DELETE", "/contacts/alice", None).await.status(), 204);
+        assert_eq!(admin_get(&app, "/contacts/alice").await.status(), 404);
+        assert_eq!(admin_request(&app, "DELETE", "/contacts/alice", None).await.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn a_contact_with_an_invalid_number_is_refused() {
+        let csv = directory::tests::TempCsv::new(directory::tests::SAMPLE_CSV);
+        let app = test_app(&[("CONTACTS_CSV", csv.path()), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let body = serde_json::json!({ "alias": "alice", "first_name": "Alice", "phone": "12345" });
+        let response = admin_request(&app, "POST", "/contacts", Some(body)).await;
+        assert_eq!(response.status(), 400);
+        assert_eq!(response_json(&response)["error"], "invalid_contact");
+        assert_eq!(admin_get(&app, "/contacts/alice").await.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn contacts_without_a_directory() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let response = admin_get(&app, "/contacts").await;
+        assert_eq!(response.status(), 404);
+        assert_eq!(response_json(&response)["error"], "not_configured");
+    }
+
+    #[tokio::test]
+    async fn an_unsupported_contact_card_falls_back_to_text() {
+        let config = test_config(&[("BASE_BACKOFF_MS", "1")]);
+        let client = MockSender::new();
+        client.then(Err(crate::error::tests::infobip_error(400, "UNSUPPORTED_MESSAGE_TYPE").into()));
+        let metrics = Metrics::new();
+        let contact = jane();
+        send_vcard(&client, &config, &metrics, &outbound(&contact)).await.unwrap();
+        let sent = client.sent();
+        assert_eq!(sent.iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["contact", "text"]);
+        assert!(sent[1].text().contains("BEGIN:VCARD"));
+        assert_eq!(metrics.contact_text_fallbacks.get(), 1);
+        assert_eq!(metrics.vcards_sent.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn other_contact_errors_do_not_fall_back_to_text() {
+        let config = test_config(&[("BASE_BACKOFF_MS", "1")]);
+        let client = MockSender::new();
+        client.then(Err(crate::error::tests::infobip_error(400, "BAD_REQUEST").into()));
+        let metrics = Metrics::new();
+        let contact = jane();
+        assert!(send_vcard(&client, &config, &metrics, &outbound(&contact)).await.is_err());
+        assert_eq!(client.sent().iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["contact"]);
+        assert_eq!(metrics.contact_text_fallbacks.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn pauses_between_sends_fall_within_the_jitter_range() {
+        let config = test_config(&[("SEND_JITTER_MS_MIN", "20"), ("SEND_JITTER_MS_MAX", "40")]);
+        let pacing = Pacing::new(&config, false, 4);
+        let started = std::time::Instant::now();
+        pacing.wait(0, 0).await;
+        assert!(started.elapsed() < Duration::from_millis(20), "no pause before the first send");
+        for sent in 1..4 {
+            let started = std::time::Instant::now();
+            pacing.wait(sent, sent).await;
+            let pause = started.elapsed();
+            assert!(pause >= Duration::from_millis(20) && pause < Duration::from_millis(60), "{:?}", pause);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_spread_broadcast_sends_each_recipient_in_its_slot() {
+        let config = test_config(&[("BROADCAST_SPREAD_MINUTES", "1"), ("SEND_JITTER_MS_MAX", "5000")]);
+        // 1200 recipients over a minute: a 50ms slot each
+        let pacing = Pacing::new(&config, true, 1200);
+        let started = std::time::Instant::now();
+        for index in 0..3 {
+            pacing.wait(index, index).await;
+            let due = Duration::from_millis(50 * index as u64);
+            let elapsed = started.elapsed();
+            assert!(elapsed >= due && elapsed < due + Duration::from_millis(70), "{:?}", elapsed);
+        }
+        // Replies to one requester ignore the spread
+        assert!(matches!(Pacing::new(&config, false, 1200), Pacing::Jitter { min_ms: 0, max_ms: 5000 }));
+    }
+
+    #[tokio::test]
+    async fn the_queue_can_be_inspected_and_flushed() {
+        let app = test_app(&[("WORKER_COUNT", "1"), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        // The worker hangs on the first message, the rest wait in the queue
+        app.worker.client.delay_by(Duration::from_secs(30));
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        app.worker.client.wait_for(1).await;
+        post_webhook(&app, &inbound("m2", "addcontact Jane Smith +15551230001")).await;
+        post_webhook(&app, &inbound("m3", "addcontact Jane Smith +15551230002")).await;
+
+        let status = response_json(&admin_get(&app, "/queue?limit=1").await);
+        assert_eq!(status["depth"], 2);
+        assert_eq!(status["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(status["messages"][0]["from"], "+15*******21");
+        assert_eq!(status["flush_token"], "flush-3");
+
+        let refused = admin_request(&app, "POST", "/queue/flush", None).await;
+        assert_eq!(refused.status(), 400);
+        assert_eq!(response_json(&refused)["error"], "confirmation_required");
+        let flushed = admin_request(&app, "POST", "/queue/flush?confirm=flush-3", None).await;
+        assert_eq!(response_json(&flushed)["flushed"], 2);
+        assert_eq!(response_json(&admin_get(&app, "/queue").await)["depth"], 0);
+        assert_eq!(get(&app, "/queue").await.status(), 401);
+    }
+
+    #[test]
+    fn vcard_keeps_emoji_and_cjk_names() {
+        let mut contact = jane();
+        contact.first_name = "Zoë 🎉".to_string();
+        contact.last_name = "李".to_string();
+        let vcard = generate_vcard(&contact, VCardVersion::V4_0);
+        assert!(vcard.contains("\nN:李;Zoë 🎉\n"), "{}", vcard);
+    }
+
+    #[test]
+    fn a_long_note_is_folded_at_75_octets() {
+        let mut contact = jane();
+        let note = "Met at the 東京 conference 🎉 to follow up about the partnership and the pilot next quarter";
+        contact.note = Some(note.to_string());
+        let vcard = generate_vcard(&contact, VCardVersion::V3_0);
+        assert!(vcard.lines().all(|line| line.len() <= 75), "{}", vcard);
+        let lines: Vec<&str> = vcard.lines().skip_while(|line| !line.starts_with("NOTE:")).collect();
+        assert!(lines[1].starts_with(' '), "{}", vcard);
+        // Unfolding gives the note back whole
+        let unfolded = vcard.replace("\n ", "");
+        assert!(unfolded.contains(&format!("\nNOTE:{}\n", note)), "{}", unfolded);
+    }
+
+    #[tokio::test]
+    async fn emoji_and_cjk_names_survive_the_webhook() {
+        let app = test_app(&[]).await;
+        handle_webhook(text_message("m1", "addcontact 🎉Zoë 李 +15551230000"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent[0].body["content"]["contacts"][0]["name"]["firstName"], "🎉Zoë");
+        assert_eq!(sent[0].body["content"]["contacts"][0]["name"]["lastName"], "李");
+    }
+
+    #[test]
+    fn the_signature_ends_the_text_message() {
+        let contact = jane();
+        let vcard = generate_vcard(&contact, VCardVersion::V3_0);
+        let unsigned = render_vcard_message(&test_config(&[]), &contact, &vcard);
+        let signed = render_vcard_message(&test_config(&[("BOT_SIGNATURE", "— Sent by AcmeBot")]), &contact, &vcard);
+        assert_eq!(signed, format!("{}\n— Sent by AcmeBot", unsigned));
+        // Blank is the same as unset
+        assert_eq!(render_vcard_message(&test_config(&[("BOT_SIGNATURE", "  ")]), &contact, &vcard), unsigned);
+    }
+
+    #[tokio::test]
+    async fn the_signature_counts_towards_the_message_length() {
+        let contact = jane();
+        let unsigned = render_vcard_message(&test_config(&[]), &contact, &generate_vcard(&contact, VCardVersion::V3_0));
+        let max = unsigned.chars().count().to_string();
+        let unsigned_app = test_app(&[("SEND_AS_TEXT", "true"), ("MAX_MESSAGE_CHARS", &max)]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &unsigned_app.worker).await.unwrap();
+        assert_eq!(unsigned_app.worker.client.sent().len(), 1);
+
+        // The same card no longer fits in one message once signed
+        let app = test_app(&[("SEND_AS_TEXT", "true"), ("MAX_MESSAGE_CHARS", &max), ("BOT_SIGNATURE", "— AcmeBot")]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|sent| sent.text().chars().count() <= unsigned.chars().count()));
+        assert!(sent[1].text().ends_with("\n— AcmeBot") || sent[1].text() == "— AcmeBot", "{}", sent[1].text());
+    }
+
+    #[test]
+    fn a_signature_longer_than_a_message_is_refused() {
+        let settings = test_settings(&[("BOT_SIGNATURE", "— Sent by AcmeBot"), ("MAX_MESSAGE_CHARS", "10")]);
+        let result = load_config(&settings);
+        assert!(matches!(result, Err(BotError::Config(message)) if message.contains("BOT_SIGNATURE is 17 characters")));
+    }
+
+    #[tokio::test]
+    async fn the_typed_casing_reaches_the_vcard() {
+        let app = test_app(&[("SEND_AS_TEXT", "true")]).await;
+        handle_webhook(text_message("m1", "ADDCONTACT Jane McDonald-O'Neil +15551230000"), &app.worker).await.unwrap();
+        handle_webhook(text_message("m2", "please addcontact DJ deLaCruz +15551230001"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert!(sent[0].text().contains("\nN:McDonald-O'Neil;Jane\n"), "{}", sent[0].text());
+        assert!(sent[1].text().contains("\nN:deLaCruz;DJ\n"), "{}", sent[1].text());
+    }
+
+    const DASHBOARD: &str = "https://admin.example.com";
+
+    async fn from_origin(app: &App<MockSender>, method: &str, path: &str, origin: &str) -> warp::http::Response<Bytes> {
+        let mut request = warp::test::request()
+            .method(method)
+            .path(path)
+            .header("origin", origin)
+            .header("authorization", format!("Bearer {}", ADMIN_TOKEN));
+        if method == "OPTIONS" {
+            request = request.header("access-control-request-method", "GET");
+        }
+        request.reply(&app.routes).await
+    }
+
+    #[tokio::test]
+    async fn cors_allows_the_configured_origin() {
+        let app = test_app(&[("CORS_ALLOWED_ORIGINS", DASHBOARD), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let response = from_origin(&app, "GET", "/info", DASHBOARD).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["access-control-allow-origin"], DASHBOARD);
+
+        let preflight = from_origin(&app, "OPTIONS", "/info", DASHBOARD).await;
+        assert_eq!(preflight.status(), 200);
+        assert_eq!(preflight.headers()["access-control-allow-origin"], DASHBOARD);
+    }
+
+    #[tokio::test]
+    async fn cors_refuses_other_origins() {
+        let app = test_app(&[("CORS_ALLOWED_ORIGINS", DASHBOARD), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let response = from_origin(&app, "GET", "/info", "https://evil.example.com").await;
+        assert!(!response.headers().contains_key("access-control-allow-origin"));
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn cors_leaves_the_webhook_alone() {
+        let app = test_app(&[("CORS_ALLOWED_ORIGINS", "*"), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let preflight = from_origin(&app, "OPTIONS", "/webhook", DASHBOARD).await;
+        assert!(!preflight.headers().contains_key("access-control-allow-origin"));
+        // And without the setting there is no CORS at all
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let response = from_origin(&app, "GET", "/info", DASHBOARD).await;
+        assert_eq!(response.status(), 200);
+        assert!(!response.headers().contains_key("access-control-allow-origin"));
+    }
+
+    #[test]
+    fn vcard_categories() {
+        let categories_line = |categories: &[&str]| {
+            let mut contact = jane();
+            contact.categories = categories.iter().map(|category| category.to_string()).collect();
+            generate_vcard(&contact, VCardVersion::V3_0).lines().find(|line| line.starts_with("CATEGORIES")).map(str::to_string)
+        };
+        assert_eq!(categories_line(&[]), None);
+        assert_eq!(categories_line(&["Support"]).as_deref(), Some("CATEGORIES:Support"));
+        assert_eq!(
+            categories_line(&["Support", "Sales, EMEA", "VIP"]).as_deref(),
+            Some("CATEGORIES:Support,Sales\\, EMEA,VIP")
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_categories_go_on_the_card() {
+        let csv = directory::tests::TempCsv::new(directory::tests::SAMPLE_CSV);
+        let app = test_app(&[("CONTACTS_CSV", csv.path()), ("SEND_AS_TEXT", "true")]).await;
+        let body = serde_json::json!({ "alias": "alice", "first_name": "Alice", "phone": "+15551230009", "categories": "Support;Sales" });
+        app.worker.directory.as_ref().unwrap().upsert(serde_json::from_value(body).unwrap()).unwrap().unwrap();
+        handle_webhook(text_message("m1", "addcontact alice"), &app.worker).await.unwrap();
+        assert!(app.worker.client.sent()[0].text().contains("\nCATEGORIES:Support,Sales\n"));
+    }
+
+    // An app whose workers were stopped, with a message waiting and a watchdog checking every 50ms
+    async fn stalled_app(settings: &[(&'static str, &str)]) -> App<MockSender> {
+        let app = test_app(settings).await;
+        for task in app.workers.lock().unwrap().iter() {
+            task.abort();
+        }
+        app.ready.store(true, Ordering::SeqCst);
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        tokio::spawn(run_watchdog(
+            app.worker.clone(),
+            app.queue_rx.clone(),
+            app.workers.clone(),
+            app.queue_tx.downgrade(),
+            app.ready.clone(),
+            Duration::from_millis(50),
+        ));
+        app
+    }
+
+    #[tokio::test]
+    async fn the_watchdog_reports_stalled_workers() {
+        let app = stalled_app(&[]).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!app.ready.load(Ordering::SeqCst));
+        assert_eq!(get(&app, "/ready").await.status(), 503);
+        // Reported once, not on every check
+        assert_eq!(app.worker.metrics.worker_stalls.get(), 1);
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_watchdog_can_respawn_stalled_workers() {
+        let app = stalled_app(&[("WATCHDOG_ACTION", "respawn")]).await;
+        let sent = app.worker.client.wait_for(1).await;
+        assert_eq!(sent[0].kind, "contact");
+        assert_eq!(app.worker.metrics.worker_stalls.get(), 1);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(app.ready.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn vcard_returns_the_contact_as_a_file() {
+        let app = test_app(&[]).await;
+        let response = get(&app, "/vcard?first_name=Jane&last_name=Smith&phone=%2B15551230000").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/vcard; charset=utf-8");
+        assert_eq!(response.headers()["content-disposition"], "attachment; filename=\"contact.vcf\"");
+        let body = std::str::from_utf8(response.body()).unwrap();
+        assert!(body.starts_with("BEGIN:VCARD\r\n"), "{}", body);
+        assert!(body.contains("\r\nN:Smith;Jane\r\n"), "{}", body);
+        assert!(body.ends_with("END:VCARD\r\n"), "{}", body);
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn vcard_explains_what_is_wrong_with_the_query() {
+        let app = test_app(&[]).await;
+        let missing = get(&app, "/vcard?first_name=Jane").await;
+        assert_eq!(missing.status(), 400);
+        assert_eq!(response_json(&missing)["error"], "invalid_contact");
+        assert!(response_json(&missing)["message"].as_str().unwrap().contains("phone"), "{:?}", missing.body());
+        assert_eq!(get(&app, "/vcard?first_name=Jane&phone=12345").await.status(), 400);
+        // Aliases need ADMIN_TOKEN
+        assert_eq!(get(&app, "/vcard?alias=jane").await.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn vcard_looks_up_an_alias() {
+        let csv = directory::tests::TempCsv::new(directory::tests::SAMPLE_CSV);
+        let app = test_app(&[("CONTACTS_CSV", csv.path()), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let response = admin_get(&app, "/vcard?alias=jane").await;
+        assert_eq!(response.status(), 200);
+        assert!(std::str::from_utf8(response.body()).unwrap().starts_with("BEGIN:VCARD"));
+        assert_eq!(admin_get(&app, "/vcard?alias=nobody").await.status(), 404);
+    }
+
+    async fn parse_test_of(app: &App<MockSender>, text: &str) -> serde_json::Value {
+        let body = serde_json::json!({ "text": text });
+        let response = admin_request(app, "POST", "/test/parse", Some(body)).await;
+        assert_eq!(response.status(), 200, "{:?}", response.body());
+        response_json(&response)
+    }
+
+    #[tokio::test]
+    async fn test_parse_reports_a_matching_command() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let report = parse_test_of(&app, "AddContact Jane Smith +1 555 123 0000").await;
+        assert_eq!(report["matched"], true);
+        assert_eq!(report["trigger"], "addcontact");
+        assert_eq!(report["command"], "Jane Smith +1 555 123 0000");
+        assert_eq!(report["contact"]["first_name"], "Jane");
+        assert_eq!(report["contact"]["phone_numbers"][0]["number"], "+15551230000");
+        assert_eq!(report["error"], serde_json::Value::Null);
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_parse_reports_text_without_a_trigger() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let report = parse_test_of(&app, "hello there").await;
+        assert_eq!(report["matched"], false);
+        assert_eq!(report["trigger"], serde_json::Value::Null);
+        assert_eq!(report["contact"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_parse_reports_a_malformed_command() {
+        let app = test_app(&[("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let report = parse_test_of(&app, "addcontact Jane Smith").await;
+        assert_eq!(report["matched"], true);
+        assert_eq!(report["contact"], serde_json::Value::Null);
+        assert_eq!(report["error"], "a phone number is required");
+        assert!(app.worker.client.sent().is_empty());
+        assert_eq!(warp::test::request().method("POST").path("/test/parse").reply(&app.routes).await.status(), 401);
+    }
+
+    #[test]
+    fn quoted_context_is_read_from_the_payload() {
+        let mut body = inbound("m2", "YES");
+        body["results"][0]["context"] = serde_json::json!({ "quotedMessageId": "prompt-1" });
+        let webhook: InboundWebhook = serde_json::from_value(body).unwrap();
+        let message: WhatsAppMessage = webhook.results.into_iter().next().unwrap().into();
+        assert_eq!(message.quoted_message_id.as_deref(), Some("prompt-1"));
+    }
+
+    #[test]
+    fn a_payload_without_context_quotes_nothing() {
+        assert_eq!(text_message("m2", "YES").quoted_message_id, None);
+        let mut body = inbound("m2", "YES");
+        body["results"][0]["context"] = serde_json::json!({});
+        let webhook: InboundWebhook = serde_json::from_value(body).unwrap();
+        let message: WhatsAppMessage = webhook.results.into_iter().next().unwrap().into();
+        assert_eq!(message.quoted_message_id, None);
+    }
+
+    #[tokio::test]
+    async fn a_quoted_reply_must_quote_the_prompt() {
+        let app = test_app(&[("REQUIRE_CONFIRMATION", "true")]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith +15551230000"), &app.worker).await.unwrap();
+        let prompt_id = app.worker.client.sent()[0].body["messageId"].as_str().unwrap().to_string();
+
+        let mut elsewhere = text_message("m2", "YES");
+        elsewhere.quoted_message_id = Some("some-other-message".to_string());
+        handle_webhook(elsewhere, &app.worker).await.unwrap();
+        assert!(app.worker.client.sent().iter().all(|sent| sent.kind != "contact"));
+
+        let mut reply = text_message("m3", "YES");
+        reply.quoted_message_id = Some(prompt_id);
+        handle_webhook(reply, &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent().last().unwrap().kind, "contact");
+    }
+
+    #[tokio::test]
+    async fn a_registered_sender_passes_the_startup_check() {
+        let config = test_config(&[("STARTUP_CHECKS", "true"), ("STRICT_STARTUP_CHECKS", "true")]);
+        let client = MockSender::new();
+        check_senders(&client, &config).await.unwrap();
+        let sent = client.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].kind, "check_sender");
+        assert_eq!(sent[0].body, "447860099299");
+    }
+
+    #[tokio::test]
+    async fn an_unregistered_sender_fails_only_a_strict_check() {
+        let refused = || BotError::from(crate::error::tests::infobip_error(404, "NOT_FOUND"));
+        let client = MockSender::new();
+        client.then(Err(refused()));
+        check_senders(&client, &test_config(&[("STARTUP_CHECKS", "true")])).await.unwrap();
+
+        client.then(Err(refused()));
+        let strict = test_config(&[("STARTUP_CHECKS", "true"), ("STRICT_STARTUP_CHECKS", "true")]);
+        let result = check_senders(&client, &strict).await;
+        assert!(matches!(result, Err(BotError::Config(message)) if message.contains("sender 447860099299 failed the startup check")));
+    }
+
+    #[tokio::test]
+    async fn the_startup_check_can_be_skipped() {
+        let client = MockSender::new();
+        check_senders(&client, &test_config(&[("STARTUP_CHECKS", "false"), ("STRICT_STARTUP_CHECKS", "true")])).await.unwrap();
+        check_senders(&client, &test_config(&[("STARTUP_CHECKS", "true"), ("DRY_RUN", "true")])).await.unwrap();
+        assert!(client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_priority_message_overtakes_a_broadcast_backlog() {
+        let app = test_app(&[("WORKER_COUNT", "1")]).await;
+        app.worker.client.delay_by(Duration::from_millis(50));
+        let pending = PendingQueue::new();
+        let queue = |lane, name: &str| {
+            let message = text_message(name, &format!("addcontact {} Smith +15551230000", name));
+            enqueue_message(&app.queue_tx, lane, &app.worker.metrics, &pending, message).unwrap();
+        };
+        for name in ["Ann", "Bob", "Cat", "Dan"] {
+            queue(Lane::Normal, name);
+        }
+        // The worker is busy with the first of the backlog by now
+        app.worker.client.wait_for(1).await;
+        queue(Lane::Priority, "Pat");
+
+        let sent = app.worker.client.wait_for(5).await;
+        let names: Vec<&str> =
+            sent.iter().map(|sent| sent.body["content"]["contacts"][0]["name"]["firstName"].as_str().unwrap()).collect();
+        assert_eq!(names, ["Ann", "Pat", "Bob", "Cat", "Dan"]);
+    }
+
+    #[tokio::test]
+    async fn a_local_number_is_sent_with_the_default_country_code() {
+        let app = test_app(&[("DEFAULT_COUNTRY_CODE", "44")]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Smith 07700 900123"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent()[0].body["content"]["contacts"][0]["phones"][0]["phone"], "+447700900123");
+    }
+
+    const OFFICE: &[(&str, &str)] = &[
+        ("LOCATION_LATITUDE", "45.8150"),
+        ("LOCATION_LONGITUDE", "15.9819"),
+        ("LOCATION_NAME", "Acme HQ"),
+        ("LOCATION_ADDRESS", "Ilica 1, Zagreb"),
+    ];
+
+    #[tokio::test]
+    async fn directions_sends_the_office_location() {
+        let app = test_app(OFFICE).await;
+        handle_webhook(text_message("m1", " Directions "), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].kind, "location");
+        assert_eq!(sent[0].to(), SENDER);
+        let content = &sent[0].body["content"];
+        assert_eq!(content["latitude"], 45.815);
+        assert_eq!(content["longitude"], 15.9819);
+        assert_eq!(content["name"], "Acme HQ");
+        assert_eq!(content["address"], "Ilica 1, Zagreb");
+    }
+
+    #[tokio::test]
+    async fn directions_without_a_location_sends_none() {
+        let app = test_app(&[]).await;
+        handle_webhook(text_message("m1", "directions"), &app.worker).await.unwrap();
+        assert!(app.worker.client.sent().iter().all(|sent| sent.kind != "location"));
+    }
+
+    #[tokio::test]
+    async fn an_inbound_location_is_parsed() {
+        let body = serde_json::json!({
+            "results": [{
+                "from": SENDER,
+                "messageId": "m1",
+                "message": { "type": "LOCATION", "latitude": 45.815, "longitude": 15.9819, "name": "Cafe", "address": "Ilica 2" },
+            }]
+        });
+        let webhook: InboundWebhook = serde_json::from_value(body.clone()).unwrap();
+        let message: WhatsAppMessage = webhook.results.into_iter().next().unwrap().into();
+        let location = message.location.as_ref().unwrap();
+        assert_eq!((location.latitude, location.longitude), (45.815, 15.9819));
+        assert_eq!(location.name.as_deref(), Some("Cafe"));
+        assert_eq!(location.address.as_deref(), Some("Ilica 2"));
+        assert_eq!(message.text, None);
+
+        // Noted, nothing is sent back
+        let app = test_app(OFFICE).await;
+        assert_eq!(post_webhook(&app, &body).await.status(), 200);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_unmatched_message_gets_a_hint_when_enabled() {
+        let app = test_app(&[("REPLY_ON_NO_MATCH", "true"), ("NO_MATCH_MESSAGE", "Try '{trigger} <name> <number>'")]).await;
+        handle_webhook(text_message("m1", "hello there"), &app.worker).await.unwrap();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to(), SENDER);
+        assert_eq!(sent[0].text(), "Try 'addcontact <name> <number>'");
+    }
+
+    #[tokio::test]
+    async fn an_unmatched_message_gets_no_reply_by_default() {
+        let app = test_app(&[]).await;
+        handle_webhook(text_message("m1", "hello there"), &app.worker).await.unwrap();
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_suppressed_sender_gets_no_hint() {
+        let app = test_app(&[("REPLY_ON_NO_MATCH", "true")]).await;
+        app.worker.suppressions.suppress(SENDER).unwrap();
+        handle_webhook(text_message("m1", "hello there"), &app.worker).await.unwrap();
+        assert!(app.worker.client.sent().is_empty());
+        assert_eq!(app.worker.metrics.suppressed_sends.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn what_is_left_at_the_drain_timeout_is_dead_lettered() {
+        let app = test_app(&[("WORKER_COUNT", "1")]).await;
+        app.worker.client.delay_by(Duration::from_secs(30));
+        for (id, number) in [("m1", "+15551230000"), ("m2", "+15551230001"), ("m3", "+15551230002")] {
+            post_webhook(&app, &inbound(id, &format!("addcontact Jane Smith {}", number))).await;
+        }
+        // The worker hangs on the first, the other two wait in the queue
+        app.worker.client.wait_for(1).await;
+
+        let App { routes, worker, queue_tx, queue_rx, workers, .. } = app;
+        drop(routes);
+        let started = std::time::Instant::now();
+        assert!(!drain(&worker, queue_tx, &workers, &queue_rx, Duration::from_millis(100)).await);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let dead = worker.dead_letters.list(10).unwrap();
+        assert_eq!(dead.len(), 2);
+        assert!(dead.iter().all(|entry| entry.reason == "not processed before the drain timeout"));
+        assert_eq!(worker.pending.snapshot(10).0, 0);
+    }
+
+    #[tokio::test]
+    async fn a_queue_that_drains_in_time_is_a_clean_shutdown() {
+        let app = test_app(&[("WORKER_COUNT", "1")]).await;
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        let App { routes, worker, queue_tx, queue_rx, workers, .. } = app;
+        drop(routes);
+        assert!(drain(&worker, queue_tx, &workers, &queue_rx, Duration::from_secs(5)).await);
+        assert_eq!(worker.client.sent().len(), 1);
+        assert!(worker.dead_letters.list(10).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_caption_goes_right_before_the_card() {
+        let config = test_config(&[("CONTACT_CAPTION", "Here's who to call")]);
+        let client = MockSender::new();
+        let contact = jane();
+        send_vcard(&client, &config, &Metrics::new(), &outbound(&contact)).await.unwrap();
+        let sent = client.sent();
+        assert_eq!(sent.iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["text", "contact"]);
+        assert_eq!(sent[0].text(), "Here's who to call");
+        assert_eq!(sent[0].to(), sent[1].to());
+    }
+
+    #[tokio::test]
+    async fn a_failed_card_is_retried_without_the_caption() {
+        let config = test_config(&[("CONTACT_CAPTION", "Here's who to call"), ("BASE_BACKOFF_MS", "1")]);
+        let client = MockSender::new();
+        client.then(Ok(())).then(Err(timeout()));
+        let contact = jane();
+        send_vcard(&client, &config, &Metrics::new(), &outbound(&contact)).await.unwrap();
+        assert_eq!(client.sent().iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["text", "contact", "contact"]);
+    }
+
+    #[tokio::test]
+    async fn a_failed_caption_holds_back_the_card() {
+        let config = test_config(&[("CONTACT_CAPTION", "Here's who to call"), ("BASE_BACKOFF_MS", "1")]);
+        let client = MockSender::new();
+        client.then(Err(timeout()));
+        let contact = jane();
+        send_vcard(&client, &config, &Metrics::new(), &outbound(&contact)).await.unwrap();
+        assert_eq!(client.sent().iter().map(|sent| sent.kind).collect::<Vec<_>>(), ["text", "text", "contact"]);
+    }
+
+    #[tokio::test]
+    async fn a_text_vcard_gets_no_caption() {
+        let config = test_config(&[("CONTACT_CAPTION", "Here's who to call"), ("SEND_AS_TEXT", "true")]);
+        let client = MockSender::new();
+        let contact = jane();
+        send_vcard(&client, &config, &Metrics::new(), &outbound(&contact)).await.unwrap();
+        let sent = client.sent();
+        assert_eq!(sent.len(), 1);
+        assert!(!sent[0].text().contains("Here's who to call"));
+    }
+
+    #[tokio::test]
+    async fn every_request_gets_an_access_log_line() {
+        let app = test_app(&[]).await;
+        let logs = LogCapture::default();
+        let _capture = logs.start();
+        let response = warp::test::request()
+            .method("GET")
+            .path("/health?token=hunter2")
+            .header("x-request-id", "req-42")
+            .reply(&app.routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let lines = logs.lines();
+        let access = lines.iter().find(|line| line.contains("tool::access")).unwrap_or_else(|| panic!("{:#?}", lines));
+        assert!(access.contains("method=GET"), "{}", access);
+        assert!(access.contains("path=\"/health\""), "{}", access);
+        assert!(access.contains("status=200"), "{}", access);
+        assert!(access.contains("duration_ms="), "{}", access);
+        assert!(access.contains("correlation_id=\"req-42\""), "{}", access);
+        // The query string stays out of the log
+        assert!(lines.iter().all(|line| !line.contains("hunter2")), "{:#?}", lines);
+    }
+
+    fn limits() -> FieldLimits {
+        FieldLimits::from_config(&test_config(&[("MAX_NAME_CHARS", "4"), ("MAX_NOTE_CHARS", "10")]))
+    }
+
+    #[test]
+    fn a_field_at_its_limit_passes() {
+        let mut contact = jane();
+        contact.last_name = "Doe".to_string();
+        contact.note = Some("x".repeat(10));
+        limits().check(&contact).unwrap();
+        // Measured trimmed
+        contact.first_name = "  Zoë  ".to_string();
+        limits().check(&contact).unwrap();
+    }
+
+    #[test]
+    fn a_field_over_its_limit_is_named() {
+        let contact = jane();
+        let error = limits().check(&contact).unwrap_err();
+        assert!(matches!(error, BotError::FieldTooLong { field: "last name", length: 5, max: 4 }), "{:?}", error);
+        let mut contact = jane();
+        contact.last_name = "Doe".to_string();
+        contact.note = Some("x".repeat(11));
+        assert_eq!(limits().check(&contact).unwrap_err().to_string(), "the note is 11 characters, over the 10 character limit");
+    }
+
+    #[tokio::test]
+    async fn the_reply_names_the_field_that_is_too_long() {
+        let app = test_app(&[("MAX_NAME_CHARS", "10")]).await;
+        handle_webhook(text_message("m1", "addcontact Jane Bartholomew-Smith +15551230000"), &app.worker).await.unwrap_err();
+        let sent = app.worker.client.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to(), SENDER);
+        assert!(sent[0].text().contains("the last name is 17 characters, over the 10 character limit"), "{}", sent[0].text());
+    }
+
+    #[tokio::test]
+    async fn the_contacts_api_refuses_an_over_long_field() {
+        let csv = directory::tests::TempCsv::new(directory::tests::SAMPLE_CSV);
+        let app = test_app(&[("CONTACTS_CSV", csv.path()), ("ADMIN_TOKEN", ADMIN_TOKEN), ("MAX_NOTE_CHARS", "10")]).await;
+        let body = serde_json::json!({ "alias": "alice", "first_name": "Alice", "phone": "+15551230009", "note": "x".repeat(11) });
+        let response = admin_request(&app, "POST", "/contacts", Some(body)).await;
+        assert_eq!(response.status(), 400);
+        assert!(response_json(&response)["message"].as_str().unwrap().contains("the note is 11 characters"));
+    }
+
+    #[test]
+    fn a_batch_is_one_full_card_per_contact() {
+        let mut bob = jane();
+        bob.first_name = "Bob".to_string();
+        bob.last_name = "O'Brien, Jr.".to_string();
+        let batch = generate_vcard_batch(&[jane(), bob], VCardVersion::V3_0);
+        let cards: Vec<&str> = batch.split_inclusive("END:VCARD").map(str::trim).collect();
+        assert_eq!(cards.len(), 2);
+        for card in &cards {
+            assert!(card.starts_with("BEGIN:VCARD\nVERSION:3.0\n"), "{}", card);
+            assert!(card.ends_with("END:VCARD"), "{}", card);
+        }
+        assert!(cards[0].contains("\nN:Smith;Jane\n"));
+        // Each card is escaped on its own
+        assert!(cards[1].contains("\nN:O'Brien\\, Jr.;Bob\n"), "{}", cards[1]);
+    }
+
+    #[tokio::test]
+    async fn the_batch_route_returns_both_contacts() {
+        let csv = directory::tests::TempCsv::new(directory::tests::SAMPLE_CSV);
+        let app = test_app(&[("CONTACTS_CSV", csv.path()), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let response = admin_get(&app, "/vcard/batch?aliases=jane,bob").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/vcard; charset=utf-8");
+        assert_eq!(response.headers()["content-disposition"], "attachment; filename=\"contacts.vcf\"");
+        let body = std::str::from_utf8(response.body()).unwrap();
+        assert_eq!(body.matches("BEGIN:VCARD\r\n").count(), 2, "{}", body);
+        assert_eq!(body.matches("END:VCARD\r\n").count(), 2, "{}", body);
+        assert!(body.contains("\r\nN:Smith;Jane\r\n") && body.contains("\r\nN:;Bob\r\n"), "{}", body);
+
+        // By category, only jane is in Sales
+        let sales = admin_get(&app, "/vcard/batch?group=sales").await;
+        assert_eq!(std::str::from_utf8(sales.body()).unwrap().matches("BEGIN:VCARD").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn an_empty_batch_is_refused() {
+        let csv = directory::tests::TempCsv::new(directory::tests::SAMPLE_CSV);
+        let app = test_app(&[("CONTACTS_CSV", csv.path()), ("ADMIN_TOKEN", ADMIN_TOKEN)]).await;
+        let empty = admin_get(&app, "/vcard/batch?group=marketing").await;
+        assert_eq!(empty.status(), 400);
+        assert_eq!(response_json(&empty)["error"], "no_contacts");
+        assert_eq!(admin_get(&app, "/vcard/batch").await.status(), 400);
+        assert_eq!(admin_get(&app, "/vcard/batch?aliases=jane,nobody").await.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn the_ack_goes_out_before_the_contact() {
+        let app = test_app(&[("ACK_MESSAGE", "Got it, working on that…"), ("WORKER_COUNT", "1")]).await;
+        assert_eq!(post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await.status(), 200);
+        let sent = app.worker.client.wait_for(2).await;
+        assert_eq!(sent[0].kind, "text");
+        assert_eq!(sent[0].to(), SENDER);
+        assert_eq!(sent[0].text(), "Got it, working on that…");
+        assert_eq!(sent[1].kind, "contact");
+    }
+
+    #[tokio::test]
+    async fn with_several_workers_the_ack_still_goes_out_before_the_contact() {
+        let app = test_app(&[
+            ("ACK_MESSAGE", "Got it"),
+            ("WORKER_COUNT", "4"),
+            ("PER_RECIPIENT_RATE_PER_MINUTE", "300"),
+            ("PER_RECIPIENT_BURST", "1"),
+        ])
+        .await;
+        // The sender's budget is spent, so the ack waits a moment while an idle worker could
+        // already be sending the contact to the recipient
+        app.worker.recipient_limiter.acquire(SENDER).await;
+        assert_eq!(post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await.status(), 200);
+        let sent = app.worker.client.wait_for(2).await;
+        assert_eq!(sent[0].kind, "text");
+        assert_eq!(sent[0].to(), SENDER);
+        assert_eq!(sent[1].kind, "contact");
+    }
+
+    #[tokio::test]
+    async fn only_triggered_messages_are_acknowledged() {
+        let app = test_app(&[("ACK_MESSAGE", "Got it")]).await;
+        post_webhook(&app, &inbound("m1", "hello there")).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(app.worker.client.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_suppressed_sender_gets_no_ack() {
+        let app = test_app(&[("ACK_MESSAGE", "Got it"), ("WORKER_COUNT", "1")]).await;
+        app.worker.suppressions.suppress(SENDER).unwrap();
+        post_webhook(&app, &inbound("m1", "addcontact Jane Smith +15551230000")).await;
+        let sent = app.worker.client.wait_for(1).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(app.worker.client.sent().len(), 1);
+        assert_eq!(sent[0].kind, "contact");
+    }
+
+    #[test]
+    fn trigger_words_are_trimmed_and_deduplicated() {
+        let config = test_config(&[("TRIGGER_WORDS", " addcontact , AddContact,share,, SHARE ,addcontact ")]);
+        assert_eq!(config.trigger_words, ["addcontact", "share"]);
+        assert_eq!(config.duplicate_trigger_words, ["AddContact", "SHARE", "addcontact"]);
+    }
+
+    #[test]
+    fn the_first_spelling_of_a_trigger_is_kept() {
+        let (words, duplicates) = parse_trigger_words("AddContact,addcontact", TriggerMatchMode::WordBoundary);
+        assert_eq!(words, ["AddContact"]);
+        assert_eq!(duplicates, ["addcontact"]);
+        let (words, duplicates) = parse_trigger_words("share , add", TriggerMatchMode::Contains);
+        assert_eq!(words, ["share", "add"]);
+        assert!(duplicates.is_empty());
+    }
+
+    #[test]
+    fn regex_triggers_are_only_dropped_when_exactly_repeated() {
+        let (words, duplicates) = parse_trigger_words(r"\d+,\D+, \d+ ", TriggerMatchMode::Regex);
+        assert_eq!(words, [r"\d+", r"\D+"]);
+        assert_eq!(duplicates, [r"\d+"]);
+    }
+
+    #[tokio::test]
+    async fn a_deduplicated_trigger_still_matches_any_case() {
+        let app = test_app(&[("TRIGGER_WORDS", "AddContact, addcontact")]).await;
+        handle_webhook(text_message("m1", "ADDCONTACT Jane Smith +15551230000"), &app.worker).await.unwrap();
+        assert_eq!(app.worker.client.sent()[0].kind, "contact");
+    }
+
+    #[test]
+    fn the_api_key_or_its_file_must_be_set_but_not_both() {
+        let neither = load_config(&test_settings(&[("INFOBIP_API_KEY", "")]));
+        assert!(matches!(neither, Err(BotError::Config(message)) if message.contains("INFOBIP_API_KEY_FILE")));
+        let both = load_config(&test_settings(&[("INFOBIP_API_KEY_FILE", "/run/secrets/infobip")]));
+        assert!(matches!(both, Err(BotError::Config(message)) if message.contains("only one")));
+    }
+
+    #[test]
+    fn the_api_key_is_read_from_its_file() {
+        let file = TempConfigFile::new("file-key\n");
+        let config = test_config(&[("INFOBIP_API_KEY", ""), ("INFOBIP_API_KEY_FILE", file.0.to_str().unwrap())]);
+        assert_eq!(config.infobip_api_key, None);
+        assert_eq!(api_key(&config).unwrap(), "file-key");
+        assert_eq!(api_key(&test_config(&[])).unwrap(), "test-key");
+    }
+
+    #[test]
+    fn a_refused_key_is_read_again_from_its_file() {
+        let file = TempConfigFile::new("file-key");
+        let path = file.0.to_str().unwrap();
+        let cli = Cli::parse_from(["tool"]);
+        let config = test_config(&[("INFOBIP_API_KEY", ""), ("INFOBIP_API_KEY_FILE", path)]);
+        assert!(key_source(&cli, &config).is_none());
+
+        let config = test_config(&[
+            ("INFOBIP_API_KEY", ""),
+            ("INFOBIP_API_KEY_FILE", path),
+            ("REFRESH_CREDENTIALS_ON_AUTH_ERROR", "true"),
+        ]);
+        let read_key = key_source(&cli, &config).unwrap();
+        std::fs::write(&file.0, "rotated-key\n").unwrap();
+        assert_eq!(read_key().unwrap(), "rotated-key");
+    }
+
+    #[tokio::test]
+    async fn a_key_file_mounted_late_is_waited_for() {
+        let path = std::env::temp_dir().join(format!("tool-test-{}.key", uuid::Uuid::new_v4()));
+        let config = test_config(&[
+            ("INFOBIP_API_KEY", ""),
+            ("INFOBIP_API_KEY_FILE", path.to_str().unwrap()),
+            ("STARTUP_RETRY_ATTEMPTS", "3"),
+            ("STARTUP_RETRY_DELAY_SECS", "1"),
+        ]);
+        let mount = path.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            std::fs::write(&mount, "file-key\n").unwrap();
+        });
+        let _file = TempConfigFile(path);
+        assert_eq!(startup_api_key(&config).await.unwrap(), "file-key");
+    }
+
+    #[tokio::test]
+    async fn a_key_file_that_never_appears_fails_startup() {
+        let path = std::env::temp_dir().join(format!("tool-test-{}.key", uuid::Uuid::new_v4()));
+        let config = test_config(&[
+            ("INFOBIP_API_KEY", ""),
+            ("INFOBIP_API_KEY_FILE", path.to_str().unwrap()),
+            ("STARTUP_RETRY_ATTEMPTS", "1"),
+        ]);
+        assert!(matches!(startup_api_key(&config).await, Err(BotError::Config(message)) if message.contains("API key file")));
+    }
+}
@@ -0,0 +1,122 @@
+// An image or document sent ahead of the contact, e.g. a company logo
+use std::time::Duration;
+
+use reqwest::header::CONTENT_TYPE;
+
+use crate::error::BotError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaKind {
+    Image,
+    Document,
+}
+
+#[derive(Debug, Clone)]
+pub struct Media {
+    pub url: String,
+    pub kind: MediaKind,
+}
+
+impl Media {
+    // The last path segment, which WhatsApp shows as the document's name
+    pub fn filename(&self) -> Option<String> {
+        let url = reqwest::Url::parse(&self.url).ok()?;
+        let name = url.path_segments()?.next_back()?;
+        (!name.is_empty()).then(|| name.to_string())
+    }
+}
+
+// WhatsApp only renders JPEG and PNG as images; anything else it accepts goes as a document
+fn media_kind(content_type: &str) -> Option<MediaKind> {
+    let content_type = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+    match content_type.as_str() {
+        "image/jpeg" | "image/png" => Some(MediaKind::Image),
+        "application/pdf"
+        | "application/msword"
+        | "application/vnd.ms-excel"
+        | "application/vnd.ms-powerpoint"
+        | "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        | "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+        | "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+        | "text/plain" => Some(MediaKind::Document),
+        _ => None,
+    }
+}
+
+// Check at startup that the URL is up and serves something WhatsApp can send, since Infobip only
+// fetches it when a message goes out
+pub async fn probe(url: &str, timeout: Duration) -> Result<Media, BotError> {
+    let unusable = |reason: String| BotError::Config(format!("MEDIA_URL {} is not usable: {}", url, reason));
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(|e| unusable(e.to_string()))?;
+    let mut response = client.head(url).send().await.map_err(|e| unusable(e.to_string()))?;
+    // Some static hosts don't answer HEAD
+    if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+        response = client.get(url).send().await.map_err(|e| unusable(e.to_string()))?;
+    }
+    if !response.status().is_success() {
+        return Err(unusable(format!("it returned {}", response.status())));
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let kind = media_kind(content_type)
+        .ok_or_else(|| unusable(format!("unsupported content type '{}'", content_type)))?;
+    Ok(Media { url: url.to_string(), kind })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use warp::Filter;
+
+    use super::*;
+
+    #[test]
+    fn content_types_map_to_what_whatsapp_renders() {
+        assert_eq!(media_kind("image/png"), Some(MediaKind::Image));
+        assert_eq!(media_kind("IMAGE/JPEG; charset=binary"), Some(MediaKind::Image));
+        assert_eq!(media_kind("application/pdf"), Some(MediaKind::Document));
+        assert_eq!(media_kind("image/gif"), None);
+        assert_eq!(media_kind(""), None);
+    }
+
+    #[test]
+    fn the_filename_is_the_last_path_segment() {
+        let media = |url: &str| Media { url: url.to_string(), kind: MediaKind::Document };
+        assert_eq!(media("https://example.com/files/brochure.pdf?v=2").filename().as_deref(), Some("brochure.pdf"));
+        assert_eq!(media("https://example.com/").filename(), None);
+    }
+
+    // Serves GET /logo with the given content type, and 404 for anything else
+    fn serve(content_type: &'static str) -> SocketAddr {
+        let route = warp::get()
+            .and(warp::path("logo"))
+            .map(move || warp::reply::with_header("data", "content-type", content_type));
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn probe_finds_the_media_kind() {
+        // warp answers HEAD on a GET route with 405, so this also covers the GET fallback
+        let addr = serve("image/png");
+        let media = probe(&format!("http://{}/logo", addr), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(media.kind, MediaKind::Image);
+    }
+
+    #[tokio::test]
+    async fn probe_refuses_unusable_media() {
+        let addr = serve("text/html");
+        let unsupported = probe(&format!("http://{}/logo", addr), Duration::from_secs(5)).await;
+        assert!(matches!(unsupported, Err(BotError::Config(message)) if message.contains("unsupported content type 'text/html'")));
+        let missing = probe(&format!("http://{}/missing", addr), Duration::from_secs(5)).await;
+        assert!(matches!(missing, Err(BotError::Config(message)) if message.contains("404")));
+    }
+}
@@ -0,0 +1,124 @@
+// Prometheus counters and histograms, scraped from GET /metrics
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+
+pub struct Metrics {
+    registry: Registry,
+    pub messages_received: IntCounter,
+    pub triggers_matched: IntCounter,
+    pub vcards_sent: IntCounter,
+    pub send_failures: IntCounter,
+    pub send_latency: Histogram,
+    pub circuit_breaker_state: IntGauge,
+    pub delivery_reports: IntCounterVec,
+    pub suppressed_sends: IntCounter,
+    pub queue_full: IntCounter,
+    pub queue_depth: IntGauge,
+    pub worker_panics: IntCounter,
+    pub worker_stalls: IntCounter,
+    pub daily_cap_reached: IntCounter,
+    pub contact_text_fallbacks: IntCounter,
+    pub duplicate_contacts: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let messages_received = IntCounter::new("messages_received_total", "Inbound messages handed to the worker")
+            .expect("valid metric");
+        let triggers_matched = IntCounter::new("triggers_matched_total", "Inbound messages containing a trigger word")
+            .expect("valid metric");
+        let vcards_sent = IntCounter::new("vcards_sent_total", "vCards delivered to a recipient")
+            .expect("valid metric");
+        let send_failures = IntCounter::new("send_failures_total", "vCard sends that failed after all retries")
+            .expect("valid metric");
+        let send_latency = Histogram::with_opts(HistogramOpts::new(
+            "infobip_send_duration_seconds",
+            "Time taken by a single Infobip send request",
+        ))
+        .expect("valid metric");
+        let circuit_breaker_state = IntGauge::new(
+            "circuit_breaker_state",
+            "Infobip circuit breaker state: 0 closed, 1 open, 2 half-open",
+        )
+        .expect("valid metric");
+        let delivery_reports = IntCounterVec::new(
+            Opts::new("delivery_reports_total", "Delivery reports received from Infobip, by status"),
+            &["status"],
+        )
+        .expect("valid metric");
+        let suppressed_sends = IntCounter::new(
+            "suppressed_sends_total",
+            "Sends skipped because the recipient opted out",
+        )
+        .expect("valid metric");
+        let queue_full = IntCounter::new("queue_full_total", "Webhook messages rejected because the queue was full")
+            .expect("valid metric");
+        let queue_depth = IntGauge::new("queue_depth", "Messages waiting in the queue for a worker")
+            .expect("valid metric");
+        let worker_panics = IntCounter::new("worker_panics_total", "Messages whose processing panicked")
+            .expect("valid metric");
+        let worker_stalls = IntCounter::new("worker_stalls_total", "Times the watchdog found the workers stalled")
+            .expect("valid metric");
+        let daily_cap_reached = IntCounter::new(
+            "daily_cap_reached_total",
+            "vCards not sent because the sender reached MAX_SENDS_PER_SENDER_PER_DAY",
+        )
+        .expect("valid metric");
+        let contact_text_fallbacks = IntCounter::new(
+            "contact_text_fallbacks_total",
+            "Contact cards the recipient couldn't receive, sent as a text vCard instead",
+        )
+        .expect("valid metric");
+        let duplicate_contacts = IntCounter::new(
+            "duplicate_contacts_total",
+            "vCards not sent because an identical contact went to the same recipient recently",
+        )
+        .expect("valid metric");
+        for collector in [
+            Box::new(messages_received.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(triggers_matched.clone()),
+            Box::new(vcards_sent.clone()),
+            Box::new(send_failures.clone()),
+            Box::new(send_latency.clone()),
+            Box::new(circuit_breaker_state.clone()),
+            Box::new(delivery_reports.clone()),
+            Box::new(suppressed_sends.clone()),
+            Box::new(queue_full.clone()),
+            Box::new(queue_depth.clone()),
+            Box::new(worker_panics.clone()),
+            Box::new(worker_stalls.clone()),
+            Box::new(daily_cap_reached.clone()),
+            Box::new(contact_text_fallbacks.clone()),
+            Box::new(duplicate_contacts.clone()),
+        ] {
+            registry.register(collector).expect("metric registered once");
+        }
+        Metrics {
+            registry,
+            messages_received,
+            triggers_matched,
+            vcards_sent,
+            send_failures,
+            send_latency,
+            circuit_breaker_state,
+            delivery_reports,
+            suppressed_sends,
+            queue_full,
+            queue_depth,
+            worker_panics,
+            worker_stalls,
+            daily_cap_reached,
+            contact_text_fallbacks,
+            duplicate_contacts,
+        }
+    }
+
+    // Everything registered, in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding into a Vec can't fail");
+        String::from_utf8(buffer).expect("Prometheus text format is UTF-8")
+    }
+}
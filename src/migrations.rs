@@ -0,0 +1,252 @@
+// The SQLite schema shared by the stores, created and upgraded on startup. Each migration runs
+// once and is recorded in schema_version; they only ever add, so an upgrade keeps the data.
+use std::time::Duration;
+
+use log::{info, warn};
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+
+use crate::error::BotError;
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+// In order; append new ones, never edit or reorder the ones already released
+const MIGRATIONS: &[Migration] = &[create_tables, add_replayed_at, add_idempotency_key];
+
+// Tables as they were before migrations were tracked. IF NOT EXISTS, because databases from
+// then already have them.
+fn create_tables(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS message_queue (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+        CREATE INDEX IF NOT EXISTS message_queue_status ON message_queue (status, id);
+        CREATE TABLE IF NOT EXISTS deliveries (
+            message_key TEXT PRIMARY KEY,
+            queue_id INTEGER NOT NULL REFERENCES message_queue (id),
+            recipient TEXT NOT NULL,
+            attempt INTEGER NOT NULL DEFAULT 0,
+            status TEXT NOT NULL DEFAULT 'sent',
+            updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+        CREATE TABLE IF NOT EXISTS dead_letters (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            payload TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            failed_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+        CREATE TABLE IF NOT EXISTS inbound_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            received_at TEXT NOT NULL DEFAULT (datetime('now')),
+            sender TEXT NOT NULL,
+            message_id TEXT,
+            text TEXT,
+            trigger_matched TEXT
+        );
+        CREATE INDEX IF NOT EXISTS inbound_log_received_at ON inbound_log (received_at);
+        CREATE TABLE IF NOT EXISTS daily_sends (
+            sender TEXT NOT NULL,
+            day TEXT NOT NULL,
+            count INTEGER NOT NULL,
+            PRIMARY KEY (sender, day)
+        );
+        CREATE TABLE IF NOT EXISTS suppressions (
+            number TEXT PRIMARY KEY,
+            suppressed_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );",
+    )
+}
+
+fn add_replayed_at(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "dead_letters", "replayed_at", "TEXT")
+}
+
+fn add_idempotency_key(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column(tx, "message_queue", "idempotency_key", "TEXT")
+}
+
+// Untracked databases may have been created with the column already there
+fn add_column(tx: &Transaction, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    let exists = tx
+        .prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")?
+        .exists([table, column])?;
+    if !exists {
+        tx.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, definition))?;
+    }
+    Ok(())
+}
+
+// Open (or create) the database at `database_url`, a sqlite:// prefix is accepted, with the
+// schema up to date. Every store opens its own connection, usually to the same file, so a write
+// waits up to 5s for another store's to finish instead of failing straight away.
+pub fn open(database_url: &str) -> Result<Connection, BotError> {
+    let path = database_url.strip_prefix("sqlite://").unwrap_or(database_url);
+    let mut conn = Connection::open(path)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    run_migrations(&mut conn)?;
+    Ok(conn)
+}
+
+// Bring the schema up to date. Safe to run on every open: the write lock is taken before the
+// version is read, so of two processes starting together one migrates and the other then finds
+// nothing left to do.
+pub fn run_migrations(conn: &mut Connection) -> Result<(), BotError> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            applied_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );",
+    )?;
+    let current: usize = tx.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))?;
+    if current > MIGRATIONS.len() {
+        warn!(
+            "Database schema is at version {}, newer than this build knows ({})",
+            current,
+            MIGRATIONS.len()
+        );
+        return Ok(());
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        migration(&tx)?;
+        tx.execute("INSERT INTO schema_version (version) VALUES (?1)", [index + 1])?;
+    }
+    tx.commit()?;
+    if current < MIGRATIONS.len() {
+        info!("Migrated the database schema from version {} to {}", current, MIGRATIONS.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // A database file of its own for one test, removed again when dropped
+    pub struct TempDatabase(PathBuf);
+
+    impl TempDatabase {
+        pub fn new() -> Self {
+            TempDatabase(std::env::temp_dir().join(format!("tool-test-{}.db", uuid::Uuid::new_v4())))
+        }
+
+        pub fn url(&self) -> String {
+            format!("sqlite://{}", self.0.display())
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn tables(conn: &Connection) -> Vec<String> {
+        let mut statement = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")
+            .unwrap();
+        statement.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect()
+    }
+
+    fn versions(conn: &Connection) -> Vec<usize> {
+        let mut statement = conn.prepare("SELECT version FROM schema_version ORDER BY version").unwrap();
+        statement.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect()
+    }
+
+    fn has_column(conn: &Connection, table: &str, column: &str) -> bool {
+        conn.prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2").unwrap().exists([table, column]).unwrap()
+    }
+
+    #[test]
+    fn an_empty_database_gets_every_table() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(
+            tables(&conn),
+            [
+                "daily_sends",
+                "dead_letters",
+                "deliveries",
+                "inbound_log",
+                "message_queue",
+                "schema_version",
+                "suppressions"
+            ]
+        );
+        assert!(has_column(&conn, "dead_letters", "replayed_at"));
+        assert!(has_column(&conn, "message_queue", "idempotency_key"));
+        assert_eq!(versions(&conn), (1..=MIGRATIONS.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn migrating_again_changes_nothing() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        conn.execute("INSERT INTO suppressions (number) VALUES ('+15551234567')", []).unwrap();
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(versions(&conn), (1..=MIGRATIONS.len()).collect::<Vec<_>>());
+        let kept: usize = conn.query_row("SELECT COUNT(*) FROM suppressions", [], |row| row.get(0)).unwrap();
+        assert_eq!(kept, 1);
+    }
+
+    #[test]
+    fn an_untracked_database_keeps_its_data() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // As created before migrations were tracked, with one of the later columns already added
+        conn.execute_batch(
+            "CREATE TABLE message_queue (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+                idempotency_key TEXT
+            );
+            INSERT INTO message_queue (payload) VALUES ('{}');",
+        )
+        .unwrap();
+        run_migrations(&mut conn).unwrap();
+        let queued: usize = conn.query_row("SELECT COUNT(*) FROM message_queue", [], |row| row.get(0)).unwrap();
+        assert_eq!(queued, 1);
+        assert!(has_column(&conn, "dead_letters", "replayed_at"));
+    }
+
+    #[test]
+    fn a_newer_schema_is_left_alone() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        conn.execute("INSERT INTO schema_version (version) VALUES (?1)", [MIGRATIONS.len() + 1]).unwrap();
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(versions(&conn).len(), MIGRATIONS.len() + 1);
+    }
+
+    #[test]
+    fn open_accepts_a_sqlite_url() {
+        let database = TempDatabase::new();
+        open(&database.url()).unwrap();
+        // A second connection, as the next store to start would open, finds it migrated
+        let conn = open(&database.url()).unwrap();
+        assert_eq!(versions(&conn), (1..=MIGRATIONS.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn concurrent_startups_migrate_once() {
+        let database = TempDatabase::new();
+        let url = database.url();
+        let opens: Vec<_> = (0..4)
+            .map(|_| {
+                let url = url.clone();
+                std::thread::spawn(move || open(&url).map(|_| ()))
+            })
+            .collect();
+        for handle in opens {
+            handle.join().unwrap().unwrap();
+        }
+        assert_eq!(versions(&open(&url).unwrap()), (1..=MIGRATIONS.len()).collect::<Vec<_>>());
+    }
+}
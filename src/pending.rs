@@ -0,0 +1,146 @@
+// What's waiting in the message queue. The channel can't be looked into, so each message is
+// also noted here until a worker takes it, for GET /queue and POST /queue/flush.
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::WhatsAppMessage;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PendingSummary {
+    pub seq: u64,
+    pub correlation_id: String,
+    // Masked, summaries are for spotting a backlog rather than reading it
+    pub from: String,
+    pub kind: &'static str,
+    pub text_chars: usize,
+    pub queued_at: String,
+}
+
+struct Inner {
+    next_seq: u64,
+    entries: BTreeMap<u64, PendingSummary>,
+    // Messages up to this sequence number were flushed and are dropped when a worker gets them
+    flushed_through: u64,
+}
+
+pub struct PendingQueue {
+    inner: Mutex<Inner>,
+}
+
+// Keep the country code and the last two digits of a number; "broadcast" and the like stay
+fn mask(from: &str) -> String {
+    if !from.chars().all(|c| c.is_ascii_digit() || c == '+') {
+        return from.to_string();
+    }
+    from.chars()
+        .enumerate()
+        .map(|(index, c)| if index < 3 || index + 2 >= from.len() { c } else { '*' })
+        .collect()
+}
+
+impl PendingQueue {
+    pub fn new() -> Self {
+        PendingQueue {
+            inner: Mutex::new(Inner { next_seq: 1, entries: BTreeMap::new(), flushed_through: 0 }),
+        }
+    }
+
+    // Note a message about to be queued and return its sequence number
+    pub fn track(&self, message: &WhatsAppMessage) -> u64 {
+        let mut inner = self.inner.lock().expect("pending queue lock poisoned");
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        let kind = if message.broadcast {
+            "broadcast"
+        } else if message.redelivery.is_some() {
+            "redelivery"
+        } else {
+            "message"
+        };
+        inner.entries.insert(
+            seq,
+            PendingSummary {
+                seq,
+                correlation_id: message.correlation_id.clone(),
+                from: mask(&message.from),
+                kind,
+                text_chars: message.text.as_deref().map_or(0, |text| text.chars().count()),
+                queued_at: chrono::Utc::now().to_rfc3339(),
+            },
+        );
+        seq
+    }
+
+    // It never made it into the queue
+    pub fn untrack(&self, seq: u64) {
+        self.inner.lock().expect("pending queue lock poisoned").entries.remove(&seq);
+    }
+
+    // A worker took the message off the queue. Returns false if it was flushed meanwhile;
+    // sequence number 0 is never handed out, so untracked messages always go through.
+    pub fn take(&self, seq: u64) -> bool {
+        let mut inner = self.inner.lock().expect("pending queue lock poisoned");
+        inner.entries.remove(&seq);
+        seq == 0 || seq > inner.flushed_through
+    }
+
+    // How many are waiting, the oldest `limit` of them, and the newest sequence number
+    pub fn snapshot(&self, limit: usize) -> (usize, Vec<PendingSummary>, u64) {
+        let inner = self.inner.lock().expect("pending queue lock poisoned");
+        let sample = inner.entries.values().take(limit).cloned().collect();
+        (inner.entries.len(), sample, inner.next_seq - 1)
+    }
+
+    // Drop everything queued up to and including `through`. Returns how many were waiting.
+    pub fn flush(&self, through: u64) -> usize {
+        let mut inner = self.inner.lock().expect("pending queue lock poisoned");
+        let through = through.min(inner.next_seq - 1);
+        let kept = inner.entries.split_off(&(through + 1));
+        let flushed = std::mem::replace(&mut inner.entries, kept).len();
+        inner.flushed_through = inner.flushed_through.max(through);
+        flushed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::text_message;
+
+    #[test]
+    fn snapshot_reports_depth_and_masks_numbers() {
+        let pending = PendingQueue::new();
+        pending.track(&text_message("m1", "addcontact Jane"));
+        let seq = pending.track(&text_message("m2", "addcontact Bob"));
+        pending.track(&text_message("m3", "addcontact Ann"));
+        pending.untrack(seq);
+
+        let (depth, sample, last_seq) = pending.snapshot(1);
+        assert_eq!((depth, last_seq), (2, 3));
+        assert_eq!(sample.len(), 1);
+        assert_eq!(sample[0].seq, 1);
+        assert_eq!(sample[0].from, "+15*******21");
+        assert_eq!(sample[0].text_chars, 15);
+        assert_eq!(mask("broadcast"), "broadcast");
+    }
+
+    #[test]
+    fn flush_drops_only_what_was_queued_through_the_token() {
+        let pending = PendingQueue::new();
+        let first = pending.track(&text_message("m1", "addcontact Jane"));
+        let second = pending.track(&text_message("m2", "addcontact Bob"));
+        let (_, _, token) = pending.snapshot(20);
+        let later = pending.track(&text_message("m3", "addcontact Ann"));
+
+        assert_eq!(pending.flush(token), 2);
+        assert_eq!(pending.snapshot(20).0, 1);
+        assert!(!pending.take(first));
+        assert!(!pending.take(second));
+        assert!(pending.take(later));
+        assert!(pending.take(0));
+        assert_eq!(pending.snapshot(20).0, 0);
+    }
+}
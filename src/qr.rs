@@ -0,0 +1,62 @@
+// QR codes for vCards, so a contact can be scanned straight into a phone from a desktop screen
+use qrcode::types::QrError;
+use qrcode::{Color, QrCode};
+
+// Pixels per QR module, and the blank border in modules that scanners need around the code
+const SCALE: usize = 8;
+const QUIET_ZONE: usize = 4;
+
+// Encode `data` as a black on white QR code PNG. Fails with DataTooLong when it doesn't fit
+// the largest QR version.
+pub fn render_png(data: &str) -> Result<Vec<u8>, QrError> {
+    let code = QrCode::new(data.as_bytes())?;
+    let modules = code.width();
+    let colors = code.to_colors();
+    let size = (modules + 2 * QUIET_ZONE) * SCALE;
+
+    let mut pixels = vec![0xFFu8; size * size];
+    for (index, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let x = (index % modules + QUIET_ZONE) * SCALE;
+        let y = (index / modules + QUIET_ZONE) * SCALE;
+        for row in y..y + SCALE {
+            pixels[row * size + x..row * size + x + SCALE].fill(0x00);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Writing to memory can only fail on a bad header, and ours is fixed
+    let mut writer = encoder.write_header().expect("valid PNG header");
+    writer.write_image_data(&pixels).expect("image data matches the header");
+    writer.finish().expect("PNG written to memory");
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    #[test]
+    fn renders_a_square_png() {
+        let png = render_png("BEGIN:VCARD\nVERSION:3.0\nN:Smith;Jane\nEND:VCARD").unwrap();
+        assert!(png.starts_with(PNG_SIGNATURE));
+        let decoder = png::Decoder::new(std::io::Cursor::new(png));
+        let info = decoder.read_info().unwrap().info().clone();
+        assert_eq!(info.width, info.height);
+        // Whole modules, with the quiet zone around them
+        assert_eq!(info.width as usize % SCALE, 0);
+        assert!(info.width as usize > 2 * QUIET_ZONE * SCALE);
+    }
+
+    #[test]
+    fn data_past_qr_capacity_fails() {
+        assert!(matches!(render_png(&"x".repeat(3000)), Err(QrError::DataTooLong)));
+    }
+}
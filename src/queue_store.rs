@@ -0,0 +1,174 @@
+// Durable SQLite backing for the message queue so queued messages survive a restart
+use std::sync::Mutex;
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::WhatsAppMessage;
+use crate::delivery::DeliveryStatus;
+use crate::error::BotError;
+use crate::migrations;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueStatus {
+    Sent,
+    Failed,
+}
+
+impl QueueStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            QueueStatus::Sent => "sent",
+            QueueStatus::Failed => "failed",
+        }
+    }
+}
+
+// A vCard handed to Infobip for one recipient, keyed by the messageId it was sent with
+#[derive(Debug)]
+pub struct Delivery {
+    pub queue_id: i64,
+    pub recipient: String,
+    // 0 for the first send, counting up with each redelivery after a failed report
+    pub attempt: u32,
+}
+
+pub struct QueueStore {
+    conn: Mutex<Connection>,
+}
+
+impl QueueStore {
+    // Open (or create) the database at `database_url`; a sqlite:// prefix is accepted
+    pub fn open(database_url: &str) -> Result<Self, BotError> {
+        let conn = migrations::open(database_url)?;
+        Ok(QueueStore { conn: Mutex::new(conn) })
+    }
+
+    // Record a message as pending and return its row id. The inbound messageId is kept as the
+    // row's idempotency key; per-recipient send keys are derived from it.
+    pub fn enqueue(&self, message: &WhatsAppMessage) -> Result<i64, BotError> {
+        let payload = serde_json::to_string(message)
+            .map_err(|e| BotError::Send(format!("could not serialize queued message: {}", e)))?;
+        let conn = self.conn.lock().expect("queue store lock poisoned");
+        conn.execute(
+            "INSERT INTO message_queue (payload, idempotency_key) VALUES (?1, ?2)",
+            params![payload, message.message_id],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    // Oldest pending message with an id greater than `after_id`
+    pub fn next_pending(&self, after_id: i64) -> Result<Option<(i64, WhatsAppMessage)>, BotError> {
+        let conn = self.conn.lock().expect("queue store lock poisoned");
+        let row: Option<(i64, String)> = conn
+            .query_row(
+                "SELECT id, payload FROM message_queue
+                 WHERE status = 'pending' AND id > ?1 ORDER BY id LIMIT 1",
+                params![after_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        match row {
+            Some((id, payload)) => Ok(Some((id, decode(id, &payload)?))),
+            None => Ok(None),
+        }
+    }
+
+    // A queued message by row id, whatever its status
+    pub fn message(&self, id: i64) -> Result<Option<WhatsAppMessage>, BotError> {
+        let conn = self.conn.lock().expect("queue store lock poisoned");
+        let payload: Option<String> = conn
+            .query_row("SELECT payload FROM message_queue WHERE id = ?1", params![id], |row| row.get(0))
+            .optional()?;
+        payload.map(|payload| decode(id, &payload)).transpose()
+    }
+
+    // Move a message out of pending once the worker is finished with it
+    pub fn mark_done(&self, id: i64, status: QueueStatus) -> Result<(), BotError> {
+        let conn = self.conn.lock().expect("queue store lock poisoned");
+        conn.execute(
+            "UPDATE message_queue SET status = ?1, updated_at = datetime('now') WHERE id = ?2",
+            params![status.as_str(), id],
+        )?;
+        Ok(())
+    }
+
+    // Remember which queued message and recipient a sent messageId belongs to, so delivery
+    // reports can be matched back to it
+    pub fn record_delivery(&self, message_key: &str, delivery: &Delivery) -> Result<(), BotError> {
+        let conn = self.conn.lock().expect("queue store lock poisoned");
+        conn.execute(
+            "INSERT OR REPLACE INTO deliveries (message_key, queue_id, recipient, attempt)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![message_key, delivery.queue_id, delivery.recipient, delivery.attempt],
+        )?;
+        Ok(())
+    }
+
+    // Store the reported status and return the delivery it belongs to, if we sent it
+    pub fn update_delivery(&self, message_key: &str, status: DeliveryStatus) -> Result<Option<Delivery>, BotError> {
+        let conn = self.conn.lock().expect("queue store lock poisoned");
+        let delivery = conn
+            .query_row(
+                "UPDATE deliveries SET status = ?1, updated_at = datetime('now') WHERE message_key = ?2
+                 RETURNING queue_id, recipient, attempt",
+                params![status.as_str(), message_key],
+                |row| {
+                    Ok(Delivery {
+                        queue_id: row.get(0)?,
+                        recipient: row.get(1)?,
+                        attempt: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(delivery)
+    }
+}
+
+fn decode(id: i64, payload: &str) -> Result<WhatsAppMessage, BotError> {
+    let mut message: WhatsAppMessage = serde_json::from_str(payload)
+        .map_err(|e| BotError::Send(format!("queued message {} is corrupt: {}", id, e)))?;
+    message.queue_id = Some(id);
+    // Rows queued before correlation IDs were carried have none
+    if message.correlation_id.is_empty() {
+        message.correlation_id = message.message_id.clone().unwrap_or_else(|| format!("queue-{}", id));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::migrations::tests::TempDatabase;
+    use crate::tests::text_message;
+
+    #[test]
+    fn pending_messages_survive_a_reopen() {
+        let database = TempDatabase::new();
+        let store = QueueStore::open(&database.url()).unwrap();
+        let first = store.enqueue(&text_message("m1", "addcontact Jane Smith +15551230000")).unwrap();
+        let second = store.enqueue(&text_message("m2", "addcontact John Doe +15551230001")).unwrap();
+        store.mark_done(first, QueueStatus::Sent).unwrap();
+        drop(store);
+
+        let store = QueueStore::open(&database.url()).unwrap();
+        let (id, message) = store.next_pending(0).unwrap().expect("the unsent message is still pending");
+        assert_eq!(id, second);
+        assert_eq!(message.queue_id, Some(second));
+        assert_eq!(message.message_id.as_deref(), Some("m2"));
+        assert_eq!(message.text.as_deref(), Some("addcontact John Doe +15551230001"));
+        assert!(store.next_pending(id).unwrap().is_none());
+    }
+
+    #[test]
+    fn deliveries_are_matched_back_to_their_message() {
+        let store = QueueStore::open(":memory:").unwrap();
+        let id = store.enqueue(&text_message("m1", "addcontact Jane Smith +15551230000")).unwrap();
+        store.record_delivery("m1-key", &Delivery { queue_id: id, recipient: "+15551234567".to_string(), attempt: 0 }).unwrap();
+        let delivery = store.update_delivery("m1-key", DeliveryStatus::Delivered).unwrap().unwrap();
+        assert_eq!(delivery.queue_id, id);
+        assert_eq!(delivery.recipient, "+15551234567");
+        assert!(store.update_delivery("unknown", DeliveryStatus::Delivered).unwrap().is_none());
+    }
+}
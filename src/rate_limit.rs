@@ -0,0 +1,207 @@
+// Token-bucket rate limiting for outgoing sends
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+// Holds up to `capacity` tokens and refills continuously at `refill_per_sec`.
+// Each send takes one token, so bursts up to the capacity go out immediately and
+// anything beyond that is throttled to the sustained rate.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(refill_per_sec: f64, capacity: u32) -> Self {
+        let capacity = f64::from(capacity.max(1));
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            refill_per_sec,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    // Change the rate and capacity, keeping the tokens already earned up to the new capacity
+    pub fn set_rate(&mut self, refill_per_sec: f64, capacity: u32) {
+        self.refill(Instant::now());
+        self.refill_per_sec = refill_per_sec;
+        self.capacity = f64::from(capacity.max(1));
+        self.tokens = self.tokens.min(self.capacity);
+    }
+
+    // How long since the bucket was last used
+    fn idle_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_refill)
+    }
+
+    // Take a token if one is available, otherwise report how long until the next one is
+    pub fn try_acquire(&mut self) -> Result<(), Duration> {
+        self.refill(Instant::now());
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - self.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_sec))
+        }
+    }
+}
+
+// Shared async wrapper so concurrent callers queue up on the same bucket
+pub struct RateLimiter {
+    bucket: Mutex<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate_per_second: f64, burst_size: u32) -> Self {
+        RateLimiter {
+            bucket: Mutex::new(TokenBucket::new(rate_per_second, burst_size)),
+        }
+    }
+
+    // Wait until a token is available and take it
+    pub async fn acquire(&self) {
+        loop {
+            let wait = match self.bucket.lock().await.try_acquire() {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    pub async fn set_rate(&self, rate_per_second: f64, burst_size: u32) {
+        self.bucket.lock().await.set_rate(rate_per_second, burst_size);
+    }
+}
+
+// One bucket per key (recipient number) so a single busy destination can't use up the
+// whole budget. Buckets idle for longer than `idle_ttl` are evicted to bound memory.
+pub struct KeyedRateLimiter {
+    buckets: Mutex<Buckets>,
+    idle_ttl: Duration,
+}
+
+// The rate lives under the same lock as the buckets so a change applies to all of them at once
+struct Buckets {
+    by_key: HashMap<String, TokenBucket>,
+    refill_per_sec: f64,
+    burst_size: u32,
+}
+
+impl KeyedRateLimiter {
+    pub fn new(refill_per_sec: f64, burst_size: u32, idle_ttl: Duration) -> Self {
+        KeyedRateLimiter {
+            buckets: Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                refill_per_sec,
+                burst_size,
+            }),
+            idle_ttl,
+        }
+    }
+
+    pub async fn set_rate(&self, refill_per_sec: f64, burst_size: u32) {
+        let mut buckets = self.buckets.lock().await;
+        buckets.refill_per_sec = refill_per_sec;
+        buckets.burst_size = burst_size;
+        for bucket in buckets.by_key.values_mut() {
+            bucket.set_rate(refill_per_sec, burst_size);
+        }
+    }
+
+    // Wait until `key` has a token available and take it
+    pub async fn acquire(&self, key: &str) {
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().await;
+                let Buckets { by_key, refill_per_sec, burst_size } = &mut *buckets;
+                let now = Instant::now();
+                by_key.retain(|k, bucket| k == key || bucket.idle_for(now) < self.idle_ttl);
+                let bucket = by_key
+                    .entry(key.to_string())
+                    .or_insert_with(|| TokenBucket::new(*refill_per_sec, *burst_size));
+                match bucket.try_acquire() {
+                    Ok(()) => return,
+                    Err(wait) => wait,
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sends_beyond_the_burst_wait_for_the_rate() {
+        let limiter = RateLimiter::new(20.0, 1);
+        let started = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire().await;
+        }
+        // The first goes out at once, the other four one refill (50ms) apart
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(190), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn burst_goes_out_immediately() {
+        let limiter = RateLimiter::new(1.0, 5);
+        let started = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire().await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn empty_bucket_reports_the_wait() {
+        let mut bucket = TokenBucket::new(2.0, 1);
+        assert!(bucket.try_acquire().is_ok());
+        let wait = bucket.try_acquire().unwrap_err();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500), "{:?}", wait);
+    }
+
+    #[tokio::test]
+    async fn one_throttled_recipient_does_not_hold_up_another() {
+        let limiter = std::sync::Arc::new(KeyedRateLimiter::new(2.0, 1, Duration::from_secs(60)));
+        limiter.acquire("+15551230000").await;
+        let started = Instant::now();
+        let throttled = tokio::spawn({
+            let limiter = limiter.clone();
+            async move {
+                limiter.acquire("+15551230000").await;
+                started.elapsed()
+            }
+        });
+        limiter.acquire("+15551239999").await;
+        let other = started.elapsed();
+        let throttled = throttled.await.unwrap();
+        assert!(other < Duration::from_millis(100), "{:?}", other);
+        assert!(throttled >= Duration::from_millis(400), "{:?}", throttled);
+    }
+
+    #[tokio::test]
+    async fn idle_buckets_are_evicted() {
+        let limiter = KeyedRateLimiter::new(1.0, 1, Duration::from_millis(20));
+        limiter.acquire("+15551230000").await;
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        limiter.acquire("+15551239999").await;
+        let buckets = limiter.buckets.lock().await;
+        assert_eq!(buckets.by_key.keys().collect::<Vec<_>>(), ["+15551239999"]);
+    }
+}
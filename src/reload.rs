@@ -0,0 +1,141 @@
+// Live configuration that a SIGHUP can replace while the server keeps running
+use std::sync::{Arc, RwLock};
+
+use crate::some_module::Config;
+
+pub struct ConfigHandle {
+    current: RwLock<Arc<Config>>,
+}
+
+// What a reload did
+pub struct Reload {
+    // Settings now in effect with a new value
+    pub changed: Vec<String>,
+    // Settings edited in the source but left as they were until a restart
+    pub needs_restart: Vec<&'static str>,
+}
+
+// Put back every listed setting `next` changed from `current`, noting its name
+macro_rules! keep_startup_settings {
+    ($next:expr, $current:expr, $needs_restart:expr, [$($field:ident),* $(,)?]) => {
+        $(
+            if $next.$field != $current.$field {
+                $next.$field = $current.$field.clone();
+                $needs_restart.push(stringify!($field));
+            }
+        )*
+    };
+}
+
+impl ConfigHandle {
+    pub fn new(config: Config) -> Self {
+        ConfigHandle {
+            current: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    // The active config. Take one snapshot per request or message so it sees one version.
+    pub fn current(&self) -> Arc<Config> {
+        self.current.read().expect("config lock poisoned").clone()
+    }
+
+    // Swap in `next`. Settings that were baked into the listener, client or stores at startup
+    // keep their running values.
+    pub fn replace(&self, mut next: Config) -> Reload {
+        let mut current = self.current.write().expect("config lock poisoned");
+        let mut needs_restart = Vec::new();
+        keep_startup_settings!(next, current, needs_restart, [
+            infobip_api_key,
+            infobip_api_key_file,
+            infobip_base_url,
+            bind_address,
+            port,
+            webhook_path,
+            tls_cert_path,
+            tls_key_path,
+            max_body_bytes,
+            persist_queue,
+            database_url,
+            log_inbound,
+            log_format,
+            log_level,
+            otlp_endpoint,
+            worker_count,
+            breaker_failure_threshold,
+            breaker_cooldown_secs,
+            send_timeout_secs,
+            max_in_flight,
+            startup_checks,
+            refresh_credentials_on_auth_error,
+            strict_startup_checks,
+            dedup_window_secs,
+            dedup_capacity,
+            confirmation_ttl_secs,
+            guided_flow_ttl_secs,
+            contact_dedup_window_secs,
+            contacts_csv,
+            max_name_chars,
+            max_note_chars,
+            max_field_chars,
+            queue_capacity,
+            broadcast_schedule,
+            per_recipient_idle_ttl_secs,
+            startup_retry_attempts,
+            startup_retry_delay_secs,
+            media_url,
+            cors_allowed_origins,
+            cors_allowed_methods,
+            cors_allowed_headers,
+            watchdog_interval_secs,
+        ]);
+        let changed = changed_settings(&current, &next);
+        *current = Arc::new(next);
+        Reload { changed, needs_restart }
+    }
+}
+
+// Names of the settings whose values differ. Only names are returned, values may be secrets.
+fn changed_settings(before: &Config, after: &Config) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(before)), Ok(serde_json::Value::Object(after))) =
+        (serde_json::to_value(before), serde_json::to_value(after))
+    else {
+        return Vec::new();
+    };
+    before
+        .iter()
+        .filter(|(name, value)| after.get(name.as_str()) != Some(value))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::test_config;
+
+    #[test]
+    fn a_reload_swaps_in_the_new_values() {
+        let handle = ConfigHandle::new(test_config(&[]));
+        let before = handle.current();
+        let reload = handle.replace(test_config(&[("TRIGGER_WORDS", "newcontact"), ("DRY_RUN", "true")]));
+        let mut changed = reload.changed.clone();
+        changed.sort();
+        assert_eq!(changed, ["dry_run", "trigger_words"]);
+        assert!(reload.needs_restart.is_empty());
+        assert_eq!(handle.current().trigger_words, ["newcontact"]);
+        // A snapshot taken before keeps its version
+        assert_eq!(before.trigger_words, ["addcontact"]);
+    }
+
+    #[test]
+    fn startup_settings_wait_for_a_restart() {
+        let handle = ConfigHandle::new(test_config(&[]));
+        let port = handle.current().port;
+        let reload = handle.replace(test_config(&[("PORT", "9999"), ("TRIGGER_WORDS", "newcontact")]));
+        assert_eq!(reload.needs_restart, ["port"]);
+        assert!(!reload.changed.contains(&"port".to_string()));
+        assert_eq!(handle.current().port, port);
+        assert_eq!(handle.current().trigger_words, ["newcontact"]);
+    }
+}
@@ -0,0 +1,103 @@
+// How many vCards each sender has triggered per day, for MAX_SENDS_PER_SENDER_PER_DAY. Kept in
+// SQLite so a restart doesn't hand everyone a fresh allowance.
+use std::sync::Mutex;
+
+use chrono::Utc;
+use rusqlite::{Connection, params};
+
+use crate::error::BotError;
+use crate::migrations;
+
+pub struct SendCounter {
+    conn: Mutex<Connection>,
+}
+
+// The day that is counting now, when days start at `reset_hour` UTC
+pub fn current_day(reset_hour: u32) -> String {
+    (Utc::now() - chrono::Duration::hours(reset_hour.into())).format("%Y-%m-%d").to_string()
+}
+
+impl SendCounter {
+    // Open (or create) the counters in the database at `database_url`; a sqlite:// prefix is accepted
+    pub fn open(database_url: &str) -> Result<Self, BotError> {
+        let conn = migrations::open(database_url)?;
+        conn.execute_batch("DELETE FROM daily_sends WHERE day < date('now', '-2 days');")?;
+        Ok(SendCounter { conn: Mutex::new(conn) })
+    }
+
+    // Count one send for `sender` on `day`, unless they already had `cap`. Returns whether it
+    // was counted.
+    pub fn try_take(&self, sender: &str, day: &str, cap: u32) -> Result<bool, BotError> {
+        let conn = self.conn.lock().expect("send counter lock poisoned");
+        let changed = conn.execute(
+            "INSERT INTO daily_sends (sender, day, count) VALUES (?1, ?2, 1)
+             ON CONFLICT (sender, day) DO UPDATE SET count = count + 1 WHERE count < ?3",
+            params![sender.trim_start_matches('+'), day, cap],
+        )?;
+        Ok(changed > 0)
+    }
+
+    // Undo a try_take for a send that didn't go out
+    pub fn give_back(&self, sender: &str, day: &str) -> Result<(), BotError> {
+        let conn = self.conn.lock().expect("send counter lock poisoned");
+        conn.execute(
+            "UPDATE daily_sends SET count = count - 1 WHERE sender = ?1 AND day = ?2 AND count > 0",
+            params![sender.trim_start_matches('+'), day],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::migrations::tests::TempDatabase;
+
+    #[test]
+    fn sends_are_allowed_up_to_the_cap() {
+        let counter = SendCounter::open(":memory:").unwrap();
+        assert!(counter.try_take("+15557654321", "2026-10-14", 2).unwrap());
+        assert!(counter.try_take("15557654321", "2026-10-14", 2).unwrap());
+        assert!(!counter.try_take("+15557654321", "2026-10-14", 2).unwrap());
+        // Every sender has their own count
+        assert!(counter.try_take("+15551230000", "2026-10-14", 2).unwrap());
+    }
+
+    #[test]
+    fn the_count_starts_again_the_next_day() {
+        let counter = SendCounter::open(":memory:").unwrap();
+        assert!(counter.try_take("+15557654321", "2026-10-14", 1).unwrap());
+        assert!(!counter.try_take("+15557654321", "2026-10-14", 1).unwrap());
+        assert!(counter.try_take("+15557654321", "2026-10-15", 1).unwrap());
+    }
+
+    #[test]
+    fn a_send_given_back_can_be_taken_again() {
+        let counter = SendCounter::open(":memory:").unwrap();
+        assert!(counter.try_take("+15557654321", "2026-10-14", 1).unwrap());
+        counter.give_back("+15557654321", "2026-10-14").unwrap();
+        assert!(counter.try_take("+15557654321", "2026-10-14", 1).unwrap());
+        // Never below zero
+        counter.give_back("+15557654321", "2026-10-14").unwrap();
+        counter.give_back("+15557654321", "2026-10-14").unwrap();
+        assert!(counter.try_take("+15557654321", "2026-10-14", 1).unwrap());
+        assert!(!counter.try_take("+15557654321", "2026-10-14", 1).unwrap());
+    }
+
+    #[test]
+    fn the_count_survives_a_restart() {
+        let database = TempDatabase::new();
+        let today = current_day(0);
+        assert!(SendCounter::open(&database.url()).unwrap().try_take("+15557654321", &today, 1).unwrap());
+        assert!(!SendCounter::open(&database.url()).unwrap().try_take("+15557654321", &today, 1).unwrap());
+    }
+
+    #[test]
+    fn the_day_starts_at_the_reset_hour() {
+        let today = Utc::now();
+        assert_eq!(current_day(0), today.format("%Y-%m-%d").to_string());
+        let shifted = today - chrono::Duration::hours(12);
+        assert_eq!(current_day(12), shifted.format("%Y-%m-%d").to_string());
+    }
+}
@@ -0,0 +1,192 @@
+// Abstraction over the outgoing WhatsApp API so the send path can run against a fake
+use std::future::Future;
+
+use infobip_sdk::model::whatsapp::{
+    SendContactRequestBody, SendDocumentRequestBody, SendImageRequestBody, SendLocationRequestBody, SendTemplateRequestBody,
+    SendTextRequestBody,
+};
+
+use crate::error::BotError;
+
+pub trait MessageSender: Send + Sync {
+    fn send_text(
+        &self,
+        request_body: SendTextRequestBody,
+    ) -> impl Future<Output = Result<(), BotError>> + Send;
+
+    fn send_contact(
+        &self,
+        request_body: SendContactRequestBody,
+    ) -> impl Future<Output = Result<(), BotError>> + Send;
+
+    fn send_template(
+        &self,
+        request_body: SendTemplateRequestBody,
+    ) -> impl Future<Output = Result<(), BotError>> + Send;
+
+    fn send_image(
+        &self,
+        request_body: SendImageRequestBody,
+    ) -> impl Future<Output = Result<(), BotError>> + Send;
+
+    fn send_document(
+        &self,
+        request_body: SendDocumentRequestBody,
+    ) -> impl Future<Output = Result<(), BotError>> + Send;
+
+    fn send_location(
+        &self,
+        request_body: SendLocationRequestBody,
+    ) -> impl Future<Output = Result<(), BotError>> + Send;
+
+    // Fails when `sender` isn't a number the account can send from
+    fn check_sender(&self, sender: &str) -> impl Future<Output = Result<(), BotError>> + Send;
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use serde::Serialize;
+
+    use super::*;
+
+    // One call made on the mock: which send it was and the request body as JSON
+    #[derive(Debug, Clone)]
+    pub struct Sent {
+        pub kind: &'static str,
+        pub body: serde_json::Value,
+    }
+
+    impl Sent {
+        pub fn to(&self) -> &str {
+            self.body["to"].as_str().unwrap_or_default()
+        }
+
+        pub fn text(&self) -> &str {
+            self.body["content"]["text"].as_str().unwrap_or_default()
+        }
+    }
+
+    pub fn text_body(to: &str, text: &str) -> SendTextRequestBody {
+        SendTextRequestBody {
+            from: "447860099299".to_string(),
+            to: to.to_string(),
+            content: infobip_sdk::model::whatsapp::TextContent { text: text.to_string(), preview_url: None },
+            ..Default::default()
+        }
+    }
+
+    // Records every call and answers with the queued results in order, Ok once they run out
+    #[derive(Default)]
+    pub struct MockSender {
+        sent: Mutex<Vec<Sent>>,
+        // None is a call that panics
+        results: Mutex<VecDeque<Option<Result<(), BotError>>>>,
+        delay: Mutex<Duration>,
+    }
+
+    impl MockSender {
+        pub fn new() -> Self {
+            MockSender::default()
+        }
+
+        // The result for the next call, after those already queued
+        pub fn then(&self, result: Result<(), BotError>) -> &Self {
+            self.results.lock().unwrap().push_back(Some(result));
+            self
+        }
+
+        // Panic in the next call, after those already queued
+        pub fn then_panic(&self) -> &Self {
+            self.results.lock().unwrap().push_back(None);
+            self
+        }
+
+        // Take `delay` to answer every call from now on, as a slow or hung Infobip would
+        pub fn delay_by(&self, delay: Duration) -> &Self {
+            *self.delay.lock().unwrap() = delay;
+            self
+        }
+
+        pub fn sent(&self) -> Vec<Sent> {
+            self.sent.lock().unwrap().clone()
+        }
+
+        // Wait for the workers to have made `count` calls; panics after 5s
+        pub async fn wait_for(&self, count: usize) -> Vec<Sent> {
+            for _ in 0..500 {
+                let sent = self.sent();
+                if sent.len() >= count {
+                    return sent;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("expected {} send(s), got {:?}", count, self.sent());
+        }
+
+        fn record(&self, kind: &'static str, body: &impl Serialize) -> Result<(), BotError> {
+            let body = serde_json::to_value(body).expect("request bodies serialize");
+            self.sent.lock().unwrap().push(Sent { kind, body });
+            // Taken out first, so the panic doesn't poison the lock
+            let next = self.results.lock().unwrap().pop_front();
+            match next {
+                Some(Some(result)) => result,
+                Some(None) => panic!("mock sender told to panic"),
+                None => Ok(()),
+            }
+        }
+
+        // The call is recorded as soon as it is made, the answer comes after the delay
+        async fn answer(&self, result: Result<(), BotError>) -> Result<(), BotError> {
+            let delay = *self.delay.lock().unwrap();
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            result
+        }
+    }
+
+    impl MessageSender for MockSender {
+        async fn send_text(&self, request_body: SendTextRequestBody) -> Result<(), BotError> {
+            self.answer(self.record("text", &request_body)).await
+        }
+
+        async fn send_contact(&self, request_body: SendContactRequestBody) -> Result<(), BotError> {
+            self.answer(self.record("contact", &request_body)).await
+        }
+
+        async fn send_template(&self, request_body: SendTemplateRequestBody) -> Result<(), BotError> {
+            self.answer(self.record("template", &request_body)).await
+        }
+
+        async fn send_image(&self, request_body: SendImageRequestBody) -> Result<(), BotError> {
+            self.answer(self.record("image", &request_body)).await
+        }
+
+        async fn send_document(&self, request_body: SendDocumentRequestBody) -> Result<(), BotError> {
+            self.answer(self.record("document", &request_body)).await
+        }
+
+        async fn send_location(&self, request_body: SendLocationRequestBody) -> Result<(), BotError> {
+            self.answer(self.record("location", &request_body)).await
+        }
+
+        async fn check_sender(&self, sender: &str) -> Result<(), BotError> {
+            self.answer(self.record("check_sender", &sender)).await
+        }
+    }
+
+    #[tokio::test]
+    async fn mock_records_calls_and_replays_results() {
+        let mock = MockSender::new();
+        mock.then(Err(BotError::Timeout(Duration::from_secs(1))));
+        assert!(mock.check_sender("+447860099299").await.is_err());
+        assert!(mock.check_sender("+447860099299").await.is_ok());
+        let sent = mock.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].kind, "check_sender");
+    }
+}
@@ -0,0 +1,141 @@
+// Which of our WhatsApp sender numbers a message goes out from
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::BotError;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SenderRouting {
+    // Recipients listed in SENDER_ROUTES get their sender, everyone else the first one
+    #[default]
+    Static,
+    // Each send takes the next sender in turn
+    RoundRobin,
+    // The longest SENDER_ROUTES prefix of the recipient's number picks the sender
+    ByRecipientPrefix,
+}
+
+impl std::str::FromStr for SenderRouting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "static" => Ok(SenderRouting::Static),
+            "round_robin" => Ok(SenderRouting::RoundRobin),
+            "by_recipient_prefix" => Ok(SenderRouting::ByRecipientPrefix),
+            other => Err(format!(
+                "unsupported sender routing '{}', expected static, round_robin or by_recipient_prefix",
+                other
+            )),
+        }
+    }
+}
+
+// Numbers are compared without the '+', config and Infobip disagree on it
+fn key(number: &str) -> &str {
+    number.trim_start_matches('+')
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SenderRouter {
+    senders: Vec<String>,
+    routing: SenderRouting,
+    // Recipient (static) or prefix, and its sender. Prefixes are kept longest first.
+    routes: Vec<(String, String)>,
+    // Shared by clones, so a config snapshot doesn't restart the rotation
+    next: Arc<AtomicUsize>,
+}
+
+impl SenderRouter {
+    // `routes` are "recipient=sender" or "prefix=sender" entries; each sender must be one of
+    // `senders`, the first of which is the default
+    pub fn new(senders: Vec<String>, routing: SenderRouting, routes: &[String]) -> Result<Self, BotError> {
+        if senders.is_empty() {
+            return Err(BotError::Config("at least one sender number is required".to_string()));
+        }
+        let mut parsed = Vec::new();
+        for route in routes {
+            let Some((target, sender)) = route.split_once('=') else {
+                return Err(BotError::Config(format!("SENDER_ROUTES entry '{}' must look like number=sender", route)));
+            };
+            let (target, sender) = (key(target.trim()), sender.trim());
+            if target.is_empty() || !senders.iter().any(|known| known == sender) {
+                return Err(BotError::Config(format!(
+                    "SENDER_ROUTES entry '{}' must name a number and one of the configured senders",
+                    route
+                )));
+            }
+            parsed.push((target.to_string(), sender.to_string()));
+        }
+        parsed.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Ok(SenderRouter { senders, routing, routes: parsed, next: Arc::new(AtomicUsize::new(0)) })
+    }
+
+    pub fn senders(&self) -> &[String] {
+        &self.senders
+    }
+
+    pub fn pick(&self, recipient: &str) -> &str {
+        let recipient = key(recipient);
+        let route = match self.routing {
+            SenderRouting::RoundRobin => {
+                let index = self.next.fetch_add(1, Ordering::Relaxed) % self.senders.len();
+                return &self.senders[index];
+            }
+            SenderRouting::Static => self.routes.iter().find(|(target, _)| target == recipient),
+            SenderRouting::ByRecipientPrefix => self.routes.iter().find(|(prefix, _)| recipient.starts_with(prefix.as_str())),
+        };
+        route.map_or(&self.senders[0], |(_, sender)| sender)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(routing: SenderRouting, routes: &[&str]) -> SenderRouter {
+        let senders = vec!["447860099299".to_string(), "447860099300".to_string(), "15557650000".to_string()];
+        let routes: Vec<String> = routes.iter().map(|route| route.to_string()).collect();
+        SenderRouter::new(senders, routing, &routes).unwrap()
+    }
+
+    #[test]
+    fn round_robin_takes_each_sender_in_turn() {
+        let router = router(SenderRouting::RoundRobin, &[]);
+        let picked: Vec<&str> = (0..6).map(|_| router.pick("+15551234567")).collect();
+        assert_eq!(picked, ["447860099299", "447860099300", "15557650000", "447860099299", "447860099300", "15557650000"]);
+        // A clone carries on where the original stopped
+        assert_eq!(router.clone().pick("+15551234567"), "447860099299");
+        assert_eq!(router.pick("+15551234567"), "447860099300");
+    }
+
+    #[test]
+    fn the_longest_prefix_picks_the_sender() {
+        let router = router(SenderRouting::ByRecipientPrefix, &["+44=447860099300", "1=15557650000", "4479=447860099299"]);
+        assert_eq!(router.pick("+447700900123"), "447860099300");
+        assert_eq!(router.pick("447912345678"), "447860099299");
+        assert_eq!(router.pick("+15551234567"), "15557650000");
+        // No prefix matches: the first sender
+        assert_eq!(router.pick("+33612345678"), "447860099299");
+    }
+
+    #[test]
+    fn static_routes_match_whole_numbers() {
+        let router = router(SenderRouting::Static, &["+15551234567=15557650000"]);
+        assert_eq!(router.pick("15551234567"), "15557650000");
+        assert_eq!(router.pick("+155512345678"), "447860099299");
+    }
+
+    #[test]
+    fn routes_must_name_a_configured_sender() {
+        let senders = vec!["447860099299".to_string()];
+        for route in ["+44=447860099300", "447860099299", "=447860099299"] {
+            let result = SenderRouter::new(senders.clone(), SenderRouting::ByRecipientPrefix, &[route.to_string()]);
+            assert!(matches!(result, Err(BotError::Config(_))), "{}", route);
+        }
+        assert!(SenderRouter::new(Vec::new(), SenderRouting::Static, &[]).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+// Tracks when each user last messaged us, to know whether WhatsApp's 24h window is open
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// WhatsApp allows free-form messages for this long after the user's last inbound message
+pub const SESSION_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Expired entries are swept at most this often
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct SessionTracker {
+    window: Duration,
+    state: Mutex<Sessions>,
+}
+
+struct Sessions {
+    last_inbound: HashMap<String, Instant>,
+    last_pruned: Instant,
+}
+
+// Infobip reports numbers without the '+', our config has it; key on the digits
+fn key(number: &str) -> &str {
+    number.trim_start_matches('+')
+}
+
+impl SessionTracker {
+    pub fn new(window: Duration) -> Self {
+        SessionTracker {
+            window,
+            state: Mutex::new(Sessions {
+                last_inbound: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        }
+    }
+
+    // Note an inbound message from `number`, opening (or extending) its session
+    pub fn record(&self, number: &str) {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("session lock poisoned");
+        state.last_inbound.insert(key(number).to_string(), now);
+        if now.duration_since(state.last_pruned) >= PRUNE_INTERVAL {
+            let window = self.window;
+            state.last_inbound.retain(|_, seen| now.duration_since(*seen) < window);
+            state.last_pruned = now;
+        }
+    }
+
+    // Whether `number` messaged us within the window, so free-form messages are allowed
+    pub fn in_window(&self, number: &str) -> bool {
+        let state = self.state.lock().expect("session lock poisoned");
+        state
+            .last_inbound
+            .get(key(number))
+            .is_some_and(|seen| seen.elapsed() < self.window)
+    }
+}
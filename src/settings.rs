@@ -0,0 +1,140 @@
+// Layered lookup of configuration values by their environment variable name
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::error::BotError;
+
+// Command-line overrides win over the environment, which wins over the config file
+#[derive(Debug, Default)]
+pub struct Settings {
+    overrides: HashMap<&'static str, String>,
+    file: HashMap<String, String>,
+}
+
+impl Settings {
+    pub fn new(overrides: HashMap<&'static str, String>) -> Self {
+        Settings { overrides, file: HashMap::new() }
+    }
+
+    // Add a TOML config file as the lowest-precedence source. Keys are the environment variable
+    // names in lower case, e.g. `port = 8080` or `trigger_words = ["addcontact", "vcard"]`.
+    pub fn with_file(mut self, path: &Path) -> Result<Self, BotError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| BotError::Config(format!("could not read config file {}: {}", path.display(), e)))?;
+        self.file = parse_toml(&contents)
+            .map_err(|e| BotError::Config(format!("config file {}: {}", path.display(), e)))?;
+        Ok(self)
+    }
+
+    // Raw value of a setting from the highest-precedence source that has it
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.overrides
+            .get(name)
+            .cloned()
+            .or_else(|| env::var(name).ok())
+            .or_else(|| self.file.get(&name.to_lowercase()).cloned())
+    }
+
+    // A setting that has no sensible default
+    pub fn required(&self, name: &str) -> Result<String, BotError> {
+        self.get(name)
+            .ok_or_else(|| BotError::Config(format!("{} must be set", name)))
+    }
+
+    // Parse a setting, falling back to the default when unset
+    pub fn parse<T: std::str::FromStr>(&self, name: &str, default: T) -> Result<T, BotError>
+    where
+        T::Err: std::fmt::Display,
+    {
+        match self.get(name) {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|e| BotError::Config(format!("{} is invalid: {}", name, e))),
+            None => Ok(default),
+        }
+    }
+
+    // Like parse, for settings with no default; unset or empty gives None
+    pub fn parse_optional<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, BotError>
+    where
+        T::Err: std::fmt::Display,
+    {
+        match self.get(name).filter(|value| !value.trim().is_empty()) {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|e| BotError::Config(format!("{} is invalid: {}", name, e))),
+            None => Ok(None),
+        }
+    }
+
+    // Boolean setting, falling back to the default when unset
+    pub fn flag(&self, name: &str, default: bool) -> bool {
+        match self.get(name) {
+            Some(value) => matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+            None => default,
+        }
+    }
+}
+
+// Flatten a TOML document into setting values; arrays become comma-separated lists so they
+// read the same as the equivalent environment variable
+fn parse_toml(contents: &str) -> Result<HashMap<String, String>, String> {
+    let table: toml::Table = contents.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                toml::Value::Array(items) => items
+                    .into_iter()
+                    .map(|item| scalar_to_string(&key, item))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(","),
+                other => scalar_to_string(&key, other)?,
+            };
+            Ok((key.to_lowercase(), value))
+        })
+        .collect()
+}
+
+fn scalar_to_string(key: &str, value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(format!("'{}' must be a string, number, boolean or a list of those", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_keys_are_lowercased_and_lists_joined() {
+        let values = parse_toml("PORT = 8080\ntrigger_words = [\"addcontact\", \"vcard\"]\ndry_run = true\nrate = 1.5").unwrap();
+        assert_eq!(values["port"], "8080");
+        assert_eq!(values["trigger_words"], "addcontact,vcard");
+        assert_eq!(values["dry_run"], "true");
+        assert_eq!(values["rate"], "1.5");
+    }
+
+    #[test]
+    fn nested_tables_are_refused() {
+        assert!(parse_toml("[server]\nport = 8080").is_err());
+    }
+
+    #[test]
+    fn overrides_win_over_the_file() {
+        let mut settings = Settings::new(HashMap::from([("PORT", "9000".to_string())]));
+        settings.file = parse_toml("port = 8000\nworker_count = 4").unwrap();
+        assert_eq!(settings.parse("PORT", 0u16).unwrap(), 9000);
+        assert_eq!(settings.parse("WORKER_COUNT", 1usize).unwrap(), 4);
+        assert_eq!(settings.parse("UNSET_IN_TESTS", 7u16).unwrap(), 7);
+    }
+}
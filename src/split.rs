@@ -0,0 +1,188 @@
+// Splitting text vCard messages to fit WhatsApp's per-message length limit
+use crate::error::BotError;
+
+// One unit of the message that is placed whole into a part where it fits
+enum Piece<'a> {
+    Line(&'a str),
+    // BEGIN:VCARD through END:VCARD; a card split across messages won't import
+    Card(&'a str),
+}
+
+// Break `text` into parts of at most `max` characters, preferring line breaks and never cutting
+// through a vCard block. Fails when a vCard block on its own is longer than `max`.
+pub fn split_message(text: &str, max: usize) -> Result<Vec<String>, BotError> {
+    let length = text.chars().count();
+    if length <= max {
+        return Ok(vec![text.to_string()]);
+    }
+
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    for piece in pieces(text) {
+        let chunks = match piece {
+            Piece::Card(card) if card.chars().count() > max => {
+                return Err(BotError::MessageTooLong { length, max });
+            }
+            Piece::Card(card) => vec![card.to_string()],
+            Piece::Line(line) => wrap_line(line, max),
+        };
+        for chunk in chunks {
+            let joined = current.chars().count() + 1 + chunk.chars().count();
+            if current.is_empty() {
+                current = chunk;
+            } else if joined <= max {
+                current.push('\n');
+                current.push_str(&chunk);
+            } else {
+                parts.push(std::mem::replace(&mut current, chunk));
+            }
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    Ok(parts)
+}
+
+fn pieces(text: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        if rest.starts_with("BEGIN:VCARD") {
+            // An unterminated card runs to the end of the text
+            let end = rest.find("END:VCARD").map_or(rest.len(), |i| i + "END:VCARD".len());
+            pieces.push(Piece::Card(&rest[..end]));
+            rest = rest[end..].strip_prefix('\n').unwrap_or(&rest[end..]);
+            continue;
+        }
+        let (line, next) = rest.split_once('\n').unwrap_or((rest, ""));
+        pieces.push(Piece::Line(line));
+        rest = next;
+    }
+    pieces
+}
+
+// Break a prose line longer than `max` at spaces, or mid-word for a single over-long word
+fn wrap_line(line: &str, max: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for word in line.split(' ') {
+        let mut word = word.to_string();
+        while word.chars().count() > max {
+            if !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+            }
+            let cut = cluster_boundary(&word, word.char_indices().nth(max).map_or(word.len(), |(i, _)| i));
+            chunks.push(word[..cut].to_string());
+            word = word[cut..].to_string();
+        }
+        if current.is_empty() {
+            current = word;
+        } else if current.chars().count() + 1 + word.chars().count() <= max {
+            current.push(' ');
+            current.push_str(&word);
+        } else {
+            chunks.push(std::mem::replace(&mut current, word));
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// Characters that belong to the one before them: combining accents, variation selectors, emoji
+// skin tones and tags, and either side of a zero-width joiner (as in a family emoji)
+fn extends_previous(previous: char, c: char) -> bool {
+    previous == '\u{200D}'
+        || matches!(c,
+            '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{200D}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FE20}'..='\u{FE2F}'
+            | '\u{1F3FB}'..='\u{1F3FF}'
+            | '\u{E0020}'..='\u{E007F}'
+            | '\u{E0100}'..='\u{E01EF}')
+}
+
+// Move a cut at byte `cut` back so it doesn't separate an accent or emoji modifier from its
+// base character. A word that is one long cluster is cut where asked.
+fn cluster_boundary(word: &str, cut: usize) -> usize {
+    let mut boundary = cut;
+    while let (Some(previous), Some(c)) = (word[..boundary].chars().next_back(), word[boundary..].chars().next()) {
+        if !extends_previous(previous, c) {
+            return boundary;
+        }
+        boundary -= previous.len_utf8();
+    }
+    if boundary == 0 { cut } else { boundary }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CARD: &str = "BEGIN:VCARD\nVERSION:3.0\nN:Smith;Jane\nTEL;TYPE=CELL:+15551230000\nEND:VCARD";
+
+    fn lengths(parts: &[String]) -> Vec<usize> {
+        parts.iter().map(|part| part.chars().count()).collect()
+    }
+
+    #[test]
+    fn an_under_limit_message_is_one_part() {
+        let text = format!("Here is the contact vCard:\n{}", CARD);
+        assert_eq!(split_message(&text, 4096).unwrap(), vec![text.clone()]);
+        // Exactly at the limit still fits
+        assert_eq!(split_message(&text, text.chars().count()).unwrap(), [text]);
+    }
+
+    #[test]
+    fn an_over_limit_message_splits_around_the_card() {
+        let prefix = "Here is the contact vCard you asked for, sent by the contact adder bot.";
+        let text = format!("{}\n{}", prefix, CARD);
+        let max = CARD.chars().count() + 5;
+        let parts = split_message(&text, max).unwrap();
+        assert!(lengths(&parts).iter().all(|&length| length <= max), "{:?}", parts);
+        // The card arrives whole, in a part of its own
+        assert_eq!(parts.last().unwrap(), CARD);
+        assert_eq!(parts[..parts.len() - 1].join(" "), prefix);
+    }
+
+    #[test]
+    fn a_long_line_is_wrapped_at_spaces() {
+        let parts = split_message("one two three four five six", 10).unwrap();
+        assert_eq!(parts, ["one two", "three four", "five six"]);
+        // Unless a single word is longer than a part
+        assert_eq!(split_message("abcdefghijkl", 5).unwrap(), ["abcde", "fghij", "kl"]);
+    }
+
+    #[test]
+    fn a_card_longer_than_the_limit_fails() {
+        let text = format!("Contact:\n{}", CARD);
+        let length = text.chars().count();
+        assert!(matches!(
+            split_message(&text, 30),
+            Err(BotError::MessageTooLong { length: reported, max: 30 }) if reported == length
+        ));
+    }
+
+    #[test]
+    fn limits_count_characters_not_bytes() {
+        // 4 characters, 12 bytes
+        assert_eq!(split_message("李小龍山", 4).unwrap(), ["李小龍山"]);
+        assert_eq!(split_message("李小龍山 田中", 4).unwrap(), ["李小龍山", "田中"]);
+    }
+
+    #[test]
+    fn a_long_word_is_not_cut_inside_an_emoji() {
+        // A thumbs up with its skin tone is two characters that must stay together
+        let parts = split_message("abc👍🏽def", 4).unwrap();
+        assert_eq!(parts, ["abc", "👍🏽de", "f"]);
+        let family = "👨\u{200D}👩\u{200D}👧";
+        let parts = split_message(&format!("ab{}", family), 5).unwrap();
+        assert_eq!(parts, ["ab".to_string(), family.to_string()]);
+    }
+}
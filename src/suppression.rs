@@ -0,0 +1,84 @@
+// Numbers that opted out with STOP. Kept in SQLite so an opt-out outlives restarts, and mirrored
+// in memory because every send checks it.
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use rusqlite::{Connection, params};
+
+use crate::error::BotError;
+use crate::migrations;
+
+pub struct SuppressionList {
+    conn: Mutex<Connection>,
+    numbers: Mutex<HashSet<String>>,
+}
+
+// Infobip reports numbers without the '+', config and contacts carry it
+fn key(number: &str) -> &str {
+    number.trim_start_matches('+')
+}
+
+impl SuppressionList {
+    // Open (or create) the list in the database at `database_url`; a sqlite:// prefix is accepted
+    pub fn open(database_url: &str) -> Result<Self, BotError> {
+        let conn = migrations::open(database_url)?;
+        let numbers = conn
+            .prepare("SELECT number FROM suppressions")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<HashSet<String>, _>>()?;
+        Ok(SuppressionList {
+            conn: Mutex::new(conn),
+            numbers: Mutex::new(numbers),
+        })
+    }
+
+    // Stop sending to `number`. Returns false if it was already suppressed.
+    pub fn suppress(&self, number: &str) -> Result<bool, BotError> {
+        let conn = self.conn.lock().expect("suppression lock poisoned");
+        conn.execute(
+            "INSERT OR IGNORE INTO suppressions (number) VALUES (?1)",
+            params![key(number)],
+        )?;
+        Ok(self.numbers.lock().expect("suppression lock poisoned").insert(key(number).to_string()))
+    }
+
+    // Allow sends to `number` again. Returns false if it wasn't suppressed.
+    pub fn resubscribe(&self, number: &str) -> Result<bool, BotError> {
+        let conn = self.conn.lock().expect("suppression lock poisoned");
+        conn.execute("DELETE FROM suppressions WHERE number = ?1", params![key(number)])?;
+        Ok(self.numbers.lock().expect("suppression lock poisoned").remove(key(number)))
+    }
+
+    pub fn is_suppressed(&self, number: &str) -> bool {
+        self.numbers.lock().expect("suppression lock poisoned").contains(key(number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::migrations::tests::TempDatabase;
+
+    #[test]
+    fn stop_and_start() {
+        let list = SuppressionList::open(":memory:").unwrap();
+        assert!(!list.is_suppressed("+15551230000"));
+        assert!(list.suppress("15551230000").unwrap());
+        assert!(!list.suppress("+15551230000").unwrap());
+        // With or without the '+'
+        assert!(list.is_suppressed("+15551230000"));
+        assert!(list.resubscribe("+15551230000").unwrap());
+        assert!(!list.is_suppressed("15551230000"));
+        assert!(!list.resubscribe("+15551230000").unwrap());
+    }
+
+    #[test]
+    fn an_opt_out_survives_a_restart() {
+        let database = TempDatabase::new();
+        SuppressionList::open(&database.url()).unwrap().suppress("+15551230000").unwrap();
+        let reopened = SuppressionList::open(&database.url()).unwrap();
+        assert!(reopened.is_suppressed("+15551230000"));
+        assert!(!reopened.is_suppressed("+15551239999"));
+    }
+}
@@ -0,0 +1,101 @@
+// OpenTelemetry span export over OTLP/HTTP, for tracing requests across the gateway and this
+// service. Unused unless OTLP_ENDPOINT is set.
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{Context, global};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use warp::http::HeaderMap;
+
+use crate::error::BotError;
+
+// A tracer exporting to `endpoint` (e.g. http://collector:4318/v1/traces) in batches. The
+// provider has to be shut down on exit to flush the last batch.
+pub fn otlp_tracer(endpoint: &str) -> Result<(Tracer, SdkTracerProvider), BotError> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| BotError::Config(format!("could not set up the OTLP exporter: {}", e)))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(env!("CARGO_PKG_NAME")).build())
+        .build();
+    // W3C traceparent/tracestate, which is what the gateway sends
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok((provider.tracer(env!("CARGO_PKG_NAME")), provider))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+// The caller's trace context from the request headers; empty without a traceparent or when no
+// exporter is set up
+pub fn remote_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use opentelemetry::trace::TraceContextExt;
+    use opentelemetry_sdk::error::OTelSdkResult;
+    use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    // Keeps the spans the provider exports, in place of a collector
+    #[derive(Debug, Clone, Default)]
+    pub struct SpanCapture(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for SpanCapture {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    impl SpanCapture {
+        // Export this thread's spans here until the guard is dropped, one by one as they end
+        pub fn start(&self) -> tracing::subscriber::DefaultGuard {
+            global::set_text_map_propagator(TraceContextPropagator::new());
+            let provider = SdkTracerProvider::builder().with_simple_exporter(self.clone()).build();
+            let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("test"));
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(layer))
+        }
+
+        pub fn spans(&self) -> Vec<SpanData> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    // The value of a span attribute, as text
+    pub fn attribute(span: &SpanData, key: &str) -> Option<String> {
+        span.attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| kv.value.to_string())
+    }
+
+    #[test]
+    fn the_remote_context_comes_from_traceparent() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap());
+        let context = remote_context(&headers);
+        let span = context.span();
+        assert_eq!(span.span_context().trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert!(span.span_context().is_remote());
+        assert!(!remote_context(&HeaderMap::new()).span().span_context().is_valid());
+    }
+}
@@ -0,0 +1,105 @@
+// Minimal {placeholder} templating for outgoing message text
+use crate::error::BotError;
+
+// Placeholders a message template may reference
+pub const MESSAGE_PLACEHOLDERS: &[&str] = &["first_name", "last_name", "phone_number", "vcard"];
+
+// Placeholders the reply to a message without a trigger word may reference
+pub const NO_MATCH_PLACEHOLDERS: &[&str] = &["trigger"];
+
+// Placeholders for WhatsApp template parameters, which must be single-line
+pub const TEMPLATE_PLACEHOLDERS: &[&str] = &["first_name", "last_name", "phone_number"];
+
+// Names of every {placeholder} in the template, in order of appearance
+fn placeholders(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        match after.find('}') {
+            Some(end) => {
+                names.push(&after[..end]);
+                rest = &after[end + 1..];
+            }
+            None => break,
+        }
+    }
+    names
+}
+
+// Fail at startup if the template (named for the error message) references a placeholder we
+// can't fill
+pub fn validate_template(setting: &str, template: &str, allowed: &[&str]) -> Result<(), BotError> {
+    for name in placeholders(template) {
+        if !allowed.contains(&name) {
+            return Err(BotError::Config(format!(
+                "{} references unknown placeholder {{{}}}, expected one of: {}",
+                setting,
+                name,
+                allowed.join(", ")
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Replace each {name} with its value in a single pass, so placeholder-like text inside a
+// value is left alone. Unknown names are kept verbatim.
+pub fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = &after[..end];
+        match values.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => rendered.push_str(value),
+            None => rendered.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_every_placeholder() {
+        let template = "{first_name} {last_name} ({phone_number}):\n{vcard}";
+        validate_template("MESSAGE_TEMPLATE", template, MESSAGE_PLACEHOLDERS).unwrap();
+        let rendered = render_template(
+            template,
+            &[
+                ("first_name", "Jane"),
+                ("last_name", "Smith"),
+                ("phone_number", "+15551230000"),
+                ("vcard", "BEGIN:VCARD"),
+            ],
+        );
+        assert_eq!(rendered, "Jane Smith (+15551230000):\nBEGIN:VCARD");
+    }
+
+    #[test]
+    fn values_are_not_rendered_again() {
+        assert_eq!(render_template("{first_name}!", &[("first_name", "{last_name}"), ("last_name", "x")]), "{last_name}!");
+    }
+
+    #[test]
+    fn unclosed_and_unknown_braces_are_kept() {
+        assert_eq!(render_template("{other} and {first_name", &[("first_name", "Jane")]), "{other} and {first_name");
+    }
+
+    #[test]
+    fn unknown_placeholder_is_a_config_error() {
+        let e = validate_template("MESSAGE_TEMPLATE", "Hi {nickname}", MESSAGE_PLACEHOLDERS).unwrap_err();
+        assert!(matches!(&e, BotError::Config(message) if message.contains("{nickname}")), "{}", e);
+        assert!(validate_template("TEMPLATE_PLACEHOLDERS", "{vcard}", TEMPLATE_PLACEHOLDERS).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+// Bounds each Infobip call, so a hung request can't hold a worker forever
+use std::time::Duration;
+
+use infobip_sdk::model::whatsapp::{
+    SendContactRequestBody, SendDocumentRequestBody, SendImageRequestBody, SendLocationRequestBody, SendTemplateRequestBody,
+    SendTextRequestBody,
+};
+
+use crate::error::BotError;
+use crate::sender::MessageSender;
+
+// Fails a send with BotError::Timeout once it has run for `timeout`. The timeout is transient,
+// so the retry loop tries again and the circuit breaker wrapped around this counts it.
+pub struct TimeoutSender<S> {
+    inner: S,
+    timeout: Duration,
+}
+
+impl<S: MessageSender> TimeoutSender<S> {
+    pub fn new(inner: S, timeout: Duration) -> Self {
+        TimeoutSender { inner, timeout }
+    }
+
+    async fn bounded(
+        &self,
+        send: impl Future<Output = Result<(), BotError>>,
+    ) -> Result<(), BotError> {
+        // Rate limiter tokens are taken before the send starts, so dropping it here holds none
+        tokio::time::timeout(self.timeout, send)
+            .await
+            .unwrap_or(Err(BotError::Timeout(self.timeout)))
+    }
+}
+
+impl<S: MessageSender> MessageSender for TimeoutSender<S> {
+    async fn send_text(&self, request_body: SendTextRequestBody) -> Result<(), BotError> {
+        self.bounded(self.inner.send_text(request_body)).await
+    }
+
+    async fn send_contact(&self, request_body: SendContactRequestBody) -> Result<(), BotError> {
+        self.bounded(self.inner.send_contact(request_body)).await
+    }
+
+    async fn send_template(&self, request_body: SendTemplateRequestBody) -> Result<(), BotError> {
+        self.bounded(self.inner.send_template(request_body)).await
+    }
+
+    async fn send_image(&self, request_body: SendImageRequestBody) -> Result<(), BotError> {
+        self.bounded(self.inner.send_image(request_body)).await
+    }
+
+    async fn send_document(&self, request_body: SendDocumentRequestBody) -> Result<(), BotError> {
+        self.bounded(self.inner.send_document(request_body)).await
+    }
+
+    async fn send_location(&self, request_body: SendLocationRequestBody) -> Result<(), BotError> {
+        self.bounded(self.inner.send_location(request_body)).await
+    }
+
+    async fn check_sender(&self, sender: &str) -> Result<(), BotError> {
+        self.bounded(self.inner.check_sender(sender)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    use crate::sender::tests::{MockSender, text_body};
+
+    #[tokio::test]
+    async fn a_send_past_the_timeout_fails_with_timeout() {
+        let mock = MockSender::new();
+        mock.delay_by(Duration::from_secs(5));
+        let sender = TimeoutSender::new(mock, Duration::from_millis(50));
+        let started = Instant::now();
+        let error = sender.send_text(text_body("+15551234567", "hello")).await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(matches!(error, BotError::Timeout(timeout) if timeout == Duration::from_millis(50)));
+        assert!(error.is_transient());
+        assert_eq!(sender.inner.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_send_within_the_timeout_passes_its_result_through() {
+        let mock = MockSender::new();
+        mock.delay_by(Duration::from_millis(10)).then(Err(BotError::Send("rejected".to_string())));
+        let sender = TimeoutSender::new(mock, Duration::from_secs(1));
+        assert!(matches!(sender.send_text(text_body("+15551234567", "hello")).await, Err(BotError::Send(_))));
+        assert!(sender.send_contact(Default::default()).await.is_ok());
+    }
+}
@@ -0,0 +1,155 @@
+// How trigger words are matched against message text
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::error::BotError;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerMatchMode {
+    // Anywhere in the text, so "add" fires on "paddle"
+    Contains,
+    // Only as a whole word
+    WordBoundary,
+    // Each trigger is a regular expression
+    Regex,
+}
+
+impl std::str::FromStr for TriggerMatchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "contains" => Ok(TriggerMatchMode::Contains),
+            "word_boundary" => Ok(TriggerMatchMode::WordBoundary),
+            "regex" => Ok(TriggerMatchMode::Regex),
+            other => Err(format!(
+                "unsupported trigger match mode '{}', expected contains, word_boundary or regex",
+                other
+            )),
+        }
+    }
+}
+
+// One case-insensitive pattern per trigger, compiled once at load so a bad pattern fails
+// startup. In contains mode the pattern is just the escaped word.
+pub fn compile_patterns(words: &[String], mode: TriggerMatchMode) -> Result<Vec<Regex>, BotError> {
+    let pattern = |word: &str| match mode {
+        TriggerMatchMode::Contains => regex::escape(word),
+        TriggerMatchMode::Regex => word.to_string(),
+        // \b is Unicode-aware; it's only added next to word characters, since a boundary
+        // before a trigger like "/add" would need a letter in front of the slash
+        TriggerMatchMode::WordBoundary => {
+            let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+            let start = if is_word(word.chars().next()) { r"\b" } else { "" };
+            let end = if is_word(word.chars().last()) { r"\b" } else { "" };
+            format!("{}{}{}", start, regex::escape(word), end)
+        }
+    };
+    words
+        .iter()
+        .map(|word| {
+            RegexBuilder::new(&pattern(word))
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| BotError::Config(format!("trigger '{}' is not a valid pattern: {}", word, e)))
+        })
+        .collect()
+}
+
+// The first trigger found in the text, lowercased. In the pattern modes that's the text it
+// matched rather than the pattern.
+pub fn find_trigger(patterns: &[Regex], text: &str) -> Option<String> {
+    patterns
+        .iter()
+        .find_map(|pattern| pattern.find(text))
+        .map(|found| found.as_str().to_lowercase())
+}
+
+// The command the sender typed around the first trigger, in their own casing. The whole word
+// the trigger is in goes, so contains mode doesn't leave "le" of "paddle" behind. What's left is
+// the text after the trigger, or the text before it for "Jane Smith +15551234567 addcontact";
+// when there is both, as in "hi! addcontact Jane", the part before is chatter and dropped.
+pub fn strip_trigger<'a>(patterns: &[Regex], text: &'a str) -> &'a str {
+    let Some(found) = patterns.iter().find_map(|pattern| pattern.find(text)) else {
+        return text;
+    };
+    let in_word = |c: char| !c.is_whitespace();
+    let after = text[found.end()..].trim_start_matches(in_word).trim();
+    if after.is_empty() {
+        text[..found.start()].trim_end_matches(in_word).trim()
+    } else {
+        after
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(words: &[&str], mode: TriggerMatchMode) -> Vec<Regex> {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        compile_patterns(&words, mode).unwrap()
+    }
+
+    #[test]
+    fn word_boundary_matches_whole_words_only() {
+        let add = patterns(&["add"], TriggerMatchMode::WordBoundary);
+        assert_eq!(find_trigger(&add, "add contact Jane +15551230000"), Some("add".to_string()));
+        assert_eq!(find_trigger(&add, "Please ADD: Jane"), Some("add".to_string()));
+        assert_eq!(find_trigger(&add, "paddle"), None);
+        assert_eq!(find_trigger(&add, "address"), None);
+        assert_eq!(find_trigger(&add, "added"), None);
+    }
+
+    #[test]
+    fn word_boundary_is_unicode_aware() {
+        let add = patterns(&["add"], TriggerMatchMode::WordBoundary);
+        assert_eq!(find_trigger(&add, "éadd"), None);
+        assert_eq!(find_trigger(&add, "¡add Jane"), Some("add".to_string()));
+        // A trigger starting with punctuation still matches at the start of a word
+        let slash = patterns(&["/add"], TriggerMatchMode::WordBoundary);
+        assert_eq!(find_trigger(&slash, "/add Jane"), Some("/add".to_string()));
+        assert_eq!(find_trigger(&slash, "/address"), None);
+    }
+
+    #[test]
+    fn contains_matches_inside_words() {
+        let add = patterns(&["add"], TriggerMatchMode::Contains);
+        assert_eq!(find_trigger(&add, "paddle"), Some("add".to_string()));
+        // Escaped, not a pattern
+        assert_eq!(find_trigger(&patterns(&["a.d"], TriggerMatchMode::Contains), "add"), None);
+    }
+
+    #[test]
+    fn regex_mode_compiles_the_triggers() {
+        let add = patterns(&[r"add(contact)?\b"], TriggerMatchMode::Regex);
+        assert_eq!(find_trigger(&add, "AddContact Jane"), Some("addcontact".to_string()));
+        let invalid = compile_patterns(&["add(".to_string()], TriggerMatchMode::Regex);
+        assert!(matches!(invalid, Err(BotError::Config(message)) if message.contains("add(")));
+    }
+
+    #[test]
+    fn the_trigger_word_is_stripped() {
+        let add = patterns(&["add"], TriggerMatchMode::Contains);
+        assert_eq!(strip_trigger(&add, "paddle Jane Smith +15551230000"), "Jane Smith +15551230000");
+        assert_eq!(strip_trigger(&add, "Jane Smith +15551230000 add"), "Jane Smith +15551230000");
+        assert_eq!(strip_trigger(&add, "hi! add Jane"), "Jane");
+    }
+
+    #[test]
+    fn match_modes_parse() {
+        assert_eq!("word_boundary".parse(), Ok(TriggerMatchMode::WordBoundary));
+        assert_eq!(" Regex ".parse(), Ok(TriggerMatchMode::Regex));
+        assert!("fuzzy".parse::<TriggerMatchMode>().is_err());
+    }
+
+    #[test]
+    fn stripping_keeps_the_casing_of_the_rest() {
+        let add = patterns(&["addcontact"], TriggerMatchMode::WordBoundary);
+        assert_eq!(strip_trigger(&add, "AddContact Jane McDonald"), "Jane McDonald");
+        // Mid-sentence: the words before the trigger go too
+        assert_eq!(strip_trigger(&add, "Please ADDCONTACT Jane McDonald"), "Jane McDonald");
+        assert_eq!(strip_trigger(&add, "Jane McDonald addcontact"), "Jane McDonald");
+    }
+}